@@ -1,13 +1,9 @@
-use std::env;
+use std::{env, io};
 
 use clap::Args;
 use colored::Colorize;
 
-use crate::{
-    console_output,
-    models::{repo::Repository, stage::Stage},
-    services::stage::StageService,
-};
+use crate::{console_output, models::repo::Repository};
 
 use super::Exec;
 
@@ -27,14 +23,12 @@ impl Exec for Add {
             return Ok(());
         }
 
-        let mut stage = repo.stage()?.into_muter();
-
-        for path in &self.paths {
-            let path = env::current_dir()?.join(path);
-            stage.add_path(&path)?;
-        }
-
-        stage.freeze().map(Stage).save()?;
+        let paths = self
+            .paths
+            .iter()
+            .map(|path| Ok(env::current_dir()?.join(path)))
+            .collect::<io::Result<Vec<_>>>()?;
+        repo.add_paths(&paths)?;
 
         Ok(())
     }
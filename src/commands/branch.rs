@@ -1,5 +1,9 @@
 use super::Exec;
-use crate::{console_output, models::repo::Repository, services::branch::BranchService};
+use crate::{
+    console_output,
+    models::repo::Repository,
+    services::{branch::BranchService, rev_parse::RevParseService},
+};
 use clap::Args;
 use colored::Colorize;
 
@@ -10,11 +14,16 @@ pub struct Branch {
     pub delete: bool,
 
     pub name: Option<String>,
+    /// the revision the new branch starts at, defaults to `HEAD`
+    pub start_point: Option<String>,
 }
 
 fn list_branch() -> anyhow::Result<()> {
     let repo = Repository::load()?;
     let branches = repo.list_branch()?;
+    if let Some(sha1) = repo.head().detached() {
+        console_output!("* {}", format!("(HEAD detached at {})", &sha1[..7]).green());
+    }
     for branch in branches {
         if repo.head().branch_name == branch {
             console_output!("* {}", branch.green());
@@ -43,7 +52,11 @@ impl Exec for Branch {
             Ok(())
         } else {
             // create branch
-            repo.create_branch(branch_name)?;
+            let start_point = match &self.start_point {
+                Some(rev) => Some(repo.rev_parse_commit(rev)?),
+                None => None,
+            };
+            repo.create_branch(branch_name, start_point)?;
             Ok(())
         }
     }
@@ -1,10 +1,13 @@
 use super::Exec;
-use crate::models::{Accessible, object::Object, repo::Repository};
+use crate::{
+    models::repo::Repository,
+    services::{object::ObjectService, rev_parse::RevParseService},
+};
 use clap::Args;
 
 #[derive(Debug, Args)]
 pub struct CatFile {
-    /// the sha1 of object
+    /// the object to show, such as a sha1, `HEAD~1` or `main^{tree}`
     sha1: String,
 }
 
@@ -21,7 +24,8 @@ impl Exec for CatFile {
                 println!("{working_tree}")
             }
             _ => {
-                let object = repo.wrap(Object::accessor(&self.sha1)).load()?;
+                let sha1 = repo.rev_parse(&self.sha1)?;
+                let object = repo.load_object(&sha1)?;
                 println!("{object}");
             }
         }
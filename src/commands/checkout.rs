@@ -1,7 +1,10 @@
 use clap::Args;
 
 use super::Exec;
-use crate::{models::repo::Repository, services::branch::BranchService};
+use crate::{
+    models::repo::Repository,
+    services::{branch::BranchService, rev_parse::RevParseService},
+};
 
 #[derive(Debug, Args)]
 pub struct Checkout {
@@ -10,6 +13,9 @@ pub struct Checkout {
     pub create: bool,
     /// the branch that will checkout to
     pub branch: String,
+    /// the revision the new branch starts at, defaults to `HEAD`
+    #[arg(requires("create"))]
+    pub start_point: Option<String>,
 }
 
 impl Exec for Checkout {
@@ -18,11 +24,20 @@ impl Exec for Checkout {
         let branch_name = &self.branch;
 
         if self.create {
-            repo.create_branch(branch_name)?;
+            let start_point = match &self.start_point {
+                Some(rev) => Some(repo.rev_parse_commit(rev)?),
+                None => None,
+            };
+            repo.create_branch(branch_name, start_point)?;
         }
 
         // Check if the branch exists
         if !repo.list_branch().unwrap().is_empty() && !repo.branch_exists(branch_name)? {
+            if repo.rev_parse_commit(branch_name).is_ok() {
+                anyhow::bail!(
+                    "detached HEAD is not supported, create a branch with 'checkout -b <branch> {branch_name}'"
+                );
+            }
             anyhow::bail!("pathspec '{branch_name}' did not match any file(s) known to git");
         }
 
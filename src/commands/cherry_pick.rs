@@ -0,0 +1,62 @@
+use super::Exec;
+use crate::{
+    models::{object::ObjectSha1, repo::Repository, sequencer::Action},
+    services::{rev_list::RevListService, rev_parse::RevParseService, sequencer::SequencerService},
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct CherryPick {
+    /// Commits to apply, ranges like `A..B` are applied from the oldest
+    #[arg(required_unless_present_any(["continue_", "abort"]))]
+    commits: Vec<String>,
+    /// Continue after the conflicts are resolved
+    #[arg(long("continue"), conflicts_with_all(["commits", "abort"]))]
+    continue_: bool,
+    /// Give up, and restore the branch to before the cherry-pick
+    #[arg(long, conflicts_with("commits"))]
+    abort: bool,
+}
+
+/// The commits named by the revisions, in the given order. If there are
+/// ranges like `A..B` or `^A`, the commits in the range newest first, or
+/// oldest first if `oldest_first`
+pub(super) fn commits_of(
+    repo: &Repository,
+    revs: &[String],
+    oldest_first: bool,
+) -> anyhow::Result<Vec<ObjectSha1>> {
+    if !revs
+        .iter()
+        .any(|rev| rev.contains("..") || rev.starts_with('^'))
+    {
+        return revs.iter().map(|rev| repo.rev_parse_commit(rev)).collect();
+    }
+    let range = repo.rev_parse_range(revs)?;
+    let mut commits: Vec<_> = repo
+        .rev_list(&range, None)?
+        .into_iter()
+        .map(|(sha1, _)| sha1)
+        .collect();
+    if oldest_first {
+        commits.reverse();
+    }
+    if commits.is_empty() {
+        anyhow::bail!("empty commit set passed");
+    }
+    Ok(commits)
+}
+
+impl Exec for CherryPick {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        if self.abort {
+            return repo.sequencer_abort();
+        }
+        if self.continue_ {
+            return repo.sequencer_continue();
+        }
+        let commits = commits_of(&repo, &self.commits, true)?;
+        repo.apply_commits(Action::Pick, &commits)
+    }
+}
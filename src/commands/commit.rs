@@ -2,7 +2,10 @@ use crate::{
     console_output,
     models::repo::Repository,
     oj_output,
-    services::commit::{CommitCreateResult, CommitService},
+    services::{
+        commit::{CommitCreateResult, CommitService},
+        identity::{Role, parse_date, parse_ident},
+    },
 };
 use clap::Args;
 
@@ -13,6 +16,12 @@ pub struct Commit {
     /// commit message
     #[arg(short, long)]
     pub message: Option<String>,
+    /// Override the commit author, like `A U Thor <author@example.com>`
+    #[arg(long)]
+    pub author: Option<String>,
+    /// Override the author date, like `2023-11-14 22:13:20 +0800`
+    #[arg(long)]
+    pub date: Option<String>,
 }
 
 impl Exec for Commit {
@@ -23,7 +32,21 @@ impl Exec for Commit {
             anyhow::bail!("commit message is required");
         };
 
-        let res = repo.create_commit(message)?;
+        let mut author = repo.signature(Role::Author)?;
+        if let Some(ident) = &self.author {
+            let Some((name, email)) = parse_ident(ident) else {
+                anyhow::bail!("--author '{ident}' is not 'Name <email>'");
+            };
+            (author.name, author.email) = (name, email);
+        }
+        if let Some(date) = &self.date {
+            let Some((timestamp, offset)) = parse_date(date) else {
+                anyhow::bail!("invalid date format: {date}");
+            };
+            (author.timestamp, author.offset) = (timestamp, offset);
+        }
+
+        let res = repo.create_commit(message, Some(author))?;
 
         match res {
             CommitCreateResult::Success(res) => {
@@ -0,0 +1,116 @@
+use super::Exec;
+use crate::{
+    models::{config::Config, repo::Repository, tree::Tree},
+    services::{
+        diff::{DiffService, DiffTarget},
+        rev_parse::RevParseService,
+        tree::RenameOptions,
+    },
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct Diff {
+    /// Compare the index with HEAD, or with the given revision
+    #[arg(long, visible_alias("staged"))]
+    cached: bool,
+    /// Generate diffs with <n> lines of context
+    #[arg(short('U'), long("unified"), default_value("3"))]
+    context: usize,
+    /// Detect renames, with the minimum similarity like `-M=50%`
+    #[arg(
+        short('M'),
+        long,
+        value_name("n"),
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("50%")
+    )]
+    find_renames: Option<String>,
+    /// Detect copies as well as renames, with the minimum similarity
+    #[arg(
+        short('C'),
+        long,
+        value_name("n"),
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("50%")
+    )]
+    find_copies: Option<String>,
+    /// Turn off rename detection, even if it is enabled by `diff.renames`
+    #[arg(long, conflicts_with_all(["find_renames", "find_copies"]))]
+    no_renames: bool,
+    /// Compare the working tree with a revision, or two revisions, such as
+    /// `main feature`, `main..feature` or `main...feature`
+    #[arg(num_args(0..=2))]
+    revisions: Vec<String>,
+}
+
+impl Diff {
+    /// How renames are detected, by the options or the config
+    fn rename_options(&self, repo: &Repository) -> anyhow::Result<Option<RenameOptions>> {
+        if self.no_renames {
+            return Ok(None);
+        }
+        let mut options = RenameOptions::from_config(&Config::load(&repo.root)?)?;
+        if let Some(threshold) = &self.find_renames {
+            options = Some(RenameOptions {
+                threshold: RenameOptions::parse_threshold(threshold)?,
+                copies: options.is_some_and(|options| options.copies),
+            });
+        }
+        if let Some(threshold) = &self.find_copies {
+            options = Some(RenameOptions {
+                threshold: RenameOptions::parse_threshold(threshold)?,
+                copies: true,
+            });
+        }
+        Ok(options)
+    }
+}
+
+impl Exec for Diff {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let stage = || -> anyhow::Result<Tree> { Ok(repo.stage()?.unwrap().tree) };
+        let tree_of =
+            |rev: &str| -> anyhow::Result<Tree> { repo.tree_of(&repo.rev_parse_commit(rev)?) };
+
+        let (from, to) = match (&self.revisions[..], self.cached) {
+            ([], false) => (stage()?, DiffTarget::WorkingTree),
+            ([], true) => {
+                // before the first commit, everything in the index is new
+                let head = match repo.rev_parse_commit("HEAD") {
+                    Ok(head) => repo.tree_of(&head)?,
+                    Err(_) => Tree::empty(),
+                };
+                (head, DiffTarget::Tree(repo.wrap(stage()?)))
+            }
+            ([rev], cached) if rev.contains("..") => {
+                if cached {
+                    anyhow::bail!("--cached can not be used with a range");
+                }
+                let range = repo.rev_parse_range(std::slice::from_ref(rev))?;
+                let (Some(to), Some(from)) = (range.include.first(), range.exclude.first()) else {
+                    anyhow::bail!("{rev} has no merge base");
+                };
+                let to = repo.tree_of(to)?;
+                (repo.tree_of(from)?, DiffTarget::Tree(repo.wrap(to)))
+            }
+            ([rev], false) => (tree_of(rev)?, DiffTarget::WorkingTree),
+            ([rev], true) => (tree_of(rev)?, DiffTarget::Tree(repo.wrap(stage()?))),
+            ([from, to], false) => {
+                let to = tree_of(to)?;
+                (tree_of(from)?, DiffTarget::Tree(repo.wrap(to)))
+            }
+            ([_, _], true) => anyhow::bail!("--cached can not be used with two revisions"),
+            _ => anyhow::bail!("too many revisions for diff"),
+        };
+
+        let renames = self.rename_options(&repo)?;
+        for diff in repo.diff(&repo.wrap(from), to, self.context, renames.as_ref())? {
+            print!("{diff}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use super::Exec;
+use crate::{models::repo::Repository, services::fsck::FsckService};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct Fsck {}
+
+impl Exec for Fsck {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let report = repo.fsck()?;
+
+        for problem in &report.problems {
+            println!("{problem}");
+        }
+        for sha1 in &report.dangling {
+            println!("dangling commit {sha1}");
+        }
+
+        if !report.problems.is_empty() {
+            anyhow::bail!(
+                "found {} problems in {} objects",
+                report.problems.len(),
+                report.checked
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use super::Exec;
+use crate::{console_output, models::repo::Repository, services::gc::GcService};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct Gc {}
+
+impl Exec for Gc {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let info = repo.gc()?;
+        console_output!(
+            "Packed {} objects ({} deltas) into pack-{}",
+            info.objects,
+            info.deltas,
+            info.pack
+        );
+        Ok(())
+    }
+}
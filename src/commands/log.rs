@@ -2,7 +2,10 @@ use super::Exec;
 use crate::{
     console_output,
     models::repo::Repository,
-    services::object::ObjectService,
+    services::{
+        rev_list::RevListService,
+        rev_parse::{RevParseService, RevRange},
+    },
 };
 use clap::Args;
 use colored::Colorize;
@@ -10,42 +13,51 @@ use std::fmt::Debug;
 
 #[derive(Debug, Args)]
 pub struct Log {
-    #[arg(default_value("10"))]
-    number: u32,
+    /// Limit the number of commits to output
+    #[arg(short('n'), long("max-count"), default_value("10"))]
+    number: usize,
+    /// Show commits reachable from the revisions, such as `main`, `HEAD~2` or
+    /// `main..feature`. Defaults to `HEAD`
+    revisions: Vec<String>,
 }
 
 impl Exec for Log {
     fn exec(&self) -> anyhow::Result<()> {
         let repo = Repository::load()?;
-        let Ok(branch) = repo.head().load_branch() else {
-            anyhow::bail!(
-                "your current branch '{}' does not have any commits yet",
-                repo.head().branch_name
-            );
-        };
-
-        let mut next_sha1 = Some(branch.unwrap().head);
-
-        for _ in 1..self.number {
-            let Some(sha1) = next_sha1 else {
-                return Ok(());
+        let range = if self.revisions.is_empty() {
+            // `HEAD` may also be detached
+            let Ok(head) = repo.rev_parse("HEAD") else {
+                anyhow::bail!(
+                    "your current branch '{}' does not have any commits yet",
+                    repo.head().branch_name
+                );
             };
+            RevRange {
+                include: vec![head],
+                exclude: Vec::new(),
+            }
+        } else {
+            repo.rev_parse_range(&self.revisions)?
+        };
 
-            let commit = repo.load_object(&sha1)?.unwrap().cast_commit();
-
+        for (sha1, commit) in repo.rev_list(&range, Some(self.number))? {
             console_output!("{} {}", "commit".yellow(), sha1);
+            if commit.parents.len() > 1 {
+                let parents: Vec<_> = commit.parents.iter().map(|p| &p.as_str()[..7]).collect();
+                console_output!("Merge: {}", parents.join(" "));
+            }
+            console_output!("Author: {} <{}>", commit.author.name, commit.author.email);
             console_output!(
                 "Date:   {}",
-                commit.time().naive_local().format("%Y-%m-%d %H:%M:%S")
+                commit.author.time().format("%a %b %-d %H:%M:%S %Y %z")
             );
             console_output!();
             commit
                 .message
-                .split('\n')
+                .lines()
                 .take(5)
                 .for_each(|s| console_output!("    {s}"));
             console_output!();
-            next_sha1 = commit.parent;
         }
 
         Ok(())
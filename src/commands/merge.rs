@@ -1,21 +1,84 @@
 use super::Exec;
-use crate::models::{Accessible, branch::Branch, repo};
-use crate::services::merge::MergeService;
+use crate::models::repo;
+use crate::oj_output;
+use crate::services::commit::CommitCreateResult;
+use crate::services::merge::{FastForward, MergeOptions, Strategy};
+use crate::services::merge_file::Favor;
+use crate::services::{merge::MergeService, rev_parse::RevParseService};
 use clap::Args;
 
 #[derive(Debug, Args)]
 pub struct Merge {
-    branch: String,
+    /// the branches or revisions to merge into the current branch, more
+    /// than one for an octopus merge
+    #[arg(required_unless_present_any(["continue_", "abort"]))]
+    commits: Vec<String>,
+    /// Only fast-forward the current branch, refuse to merge otherwise
+    #[arg(long, conflicts_with("no_ff"))]
+    ff_only: bool,
+    /// Create a merge commit even when the merge can fast-forward
+    #[arg(long)]
+    no_ff: bool,
+    /// Update the index and the working tree as a merge, but do not commit
+    /// or record the merge
+    #[arg(long, conflicts_with("no_ff"))]
+    squash: bool,
+    /// Use the merge strategy, `ort` (the default) or `ours`
+    #[arg(short, long)]
+    strategy: Option<String>,
+    /// Pass the option to the merge strategy, `ours` or `theirs` to take
+    /// that side for conflicting chunks
+    #[arg(short('X'), long, value_name("option"))]
+    strategy_option: Vec<String>,
+    /// Conclude the merge after the conflicts are resolved
+    #[arg(long("continue"), conflicts_with_all(["commits", "abort"]))]
+    continue_: bool,
+    /// Abort the merge, and restore the index and the working tree
+    #[arg(long, conflicts_with("commits"))]
+    abort: bool,
 }
 
 impl Exec for Merge {
     fn exec(&self) -> anyhow::Result<()> {
         let repo = repo::Repository::load()?;
-        let Ok(branch) = repo.wrap(Branch::accessor(&self.branch.as_str())).load() else {
-            anyhow::bail!("branch {} not found", self.branch);
-        };
 
-        repo.merge(branch.unwrap())?;
+        if self.abort {
+            return repo.merge_abort();
+        }
+        if self.continue_ {
+            if let CommitCreateResult::Success(res) = repo.merge_continue()? {
+                oj_output!("{}", res.commit_sha1);
+            }
+            return Ok(());
+        }
+
+        let fast_forward = if self.ff_only {
+            FastForward::Only
+        } else if self.no_ff {
+            FastForward::Never
+        } else {
+            FastForward::Allow
+        };
+        let mut options = MergeOptions {
+            fast_forward,
+            squash: self.squash,
+            ..Default::default()
+        };
+        if let Some(strategy) = &self.strategy {
+            options.strategy = Strategy::from_name(strategy)?;
+        }
+        for option in &self.strategy_option {
+            options.favor = Favor::from_strategy_option(option)?;
+        }
+        if let [commit] = self.commits.as_slice() {
+            let theirs = repo.rev_parse_commit(commit)?;
+            return repo.merge(&theirs, commit, &options);
+        }
+        let mut heads = Vec::new();
+        for commit in &self.commits {
+            heads.push((repo.rev_parse_commit(commit)?, commit.clone()));
+        }
+        repo.merge_octopus(&heads, &options)?;
         Ok(())
     }
 }
@@ -0,0 +1,45 @@
+use super::Exec;
+use crate::{
+    models::repo::Repository,
+    services::{merge::MergeService, rev_parse::RevParseService},
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct MergeBase {
+    /// Output all best common ancestors, instead of only one
+    #[arg(short, long)]
+    all: bool,
+    /// Check if the first commit is an ancestor of the second, and exit with
+    /// status 0 if true, or 1 if not
+    #[arg(long, conflicts_with("all"))]
+    is_ancestor: bool,
+    commit1: String,
+    commit2: String,
+}
+
+impl Exec for MergeBase {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let a = repo.rev_parse_commit(&self.commit1)?;
+        let b = repo.rev_parse_commit(&self.commit2)?;
+
+        if self.is_ancestor {
+            if !repo.is_ancestor(&a, &b)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+
+        let bases = repo.merge_bases(&a, &b)?;
+        if bases.is_empty() {
+            // like git, unrelated histories exit with 1 silently
+            std::process::exit(1);
+        }
+        let count = if self.all { bases.len() } else { 1 };
+        for base in &bases[..count] {
+            println!("{base}");
+        }
+        Ok(())
+    }
+}
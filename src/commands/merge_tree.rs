@@ -0,0 +1,88 @@
+use super::Exec;
+use crate::{
+    models::{object::Sha1Able, repo::Repository},
+    services::{
+        merge::{MergeOptions, MergeService, MergedTree, Strategy},
+        merge_file::Favor,
+        rev_parse::RevParseService,
+    },
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct MergeTree {
+    /// Only list the names of conflicted files, instead of their stages
+    #[arg(long)]
+    name_only: bool,
+    /// Do not print the messages about the conflicts
+    #[arg(long)]
+    no_messages: bool,
+    /// Use the merge strategy, `ort` (the default) or `ours`
+    #[arg(short, long)]
+    strategy: Option<String>,
+    /// Pass the option to the merge strategy, `ours` or `theirs`
+    #[arg(short('X'), long, value_name("option"))]
+    strategy_option: Vec<String>,
+    branch1: String,
+    branch2: String,
+}
+
+impl Exec for MergeTree {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let ours = repo.rev_parse_commit(&self.branch1)?;
+        let theirs = repo.rev_parse_commit(&self.branch2)?;
+
+        let mut options = MergeOptions::default();
+        if let Some(strategy) = &self.strategy {
+            options.strategy = Strategy::from_name(strategy)?;
+        }
+        for option in &self.strategy_option {
+            options.favor = Favor::from_strategy_option(option)?;
+        }
+        let names = [self.branch1.as_str(), self.branch2.as_str()];
+        let MergedTree { tree, conflicts } = repo.merge_tree(&ours, &theirs, names, &options)?;
+
+        // like `git merge-tree --write-tree`, the tree, the conflicted files
+        // and the messages
+        println!("{}", tree.sha1());
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        let mut files = Vec::new();
+        let mut messages = Vec::new();
+        for conflict in &conflicts {
+            let message = conflict.message(names[0], names[1]);
+            if !messages.contains(&message) {
+                messages.push(message);
+            }
+            if files.contains(&&conflict.file) {
+                continue;
+            }
+            files.push(&conflict.file);
+            if self.name_only {
+                println!("{}", conflict.file);
+                continue;
+            }
+            for (stage, line) in conflict.versions.iter().enumerate() {
+                if let Some(line) = line {
+                    println!(
+                        "{} {} {}\t{}",
+                        line.kind.mode(),
+                        line.sha1,
+                        stage + 1,
+                        conflict.file
+                    );
+                }
+            }
+        }
+        if !self.no_messages {
+            println!();
+            for message in messages {
+                println!("{message}");
+            }
+        }
+        // like git, conflicts exit with 1
+        std::process::exit(1);
+    }
+}
@@ -15,15 +15,29 @@ mod add;
 mod branch;
 mod cat_file;
 mod checkout;
+mod cherry_pick;
 mod commit;
+mod diff;
 mod fetch;
+mod fsck;
+mod gc;
 mod init;
 mod log;
 mod merge;
+mod merge_base;
+mod merge_tree;
+mod prune;
 mod pull;
 mod push;
+mod rebase;
 mod remove;
+mod reset;
+mod restore;
+mod rev_parse;
+mod revert;
+mod stash;
 mod status;
+mod tag;
 
 #[enum_dispatch]
 pub trait Exec {
@@ -46,6 +60,24 @@ pub enum Commands {
     Checkout(checkout::Checkout),
     /// Join two or more development histories together
     Merge(merge::Merge),
+    /// Find as good common ancestors as possible for a merge
+    MergeBase(merge_base::MergeBase),
+    /// Perform merge without touching index or working tree
+    MergeTree(merge_tree::MergeTree),
+    /// Reset current HEAD to the specified state
+    Reset(reset::Reset),
+    /// Reapply commits on top of another base tip
+    Rebase(rebase::Rebase),
+    /// Revert some existing commits
+    Revert(revert::Revert),
+    /// Apply the changes introduced by some existing commits
+    CherryPick(cherry_pick::CherryPick),
+    /// Restore working tree files
+    Restore(restore::Restore),
+    /// Stash the changes in a dirty working directory away
+    Stash(stash::Stash),
+    /// Create, list, delete or verify a tag object
+    Tag(tag::Tag),
     /// Remove files from the working tree and from the index
     #[command(aliases(["remove"]))]
     Rm(remove::Remove),
@@ -53,12 +85,22 @@ pub enum Commands {
     Log(log::Log),
     /// Show the working tree status
     Status(status::Status),
+    /// Show changes between commits, commit and working tree, etc
+    Diff(diff::Diff),
     /// Download objects and refs from another repository
     Fetch(fetch::Fetch),
     /// Fetch from and integrate with another repository or a local branch
     Pull(pull::Pull),
     /// Update remote refs along with associated objects
     Push(push::Push),
+    /// Verifies the connectivity and validity of the objects in the database
+    Fsck(fsck::Fsck),
+    /// Cleanup unnecessary files and optimize the local repository
+    Gc(gc::Gc),
+    /// Prune all unreachable objects from the object database
+    Prune(prune::Prune),
+    /// Pick out and massage parameters
+    RevParse(rev_parse::RevParse),
     /// (For debug) Display information about a object
     CatFile(cat_file::CatFile),
 }
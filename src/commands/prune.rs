@@ -0,0 +1,32 @@
+use super::Exec;
+use crate::{
+    models::repo::Repository,
+    services::prune::{DEFAULT_PRUNE_EXPIRE, PruneService, parse_expire},
+};
+use clap::Args;
+use std::time::SystemTime;
+
+#[derive(Debug, Args)]
+pub struct Prune {
+    /// Do not remove anything; just report what it would remove
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Only expire loose objects older than <time>, such as `now` or
+    /// `2.weeks.ago`
+    #[arg(long, default_value = DEFAULT_PRUNE_EXPIRE)]
+    expire: String,
+}
+
+impl Exec for Prune {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let expire = parse_expire(&self.expire, SystemTime::now()).transpose()?;
+
+        for (sha1, object_type) in repo.prune(expire, self.dry_run)? {
+            if self.dry_run {
+                println!("{sha1} {object_type}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use super::Exec;
+use crate::{
+    console_output,
+    models::{
+        object::ObjectSha1,
+        repo::Repository,
+        sequencer::{Action, parse_todo},
+    },
+    services::{
+        merge::MergeService, object::ObjectService, rebase::RebaseService,
+        rev_parse::RevParseService,
+    },
+};
+use clap::Args;
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Args)]
+pub struct Rebase {
+    /// The commit to replay the commits onto, defaults to the upstream of the
+    /// current branch
+    #[arg(conflicts_with_all(["continue_", "skip", "abort"]))]
+    upstream: Option<String>,
+    /// Edit the todo list of the commits with the editor before replaying
+    /// them. It is `GIT_SEQUENCE_EDITOR`, `sequence.editor`, or the editor of
+    /// commit messages
+    #[arg(short, long)]
+    interactive: bool,
+    /// Read the todo list from the file instead of the editor, implies
+    /// `--interactive`
+    #[arg(long, value_name("file"))]
+    todo_file: Option<PathBuf>,
+    /// Continue after the conflicts are resolved
+    #[arg(long("continue"), conflicts_with_all(["skip", "abort"]))]
+    continue_: bool,
+    /// Skip the commit stopped by conflicts
+    #[arg(long, conflicts_with("abort"))]
+    skip: bool,
+    /// Give up, and restore the branch to before the rebase
+    #[arg(long)]
+    abort: bool,
+}
+
+/// The todo list for the user to edit, like git
+fn todo_text(
+    repo: &Repository,
+    commits: &[ObjectSha1],
+    onto: &ObjectSha1,
+) -> anyhow::Result<String> {
+    let mut text = String::new();
+    for sha1 in commits {
+        let commit = repo.load_commit(sha1)?;
+        let subject = commit.message.lines().next().unwrap_or_default();
+        text.push_str(&format!("pick {} {subject}\n", &sha1.as_str()[..7]));
+    }
+    text.push_str(&format!(
+        "\n# Rebase onto {} ({} commands)\n\
+         #\n\
+         # Commands:\n\
+         # p, pick <commit> = use commit\n\
+         # r, reword <commit> = use commit, but edit the commit message\n\
+         # s, squash <commit> = use commit, but meld into previous commit\n\
+         # f, fixup <commit> = like \"squash\", but discard this commit's log message\n\
+         # d, drop <commit> = remove commit\n\
+         #\n\
+         # These lines can be re-ordered; they are executed from top to bottom.\n\
+         # If you remove a line here THAT COMMIT WILL BE LOST.\n",
+        &onto.as_str()[..7],
+        commits.len()
+    ));
+    Ok(text)
+}
+
+impl Exec for Rebase {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        if self.abort {
+            return repo.rebase_abort();
+        }
+        if self.continue_ {
+            return repo.rebase_continue();
+        }
+        if self.skip {
+            return repo.rebase_skip();
+        }
+
+        repo.check_no_rebase()?;
+        let branch = repo.head().branch_name.clone();
+        let upstream = match &self.upstream {
+            Some(upstream) => upstream.clone(),
+            None => repo.upstream_of(&branch)?,
+        };
+        let onto = repo.rev_parse_commit(&upstream)?;
+        let head = repo.rev_parse_commit("HEAD")?;
+        let interactive = self.interactive || self.todo_file.is_some();
+        if !interactive && repo.is_ancestor(&onto, &head)? {
+            console_output!("Current branch {branch} is up to date.");
+            return Ok(());
+        }
+
+        let commits = repo.rebase_commits(&onto)?;
+        if !interactive {
+            let todo = commits.into_iter().map(|sha1| (Action::Pick, sha1));
+            return repo.rebase(&onto, todo.collect());
+        }
+        let text = match &self.todo_file {
+            Some(file) => fs::read_to_string(file)?,
+            None => {
+                let path = repo.root.join("git-rebase-todo");
+                fs::write(&path, todo_text(&repo, &commits, &onto)?)?;
+                repo.edit_file(&path, true)?;
+                let text = fs::read_to_string(&path)?;
+                fs::remove_file(&path)?;
+                text
+            }
+        };
+        let mut todo = Vec::new();
+        for (action, rev) in parse_todo(&text)? {
+            if matches!(action, Action::Revert) {
+                anyhow::bail!("invalid command 'revert' in the todo list");
+            }
+            todo.push((action, repo.rev_parse_commit(&rev)?));
+        }
+        if todo.is_empty() {
+            anyhow::bail!("Nothing to do");
+        }
+        repo.rebase(&onto, todo)
+    }
+}
@@ -21,7 +21,9 @@ impl Exec for Remove {
     fn exec(&self) -> anyhow::Result<()> {
         // rm不需要真的删除文件，只需要删掉stage area的索引就行了
         let repo = Repository::load()?;
-        let mut stage = repo.stage()?.into_muter();
+        let stage = repo.stage()?;
+        let (unmerged, subtrees) = (stage.unmerged.clone(), stage.subtrees.clone());
+        let mut muter = stage.into_muter();
 
         for path in &self.path {
             let path = env::current_dir()?.join(path);
@@ -29,12 +31,27 @@ impl Exec for Remove {
                 return Err(anyhow::anyhow!("rm: {} is a directory", path.display()));
             }
         }
+        let mut resolved = Vec::new();
         for path in &self.path {
             let path = env::current_dir()?.join(path);
-            stage.remove_path(&path)?;
+            let relative = repo.relative_path(&path);
+            // a conflicted file deleted by us is not in the tree, removing it
+            // resolves the conflict as deleted
+            if !matches!(unmerged.get(&relative), Some([_, None, _])) {
+                muter.remove_path(&path)?;
+            }
             fs::remove_file(path)?;
+            resolved.push(relative);
+        }
+        let mut stage = muter.freeze().map(|tree| Stage {
+            tree,
+            unmerged,
+            subtrees,
+        });
+        for path in resolved {
+            stage.resolve(&path);
         }
-        stage.freeze().map(Stage).save()?;
+        stage.save()?;
         Ok(())
     }
 }
@@ -0,0 +1,130 @@
+use super::Exec;
+use crate::{
+    console_output,
+    models::{repo::Repository, tree::Tree},
+    services::{
+        object::ObjectService,
+        reset::{ResetMode, ResetService},
+        rev_parse::RevParseService,
+        tree::{ComparedKind, compare_trees},
+    },
+};
+use clap::Args;
+use std::{env, path::Path};
+
+#[derive(Debug, Args)]
+pub struct Reset {
+    /// Only move the current branch, keep the index and the working tree
+    #[arg(long, conflicts_with_all(["mixed", "hard"]))]
+    soft: bool,
+    /// Move the current branch and reset the index, but not the working tree
+    /// (the default)
+    #[arg(long, conflicts_with("hard"))]
+    mixed: bool,
+    /// Move the current branch, and reset the index and the working tree.
+    /// Changes to tracked files are discarded
+    #[arg(long)]
+    hard: bool,
+    /// The commit to reset to, defaults to `HEAD`, followed by the paths to
+    /// unstage
+    #[arg(value_name("commit|paths"))]
+    args: Vec<String>,
+    /// The paths to unstage, after `--`
+    #[arg(last(true))]
+    paths: Vec<String>,
+}
+
+impl Reset {
+    /// Split the arguments into the commit and the paths, like git, the first
+    /// argument is the commit if it names one, otherwise it should be a path
+    fn commit_and_paths(&self, repo: &Repository) -> anyhow::Result<(Option<String>, Vec<String>)> {
+        let mut args = self.args.clone();
+        if !self.paths.is_empty() {
+            if args.len() > 1 {
+                anyhow::bail!("only one commit can be given before '--'");
+            }
+            return Ok((args.pop(), self.paths.clone()));
+        }
+        match args.first() {
+            Some(first) if repo.rev_parse_commit(first).is_ok() => {
+                let commit = args.remove(0);
+                Ok((Some(commit), args))
+            }
+            Some(first) if !Path::new(first).exists() => anyhow::bail!(
+                "ambiguous argument '{first}': unknown revision or path not in the working tree.\n\
+                 Use '--' to separate paths from revisions"
+            ),
+            _ => Ok((None, args)),
+        }
+    }
+}
+
+impl Exec for Reset {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let (commit, paths) = self.commit_and_paths(&repo)?;
+
+        if !paths.is_empty() {
+            if self.soft || self.hard {
+                let mode = if self.soft { "soft" } else { "hard" };
+                anyhow::bail!("Cannot do {mode} reset with paths.");
+            }
+            let tree = match &commit {
+                Some(rev) => repo.tree_of(&repo.rev_parse_commit(rev)?)?,
+                // before the first commit, paths are removed from the index
+                None => match repo.rev_parse_commit("HEAD") {
+                    Ok(head) => repo.tree_of(&head)?,
+                    Err(_) => Tree::empty(),
+                },
+            };
+            let cwd = env::current_dir()?;
+            let paths: Vec<_> = paths
+                .iter()
+                .map(|path| repo.relative_path(&cwd.join(path)))
+                .collect();
+            repo.reset_paths(&tree, &paths)?;
+            return print_unstaged(&repo);
+        }
+
+        let commit = repo.rev_parse_commit(commit.as_deref().unwrap_or("HEAD"))?;
+        let mode = if self.soft {
+            ResetMode::Soft
+        } else if self.hard {
+            ResetMode::Hard
+        } else {
+            ResetMode::Mixed
+        };
+        repo.reset(&commit, mode)?;
+
+        match mode {
+            ResetMode::Soft => Ok(()),
+            ResetMode::Mixed => print_unstaged(&repo),
+            ResetMode::Hard => {
+                let subject = repo.load_commit(&commit)?.message;
+                let subject = subject.lines().next().unwrap_or_default();
+                console_output!("HEAD is now at {} {subject}", &commit[..7]);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// List the tracked files that differ from the index, like git
+fn print_unstaged(repo: &Repository) -> anyhow::Result<()> {
+    let stage = repo.stage()?.map(|stage| stage.tree);
+    let mut changes = compare_trees(&stage, &repo.working_tree()?)?;
+    changes.retain(|change| !matches!(change.kind, ComparedKind::Added));
+    if changes.is_empty() {
+        return Ok(());
+    }
+    changes.sort_by(|a, b| a.line.name.cmp(&b.line.name));
+    console_output!("Unstaged changes after reset:");
+    for change in changes {
+        let status = match change.kind {
+            ComparedKind::Deleted => 'D',
+            _ => 'M',
+        };
+        console_output!("{status}\t{}", change.line.name);
+    }
+    Ok(())
+}
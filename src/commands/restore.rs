@@ -0,0 +1,58 @@
+use super::Exec;
+use crate::{
+    models::{repo::Repository, tree::Tree},
+    services::{restore::RestoreService, rev_parse::RevParseService, tree::path_matches},
+};
+use clap::Args;
+use std::env;
+
+#[derive(Debug, Args)]
+pub struct Restore {
+    /// Restore from the tree of the revision. Defaults to the index, or to
+    /// `HEAD` with `--staged`
+    #[arg(short, long)]
+    source: Option<String>,
+    /// Restore the index
+    #[arg(short('S'), long)]
+    staged: bool,
+    /// Restore the working tree, the default without `--staged`
+    #[arg(short('W'), long)]
+    worktree: bool,
+    /// Files or directories to restore
+    #[arg(required(true))]
+    paths: Vec<String>,
+}
+
+impl Exec for Restore {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        let cwd = env::current_dir()?;
+        let paths: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| repo.relative_path(&cwd.join(path)))
+            .collect();
+        let worktree = self.worktree || !self.staged;
+
+        let source = match &self.source {
+            Some(rev) => repo.tree_of(&repo.rev_parse_commit(rev)?)?,
+            // before the first commit, the files are removed from the index
+            None if self.staged => match repo.rev_parse_commit("HEAD") {
+                Ok(head) => repo.tree_of(&head)?,
+                Err(_) => Tree::empty(),
+            },
+            None => {
+                let stage = repo.stage()?.unwrap();
+                if let Some(path) = stage
+                    .unmerged
+                    .keys()
+                    .find(|name| paths.iter().any(|path| path_matches(path, name)))
+                {
+                    anyhow::bail!("path '{path}' is unmerged");
+                }
+                stage.tree
+            }
+        };
+        repo.restore(&source, &paths, self.staged, worktree)
+    }
+}
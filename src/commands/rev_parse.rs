@@ -0,0 +1,33 @@
+use super::Exec;
+use crate::{models::repo::Repository, services::rev_parse::RevParseService};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct RevParse {
+    /// revisions to parse, such as `HEAD~2`, `main^`, `@{upstream}`, `A..B` or
+    /// `A...B`
+    #[arg(required = true)]
+    revisions: Vec<String>,
+}
+
+impl Exec for RevParse {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+
+        for rev in &self.revisions {
+            if !rev.contains("..") && !rev.starts_with('^') {
+                // a single revision may name any object, not only commits
+                println!("{}", repo.rev_parse(rev)?);
+                continue;
+            }
+            let range = repo.rev_parse_range(std::slice::from_ref(rev))?;
+            for sha1 in range.include {
+                println!("{sha1}");
+            }
+            for sha1 in range.exclude {
+                println!("^{sha1}");
+            }
+        }
+        Ok(())
+    }
+}
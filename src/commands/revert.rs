@@ -0,0 +1,33 @@
+use super::{Exec, cherry_pick::commits_of};
+use crate::{
+    models::{repo::Repository, sequencer::Action},
+    services::sequencer::SequencerService,
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct Revert {
+    /// Commits to revert, ranges like `A..B` are reverted from the newest
+    #[arg(required_unless_present_any(["continue_", "abort"]))]
+    commits: Vec<String>,
+    /// Continue after the conflicts are resolved
+    #[arg(long("continue"), conflicts_with_all(["commits", "abort"]))]
+    continue_: bool,
+    /// Give up, and restore the branch to before the revert
+    #[arg(long, conflicts_with("commits"))]
+    abort: bool,
+}
+
+impl Exec for Revert {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        if self.abort {
+            return repo.sequencer_abort();
+        }
+        if self.continue_ {
+            return repo.sequencer_continue();
+        }
+        let commits = commits_of(&repo, &self.commits, false)?;
+        repo.apply_commits(Action::Revert, &commits)
+    }
+}
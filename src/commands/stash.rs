@@ -0,0 +1,188 @@
+use super::Exec;
+use crate::{
+    console_output,
+    models::{repo::Repository, tree::Tree},
+    services::{
+        diff::{DiffService, DiffTarget, HunkLine},
+        object::ObjectService,
+        stash::StashService,
+    },
+};
+use clap::{Args, Subcommand};
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands(true))]
+pub struct Stash {
+    #[command(subcommand)]
+    command: Option<StashCommand>,
+    /// `stash` alone is `stash push`
+    #[command(flatten)]
+    push: Push,
+}
+
+#[derive(Debug, Subcommand)]
+enum StashCommand {
+    /// Save the local changes to a new stash entry, and reset them to HEAD
+    Push(Push),
+    /// Apply a stash entry, and remove it if there is no conflict
+    Pop(Apply),
+    /// Apply a stash entry, and keep it
+    Apply(Apply),
+    /// List the stash entries
+    List,
+    /// Remove a stash entry
+    Drop(Entry),
+    /// Show the changes recorded in a stash entry
+    Show(Show),
+}
+
+#[derive(Debug, Args)]
+struct Push {
+    /// The description of the stash entry
+    #[arg(short, long)]
+    message: Option<String>,
+    /// Also stash untracked files, and remove them
+    #[arg(short('u'), long)]
+    include_untracked: bool,
+}
+
+#[derive(Debug, Args)]
+struct Entry {
+    /// The stash entry, like `stash@{1}` or `1`, defaults to the newest one
+    stash: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct Apply {
+    /// Also restore the changes of the index
+    #[arg(long)]
+    index: bool,
+    #[command(flatten)]
+    entry: Entry,
+}
+
+#[derive(Debug, Args)]
+struct Show {
+    /// Show the changes as a patch instead of a diffstat
+    #[arg(short, long)]
+    patch: bool,
+    #[command(flatten)]
+    entry: Entry,
+}
+
+impl Entry {
+    /// The `n` of `stash@{n}`
+    fn index(&self) -> anyhow::Result<usize> {
+        let Some(stash) = &self.stash else {
+            return Ok(0);
+        };
+        let n = stash
+            .strip_prefix("stash@{")
+            .and_then(|n| n.strip_suffix('}'))
+            .unwrap_or(stash);
+        n.parse()
+            .map_err(|_| anyhow::anyhow!("'{stash}' is not a stash-like commit"))
+    }
+}
+
+/// Print `stash@{n}` as a diffstat, or a patch
+fn show(repo: &Repository, n: usize, patch: bool) -> anyhow::Result<()> {
+    let sha1 = repo.stash_entry(n)?.commit;
+    let base = match repo.load_commit(&sha1)?.parents.first() {
+        Some(base) => repo.tree_of(base)?,
+        None => Tree::empty(),
+    };
+    let stash = repo.tree_of(&sha1)?;
+    let diffs = repo.diff(
+        &repo.wrap(base),
+        DiffTarget::Tree(repo.wrap(stash)),
+        3,
+        None,
+    )?;
+    if patch {
+        diffs.iter().for_each(|diff| print!("{diff}"));
+        return Ok(());
+    }
+
+    // the numbers of inserted and deleted lines of each file
+    let stats: Vec<_> = diffs
+        .iter()
+        .map(|diff| {
+            let lines = diff.hunks.iter().flatten().flat_map(|hunk| &hunk.lines);
+            let count = |f: fn(&HunkLine) -> bool| lines.clone().filter(|l| f(l)).count();
+            (
+                &diff.path,
+                count(|l| matches!(l, HunkLine::Insert(_))),
+                count(|l| matches!(l, HunkLine::Delete(_))),
+            )
+        })
+        .collect();
+    let width = stats.iter().map(|(path, ..)| path.len()).max().unwrap_or(0);
+    for (path, insertions, deletions) in &stats {
+        println!(
+            " {path:width$} | {} {}{}",
+            insertions + deletions,
+            "+".repeat(*insertions),
+            "-".repeat(*deletions)
+        );
+    }
+    let plural = |n: usize, word: &str| match n {
+        1 => format!("{n} {word}"),
+        n => format!("{n} {word}s"),
+    };
+    let insertions: usize = stats.iter().map(|(_, i, _)| i).sum();
+    let deletions: usize = stats.iter().map(|(.., d)| d).sum();
+    let mut summary = format!(" {} changed", plural(stats.len(), "file"));
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&format!(", {}(+)", plural(insertions, "insertion")));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&format!(", {}(-)", plural(deletions, "deletion")));
+    }
+    println!("{summary}");
+    Ok(())
+}
+
+impl Exec for Stash {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        match self.command.as_ref() {
+            None => push(&repo, &self.push),
+            Some(StashCommand::Push(args)) => push(&repo, args),
+            Some(StashCommand::Pop(args)) => {
+                let n = args.entry.index()?;
+                if !repo.stash_apply(n, args.index)? {
+                    console_output!("The stash entry is kept in case you need it again.");
+                    return Ok(());
+                }
+                let sha1 = repo.stash_drop(n)?;
+                console_output!("Dropped refs/stash@{{{n}}} ({sha1})");
+                Ok(())
+            }
+            Some(StashCommand::Apply(args)) => {
+                repo.stash_apply(args.entry.index()?, args.index)?;
+                Ok(())
+            }
+            Some(StashCommand::List) => {
+                for (n, entry) in repo.stash_list()?.entries.iter().enumerate() {
+                    println!("stash@{{{n}}}: {}", entry.message);
+                }
+                Ok(())
+            }
+            Some(StashCommand::Drop(args)) => {
+                let n = args.index()?;
+                let sha1 = repo.stash_drop(n)?;
+                console_output!("Dropped refs/stash@{{{n}}} ({sha1})");
+                Ok(())
+            }
+            Some(StashCommand::Show(args)) => show(&repo, args.entry.index()?, args.patch),
+        }
+    }
+}
+
+fn push(repo: &Repository, args: &Push) -> anyhow::Result<()> {
+    if !repo.stash_push(args.message.as_deref(), args.include_untracked)? {
+        console_output!("No local changes to save");
+    }
+    Ok(())
+}
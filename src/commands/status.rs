@@ -5,8 +5,11 @@ use colored::Colorize;
 
 use crate::{
     console_output,
-    models::{Accessible, object::Object, repo::Repository, tree::Tree},
-    services::tree::{ComparedKind, compare_trees},
+    models::{repo::Repository, stage::Unmerged},
+    services::{
+        status::{StatusChanges, StatusService},
+        tree::ComparedKind,
+    },
 };
 
 use super::Exec;
@@ -14,33 +17,58 @@ use super::Exec;
 #[derive(Debug, Args)]
 pub struct Status {}
 
+/// How the path conflicts, like `git status`
+fn unmerged_kind(versions: &Unmerged) -> &'static str {
+    match versions {
+        [Some(_), Some(_), Some(_)] => "both modified:",
+        [None, Some(_), Some(_)] => "both added:",
+        [None, Some(_), None] => "added by us:",
+        [None, None, Some(_)] => "added by them:",
+        [_, Some(_), None] => "deleted by them:",
+        [_, None, Some(_)] => "deleted by us:",
+        [_, None, None] => "both deleted:",
+    }
+}
+
 impl Exec for Status {
     fn exec(&self) -> anyhow::Result<()> {
         let repo = Repository::load()?;
-        let branch = repo.head().load_branch();
-
-        console_output!("On branch {}", repo.head().branch_name);
-
-        let working_tree = repo.working_tree()?;
-        let stage_tree = repo.stage()?.map(|s| s.0);
-        let head_tree = if let Ok(sha1) = branch.map(|b| b.unwrap().head) {
-            let head_commit = repo
-                .wrap(Object::accessor(&sha1))
-                .load()?
-                .map(|c| c.cast_commit());
-
-            repo.wrap(Object::accessor(&head_commit.tree))
-                .load()?
-                .map(|t| t.cast_tree())
-        } else {
-            console_output!("No commits yet\n");
-            repo.wrap(Tree::empty())
+        // `HEAD` may also be detached
+        let head = match repo.head().detached() {
+            Some(sha1) => {
+                console_output!("HEAD detached at {}", &sha1[..7]);
+                Ok(sha1.clone())
+            }
+            None => {
+                console_output!("On branch {}", repo.head().branch_name);
+                repo.head().load_branch().map(|b| b.unwrap().head)
+            }
         };
+        if head.is_err() {
+            console_output!("No commits yet\n");
+        }
 
-        let mut staging_changes = compare_trees(&head_tree, &stage_tree)?;
-        staging_changes.sort_by(|a, b| a.line.name.cmp(&b.line.name));
-        let mut working_changes = compare_trees(&stage_tree, &working_tree)?;
-        working_changes.sort_by(|a, b| a.line.name.cmp(&b.line.name));
+        let stage = repo.stage()?;
+        let unmerged = stage.unmerged.clone();
+        if repo.merge_state()?.is_some() {
+            if unmerged.is_empty() {
+                console_output!(
+                    "All conflicts fixed but you are still merging.
+  (use \"git commit\" to conclude merge)"
+                );
+            } else {
+                console_output!(
+                    "You have unmerged paths.
+  (fix conflicts and run \"git commit\")
+  (use \"git merge --abort\" to abort the merge)"
+                );
+            }
+        }
+
+        let StatusChanges {
+            staged: staging_changes,
+            unstaged: working_changes,
+        } = repo.status_changes()?;
 
         if staging_changes.is_empty().not() {
             console_output!(
@@ -56,13 +84,29 @@ Changes to be committed:
                     ComparedKind::Deleted => {
                         console_output!("{}", diff.to_string().red());
                     }
-                    ComparedKind::Added => {
+                    ComparedKind::Added
+                    | ComparedKind::Renamed { .. }
+                    | ComparedKind::Copied { .. } => {
                         console_output!("{}", diff.to_string().green());
                     }
                 }
             }
         }
 
+        if unmerged.is_empty().not() {
+            console_output!(
+                "
+Unmerged paths:
+  (use \"git add <file>...\" to mark resolution)"
+            );
+            for (path, versions) in &unmerged {
+                console_output!(
+                    "        {}",
+                    format!("{:<17}{path}", unmerged_kind(versions)).red()
+                );
+            }
+        }
+
         let changes_not_staged_for_commit = working_changes
             .iter()
             .filter(|x| x.kind != ComparedKind::Added)
@@ -83,7 +127,9 @@ Changes not staged for commit:
                     ComparedKind::Deleted => {
                         console_output!("{}", diff.to_string().red());
                     }
-                    ComparedKind::Added => {
+                    ComparedKind::Added
+                    | ComparedKind::Renamed { .. }
+                    | ComparedKind::Copied { .. } => {
                         console_output!("{}", diff.to_string().green());
                     }
                 }
@@ -106,7 +152,7 @@ Untracked files:
             }
         }
 
-        if working_changes.is_empty() && staging_changes.is_empty() {
+        if working_changes.is_empty() && staging_changes.is_empty() && unmerged.is_empty() {
             console_output!("nothing to commit, working tree clean");
         }
 
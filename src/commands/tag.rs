@@ -0,0 +1,88 @@
+use super::Exec;
+use crate::{
+    console_output,
+    models::{object::GitObject, repo::Repository},
+    services::{
+        rev_parse::RevParseService,
+        tag::{TagService, tag_matches},
+    },
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct Tag {
+    /// List tags matching any of the patterns, like `v1.*`, which is the
+    /// default without a tag name
+    #[arg(short, long, conflicts_with_all(["delete", "verify", "annotate", "message", "force"]))]
+    list: bool,
+    /// Delete the tags
+    #[arg(short, long, conflicts_with_all(["verify", "annotate", "message", "force"]))]
+    delete: bool,
+    /// Check that the annotated tags and the objects they point to are valid,
+    /// and print them. Signatures are not supported
+    #[arg(short, long, conflicts_with_all(["annotate", "message", "force"]))]
+    verify: bool,
+    /// Create an annotated tag, with the message from the editor if there is
+    /// no `-m`
+    #[arg(short, long)]
+    annotate: bool,
+    /// The message of the annotated tag, implies `-a`
+    #[arg(short, long)]
+    message: Option<String>,
+    /// Replace an existing tag
+    #[arg(short, long)]
+    force: bool,
+    /// `<tagname> [<commit>]` to create a tag, defaults to `HEAD`. The tags
+    /// to delete or verify, or the patterns to list
+    #[arg(value_name("args"))]
+    args: Vec<String>,
+}
+
+impl Exec for Tag {
+    fn exec(&self) -> anyhow::Result<()> {
+        let repo = Repository::load()?;
+        if self.delete {
+            for name in &self.args {
+                let sha1 = repo.delete_tag(name)?;
+                console_output!("Deleted tag '{name}' (was {})", &sha1[..7]);
+            }
+            return Ok(());
+        }
+        if self.verify {
+            for name in &self.args {
+                let tag = repo.verify_tag(name)?;
+                print!("{}", String::from_utf8_lossy(&tag.content()?));
+            }
+            return Ok(());
+        }
+        let annotated = self.annotate || self.message.is_some();
+        if self.list || (self.args.is_empty() && !annotated && !self.force) {
+            for name in repo.list_tags()? {
+                let pattern_matches = |p: &String| tag_matches(p.as_bytes(), name.as_bytes());
+                if self.args.is_empty() || self.args.iter().any(pattern_matches) {
+                    println!("{name}");
+                }
+            }
+            return Ok(());
+        }
+
+        let (name, rev) = match &self.args[..] {
+            [name] => (name, "HEAD"),
+            [name, rev] => (name, rev.as_str()),
+            [] => anyhow::bail!("tag name required"),
+            _ => anyhow::bail!("too many arguments"),
+        };
+        let object = repo.rev_parse(rev)?;
+        let message = match (&self.message, annotated) {
+            (Some(message), _) => Some(message.clone()),
+            (None, true) => {
+                Some(repo.edit_message(&format!("\n# Write a message for tag:\n#   {name}"))?)
+            }
+            (None, false) => None,
+        };
+        if let Some(old) = repo.create_tag(name, &object, message.as_deref(), self.force)? {
+            console_output!("Updated tag '{name}' (was {})", &old[..7]);
+        }
+        Ok(())
+    }
+}
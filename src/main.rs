@@ -36,5 +36,6 @@ fn main() {
 
     if let Err(e) = args.command.exec() {
         println!("{}: {e}", "error".red());
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,151 @@
+//! Attributes of paths, such as the merge driver
+
+use std::{fs, io, path::Path};
+
+/// The state of an attribute of a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+    /// `!attr`, as if it is not specified
+    Unspecified,
+}
+
+/// Attributes from the `.gitattributes` file in the top of the working
+/// directory, and `info/attributes` in the `.git` dir, which overrides it:
+///
+/// ```txt
+/// # a comment
+/// *.txt       text
+/// CHANGELOG   merge=union
+/// generated/** -merge
+/// ```
+///
+/// Later lines override earlier ones. Patterns without `/` match the name of
+/// a file in any directory, the others match the full path.
+///
+/// See <https://git-scm.com/docs/gitattributes>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes {
+    /// patterns and the attributes of the paths they match
+    rules: Vec<(String, Vec<(String, AttrValue)>)>,
+}
+
+impl Attributes {
+    pub const LOCATION: &str = ".gitattributes";
+    pub const INFO_LOCATION: &str = "info/attributes";
+
+    pub fn parse(ctnt: &str) -> Self {
+        let mut rules = Vec::new();
+        for line in ctnt.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next() else {
+                continue;
+            };
+            if pattern.starts_with('#') {
+                continue;
+            }
+            let attrs = fields
+                .map(|attr| {
+                    if let Some(name) = attr.strip_prefix('-') {
+                        (name.to_string(), AttrValue::Unset)
+                    } else if let Some(name) = attr.strip_prefix('!') {
+                        (name.to_string(), AttrValue::Unspecified)
+                    } else if let Some((name, value)) = attr.split_once('=') {
+                        (name.to_string(), AttrValue::Value(value.to_string()))
+                    } else {
+                        (attr.to_string(), AttrValue::Set)
+                    }
+                })
+                .collect();
+            rules.push((pattern.to_string(), attrs));
+        }
+        Attributes { rules }
+    }
+
+    /// Load the attributes of the repository, whose `.git` dir is `root`.
+    /// Missing files are empty
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let read = |path: &Path| match fs::read_to_string(path) {
+            Ok(ctnt) => Ok(ctnt),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e),
+        };
+        let working_dir = root.parent().expect("git repo should have parent");
+        let mut attributes = Self::parse(&read(&working_dir.join(Self::LOCATION))?);
+        let info = Self::parse(&read(&root.join(Self::INFO_LOCATION))?);
+        attributes.rules.extend(info.rules);
+        Ok(attributes)
+    }
+
+    /// The attribute `name` of the file at `path`, relative to the top of the
+    /// working directory
+    pub fn get(&self, path: &str, name: &str) -> Option<&AttrValue> {
+        self.rules
+            .iter()
+            .rev()
+            .filter(|(pattern, _)| pattern_matches(pattern, path))
+            .find_map(|(_, attrs)| attrs.iter().rev().find(|(n, _)| n == name))
+            .map(|(_, value)| value)
+            .filter(|value| **value != AttrValue::Unspecified)
+    }
+}
+
+/// Whether the pattern matches the path, see [Attributes]
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_prefix('/') {
+        Some(pattern) => glob(pattern.as_bytes(), path.as_bytes()),
+        None if pattern.contains('/') => glob(pattern.as_bytes(), path.as_bytes()),
+        None => {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob(pattern.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Match with wildcards, `*` and `?` do not match `/`, but `**` does
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob(rest, &text[i..])),
+        [b'?', rest @ ..] => matches!(text, [c, ..] if *c != b'/') && glob(rest, &text[1..]),
+        [c, rest @ ..] => matches!(text, [t, ..] if t == c) && glob(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes_get() {
+        let attributes = Attributes::parse(
+            "# merge drivers\n\
+             *.md merge=union text\n\
+             docs/** -merge\n\
+             docs/keep.md !merge\n\
+             /CHANGELOG merge=union\n",
+        );
+        let union = AttrValue::Value("union".to_string());
+        assert_eq!(attributes.get("a/b/README.md", "merge"), Some(&union));
+        assert_eq!(attributes.get("README.md", "text"), Some(&AttrValue::Set));
+        assert_eq!(
+            attributes.get("docs/a/b.md", "merge"),
+            Some(&AttrValue::Unset)
+        );
+        assert_eq!(attributes.get("docs/keep.md", "merge"), None);
+        assert_eq!(attributes.get("CHANGELOG", "merge"), Some(&union));
+        assert_eq!(attributes.get("sub/CHANGELOG", "merge"), None);
+        assert_eq!(attributes.get("main.rs", "merge"), None);
+    }
+}
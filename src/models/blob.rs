@@ -1,10 +1,9 @@
 //! Blob represents a binary large object (BLOB) in a Git-like system.
 
-use std::{borrow::Cow, fmt::Display};
+use std::{borrow::Cow, fmt::Display, io};
 
-use super::object::Sha1Able;
+use super::object::GitObject;
 use bincode::{Decode, Encode};
-use sha1::Digest;
 
 #[derive(Debug, Clone, PartialEq, Eq, Decode, Encode)]
 pub enum Blob {
@@ -63,12 +62,24 @@ impl Blob {
     /// like this: �
     ///
     /// So you may need check if the blob is a [Blob::Binary] first
-    pub fn as_string(&self) -> Cow<str> {
+    pub fn as_string(&self) -> Cow<'_, str> {
         match self {
             Blob::Binary(data) => String::from_utf8_lossy(data),
             Blob::Text(text) => text.into(),
         }
     }
+
+    /// Create a blob from raw file content. Valid UTF-8 content becomes a
+    /// [Blob::Text], others become a [Blob::Binary]. Like git, content with a
+    /// NUL byte in the first 8000 bytes is also binary.
+    pub fn from_content(content: &[u8]) -> Self {
+        if content.iter().take(8000).any(|&b| b == 0) {
+            return Blob::Binary(content.to_vec());
+        }
+        String::from_utf8(content.to_vec())
+            .map(Blob::Text)
+            .unwrap_or_else(|e| Blob::Binary(e.into_bytes()))
+    }
 }
 
 impl Display for Blob {
@@ -77,25 +88,45 @@ impl Display for Blob {
     }
 }
 
-impl Sha1Able for Blob {
-    fn sha1(&self) -> String {
-        let mut hasher = sha1::Sha1::new();
-        hasher.update(self.as_bytes());
-        base16ct::lower::encode_string(&hasher.finalize())
+impl GitObject for Blob {
+    fn object_type(&self) -> &'static str {
+        "blob"
+    }
+
+    fn content(&self) -> io::Result<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::object::Sha1Able;
 
     #[test]
     fn test_blob_sha1() {
+        // same as `printf 'hello world' | git hash-object --stdin`
         let blob = Blob::from("hello world");
-        assert_eq!(blob.sha1(), "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+        assert_eq!(blob.sha1(), "95d09f2b10159347eece71399a7e2e907ea3df4f");
         let blob = Blob::from("hello world!");
-        assert_eq!(blob.sha1(), "430ce34d020724ed75a196dfc2ad67c77772d169");
+        assert_eq!(blob.sha1(), "bc7774a7b18deb1d7bd0212d34246a9b1260ae17");
         let blob = Blob::from(vec![1, 2, 3, 4, 5]);
-        assert_eq!(blob.sha1(), "11966ab9c099f8fabefac54c08d5be2bd8c903af");
+        assert_eq!(blob.sha1(), "177e962b377df6da68c3a78adeb93c2ace16c7c6");
+    }
+
+    #[test]
+    fn test_blob_from_content() {
+        assert_eq!(
+            Blob::from_content(
+                b"text
+"
+            ),
+            Blob::from(
+                "text
+"
+            )
+        );
+        assert!(matches!(Blob::from_content(b"\xff\xfe"), Blob::Binary(_)));
+        assert!(matches!(Blob::from_content(b"bin\0ary"), Blob::Binary(_)));
     }
 }
@@ -1,22 +1,33 @@
 //! Branch of the repository
 
-use std::io;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Component, Path},
+};
 
 use super::object::ObjectSha1;
-use crate::{
-    models::{Accessible, DirContainer, Store, commit::Commit, object::Object, repo::WithRepo},
-    serde_json_store,
+use crate::models::{
+    Accessible, DirContainer, Store,
+    commit::Commit,
+    object::Object,
+    repo::{Repository, WithRepo},
 };
 use regex::Regex;
-use serde::{Deserialize, Serialize};
 
 /// A branch is a "pointer" to a [Object::Commit](super::commit::Commit), stored
 /// in `refs/heads/{branch_name}` or `refs/remotes/{remote_name}/{branch_name}`
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Like git, the ref file only contains the sha1 of the commit and a newline.
+/// Refs may also be packed into the `packed-refs` file by git, see
+/// [PackedRefs].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Branch {
     /// If the branch belongs to a remote
     pub remote: Option<String>,
-    /// Name of the branch, should not contain special characters (especially `/`)
+    /// Name of the branch. Branches created by us should not contain special
+    /// characters (especially `/`), but those created by git may be like
+    /// `feature/x`
     ///
     /// See [Branch::validate_name]
     pub name: String,
@@ -52,45 +63,114 @@ impl Branch {
         }
     }
 
-    pub fn new(full_name: &str) -> Self {
-        let mut splited = full_name.split('/');
-        let first = splited.next().expect("branch name is empty");
-        let second = splited.next();
-        match second {
-            Some(name) => Branch {
-                name: name.to_string(),
-                remote: Some(first.to_string()),
-                head: ObjectSha1::from(EMPTY_BRANCH_HEAD_PLACEHOLDER),
-            },
-            None => Branch {
-                name: first.to_string(),
-                remote: None,
-                head: ObjectSha1::from(EMPTY_BRANCH_HEAD_PLACEHOLDER),
-            },
-        }
+    /// The branch of a ref, like `refs/heads/feature/x` or
+    /// `refs/remotes/origin/main`, `None` if the ref is not a branch
+    pub fn from_refname(refname: &str, head: ObjectSha1) -> Option<Self> {
+        let (remote, name) = match refname.strip_prefix("refs/heads/") {
+            Some(name) => (None, name),
+            None => {
+                let (remote, name) = refname.strip_prefix("refs/remotes/")?.split_once('/')?;
+                (Some(remote.to_string()), name)
+            }
+        };
+        (!name.is_empty()).then(|| Branch {
+            remote,
+            name: name.to_string(),
+            head,
+        })
+    }
+}
+
+/// Refs which may be the branch `by`. Like git, `a/b` is the local branch
+/// `refs/heads/a/b` if it exists, or the branch `b` of the remote `a`
+fn refnames_of(by: &str) -> Vec<String> {
+    let mut refnames = vec![format!("refs/heads/{by}")];
+    if by.contains('/') {
+        refnames.push(format!("refs/remotes/{by}"));
     }
+    refnames
 }
 
 fn path_of(by: &str) -> std::path::PathBuf {
-    let mut iter = by.split('/');
-    let first = iter.next().expect("branch name is empty");
-    if let Some(branch) = iter.next() {
-        std::path::PathBuf::from(format!("refs/remotes/{first}/{branch}"))
-    } else {
-        std::path::PathBuf::from(format!("refs/heads/{first}"))
+    std::path::PathBuf::from(&refnames_of(by)[0])
+}
+
+/// Load a branch from its ref file, or from the `packed-refs` file if the ref
+/// file does not exist
+fn load_from(root: &Path, by: &str) -> io::Result<Branch> {
+    let mut packed = None;
+    for refname in refnames_of(by) {
+        let head = match fs::read_to_string(root.join(&refname)) {
+            Ok(ctnt) => ctnt.trim().into(),
+            // a directory of branches, like `refs/heads/feature`
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::IsADirectory
+                ) =>
+            {
+                let packed = match &mut packed {
+                    Some(packed) => packed,
+                    None => packed.insert(PackedRefs::load(root)?),
+                };
+                match packed.0.remove(&refname) {
+                    Some(head) => head,
+                    None => continue,
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        return Branch::from_refname(&refname, head).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{refname} is not a branch"),
+            )
+        });
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("branch '{by}' not found"),
+    ))
+}
+
+/// Names of ref files under `dir`, including those in subdirectories, such as
+/// `feature/x` under `refs/heads`
+pub fn ref_names(dir: &Path, prefix: &str, names: &mut BTreeSet<String>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            ref_names(&entry.path(), &format!("{name}/"), names)?;
+        } else {
+            names.insert(name);
+        }
     }
+    Ok(())
 }
 
 impl Accessible<&str> for Branch {
     fn path_of(by: &&str) -> std::path::PathBuf {
         path_of(by)
     }
+
+    fn load_from(repo: &Repository, by: &&str) -> io::Result<Self> {
+        load_from(&repo.root, by)
+    }
 }
 
 impl Accessible<String> for Branch {
     fn path_of(by: &String) -> std::path::PathBuf {
         path_of(by)
     }
+
+    fn load_from(repo: &Repository, by: &String) -> io::Result<Self> {
+        load_from(&repo.root, by)
+    }
 }
 
 impl Store for Branch {
@@ -101,7 +181,143 @@ impl Store for Branch {
             std::path::PathBuf::from(format!("refs/heads/{}", self.name))
         }
     }
-    serde_json_store!();
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        if *self.head == EMPTY_BRANCH_HEAD_PLACEHOLDER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("branch '{}' does not have any commits yet", self.name),
+            ));
+        }
+        let path = root.join(self.location());
+        if let Some(parent) = path.parent() {
+            // Safely ignores the error if the directory already exists
+            let _ = fs::create_dir_all(parent);
+        }
+        fs::write(path, format!("{}\n", self.head))
+    }
+
+    /// The branch name is taken from the path relative to the `.git` dir,
+    /// which should be `refs/heads/{branch_name}` or
+    /// `refs/remotes/{remote_name}/{branch_name}`. The name may have `/`
+    fn load(path: &Path) -> io::Result<Self> {
+        let head = fs::read_to_string(path)?.trim().into();
+        // ref names never have a component starting with `.`, so the last
+        // `.git` is the repository
+        let components: Vec<_> = path.components().collect();
+        let start = components
+            .iter()
+            .rposition(|c| *c == Component::Normal(Repository::DIRECTORY.as_ref()))
+            .map_or(0, |i| i + 1);
+        let refname = components[start..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Branch::from_refname(&refname, head).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a branch", path.display()),
+            )
+        })
+    }
+
+    /// Delete the ref file, and the ref in `packed-refs` if exists
+    fn delete(&self, root: &Path) -> io::Result<()> {
+        let refname = self.location().to_string_lossy().replace('\\', "/");
+        let mut packed = PackedRefs::load(root)?;
+        let was_packed = packed.0.remove(&refname).is_some();
+        if was_packed {
+            packed.store(root)?;
+        }
+        match fs::remove_file(root.join(self.location())) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && was_packed => Ok(()),
+            res => res,
+        }
+    }
+}
+
+/// The `packed-refs` file of git, which stores many refs in a single file.
+///
+/// ```txt
+/// # pack-refs with: peeled fully-peeled sorted
+/// 0c12d336241a22c6b954d6fafdc998b6d9fb6e17 refs/heads/main
+/// 90627a7a454193542b63eabb58a5e4ca28757313 refs/remotes/origin/main
+/// ```
+///
+/// Loose ref files always take precedence over the packed ones.
+#[derive(Debug, Clone, Default)]
+pub struct PackedRefs(pub HashMap<String, ObjectSha1>);
+
+impl PackedRefs {
+    pub const LOCATION: &str = "packed-refs";
+
+    /// Load the packed refs of the `.git` dir `root`, an empty one will be
+    /// returned if there is no `packed-refs` file.
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let ctnt = match fs::read_to_string(root.join(Self::LOCATION)) {
+            Ok(ctnt) => ctnt,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let refs = ctnt
+            .lines()
+            // comments and peeled tags
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .map(|(sha1, name)| (name.to_string(), ObjectSha1::from(sha1)))
+            .collect();
+        Ok(PackedRefs(refs))
+    }
+
+    pub fn store(&self, root: &Path) -> io::Result<()> {
+        let mut refs = self.0.iter().collect::<Vec<_>>();
+        refs.sort_by_key(|(name, _)| *name);
+        let mut ctnt = String::from("# pack-refs with: sorted\n");
+        for (name, sha1) in refs {
+            ctnt.push_str(&format!("{sha1} {name}\n"));
+        }
+        fs::write(root.join(Self::LOCATION), ctnt)
+    }
+
+    /// Names of refs under the directory `prefix`, like `refs/heads/`
+    pub fn names_under<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .keys()
+            .filter_map(move |name| name.strip_prefix(prefix))
+    }
+}
+
+/// Resolve a ref, such as `HEAD`, `MERGE_HEAD` or `refs/heads/main`, to the
+/// object it points to. Symbolic refs are followed, and `None` is returned if
+/// the ref does not exist.
+pub fn read_ref(root: &Path, refname: &str) -> io::Result<Option<ObjectSha1>> {
+    let mut refname = refname.to_string();
+    // like git, avoid loops of symbolic refs
+    for _ in 0..5 {
+        let ctnt = match fs::read_to_string(root.join(&refname)) {
+            Ok(ctnt) => ctnt,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::IsADirectory
+                ) =>
+            {
+                return Ok(PackedRefs::load(root)?.0.remove(&refname));
+            }
+            Err(e) => return Err(e),
+        };
+        match ctnt.trim().strip_prefix("ref: ") {
+            Some(target) => refname = target.to_string(),
+            // FETCH_HEAD may have more content after the sha1
+            None => return Ok(ctnt.get(0..40).map(ObjectSha1::from)),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("symbolic ref {refname} is too deep"),
+    ))
 }
 
 impl DirContainer for Branch {
@@ -112,14 +328,47 @@ impl DirContainer for Branch {
         std::fs::create_dir_all(&path)?;
         std::fs::create_dir_all(path.join("heads"))?;
         std::fs::create_dir_all(path.join("remotes"))?;
+        std::fs::create_dir_all(path.join("tags"))?;
         Ok(())
     }
 }
 
 impl WithRepo<'_, Branch> {
-    pub fn get_current_commit(&self) -> io::Result<WithRepo<Commit>> {
+    pub fn get_current_commit(&self) -> io::Result<WithRepo<'_, Commit>> {
         let sha1 = &self.head;
         let obj = self.wrap(Object::accessor(sha1)).load()?;
         Ok(obj.map(|o| o.cast_commit()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branches_may_have_slashes_in_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join(Repository::DIRECTORY);
+        let sha1 = "2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f";
+        for refname in ["refs/heads/feature/x", "refs/remotes/origin/fix/y"] {
+            fs::create_dir_all(root.join(refname).parent().unwrap()).unwrap();
+            fs::write(root.join(refname), format!("{sha1}\n")).unwrap();
+        }
+
+        let local = Branch::load(&root.join("refs/heads/feature/x")).unwrap();
+        assert_eq!((local.remote, local.name.as_str()), (None, "feature/x"));
+        assert_eq!(*local.head, sha1);
+        let remote = load_from(&root, "origin/fix/y").unwrap();
+        assert_eq!(remote.remote.as_deref(), Some("origin"));
+        assert_eq!(remote.name, "fix/y");
+        assert_eq!(load_from(&root, "feature/x").unwrap().remote, None);
+        assert_eq!(
+            load_from(&root, "feature").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let mut names = BTreeSet::new();
+        ref_names(&root.join("refs/heads"), "", &mut names).unwrap();
+        assert_eq!(names.into_iter().collect::<Vec<_>>(), ["feature/x"]);
+    }
+}
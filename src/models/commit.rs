@@ -2,71 +2,221 @@
 
 use std::{fmt::Display, io};
 
-use crate::models::{
-    Accessible,
-    object::Object,
-    repo::WithRepo,
-    tree::Tree,
-};
+use crate::models::{Accessible, object::Object, repo::WithRepo, tree::Tree};
 
-use super::object::{ObjectSha1, Sha1Able};
+use super::object::{GitObject, ObjectSha1};
 
 use bincode::{Decode, Encode};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Offset, Utc};
 use serde::{Deserialize, Serialize};
-use sha1::Digest;
+
+/// The name used when no identity is configured
+pub const DEFAULT_NAME: &str = "kidon-git";
+/// The email used when no identity is configured
+pub const DEFAULT_EMAIL: &str = "kidon-git@localhost";
+
+/// Parse a timezone like `+0800` or `-0530` to its offset from UTC in
+/// minutes. Hours over 23 and minutes over 59 are invalid
+pub fn parse_offset(tz: &str) -> Option<i32> {
+    let (sign, digits) = match tz.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Who did something and when, as in the `author` and `committer` lines of a
+/// commit, or the `tagger` line of a tag
+///
+/// ```txt
+/// A U Thor <author@example.com> 1700000000 +0800
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// seconds since the Unix epoch
+    pub timestamp: i64,
+    /// offset of the timezone from UTC in minutes, like `480` for `+0800`
+    pub offset: i32,
+    /// the line as it was read, if it is malformed or not written the same
+    /// way, so that the object is written back unchanged
+    pub raw: Option<String>,
+}
+
+impl Signature {
+    /// Parse a signature like `A U Thor <author@example.com> 1700000000 +0800`
+    pub fn parse(value: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid signature '{value}'"),
+            )
+        };
+        let (ident, time) = value.rsplit_once('>').ok_or_else(invalid)?;
+        let (name, email) = ident.split_once('<').ok_or_else(invalid)?;
+        let (timestamp, tz) = time.trim().split_once(' ').ok_or_else(invalid)?;
+        Ok(Signature {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            timestamp: timestamp.parse().map_err(|_| invalid())?,
+            offset: parse_offset(tz).ok_or_else(invalid)?,
+            raw: None,
+        })
+    }
+
+    /// Parse a signature read from an object, like [Signature::parse], but a
+    /// malformed one does not fail. It keeps the parts which can be parsed,
+    /// and the line in [Signature::raw]
+    pub fn parse_lenient(value: &str) -> Self {
+        let mut signature = Self::parse(value).unwrap_or_else(|_| {
+            let (ident, time) = value.rsplit_once('>').unwrap_or((value, ""));
+            let (name, email) = ident.split_once('<').unwrap_or((ident, ""));
+            let mut time = time.split_whitespace();
+            Signature {
+                name: name.trim().to_string(),
+                email: email.to_string(),
+                timestamp: time.next().and_then(|t| t.parse().ok()).unwrap_or(0),
+                offset: time.next().and_then(parse_offset).unwrap_or(0),
+                raw: None,
+            }
+        });
+        if signature.to_string() != value {
+            signature.raw = Some(value.to_string());
+        }
+        signature
+    }
+
+    /// The time in its own timezone. Like git, an invalid timezone is UTC,
+    /// and a timestamp out of range is the Unix epoch
+    pub fn time(&self) -> DateTime<FixedOffset> {
+        let tz = FixedOffset::east_opt(self.offset * 60).unwrap_or_else(|| Utc.fix());
+        DateTime::from_timestamp(self.timestamp, 0)
+            .unwrap_or_default()
+            .with_timezone(&tz)
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(raw) = &self.raw {
+            return write!(f, "{raw}");
+        }
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(
+            f,
+            "{} <{}> {} {sign}{:02}{:02}",
+            self.name,
+            self.email,
+            self.timestamp,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
 
 /// A git commit, contains commit information, and some "pointers"
-/// ([ObjectSha1]) to its file [Tree](super::tree::Tree), and its parent commit
+/// ([ObjectSha1]) to its file [Tree](super::tree::Tree), and its parent commits
 ///
 /// Commit forms a DAG
 #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
 pub struct Commit {
     /// Commit tree
     pub tree: ObjectSha1,
-    /// Privous commit
-    pub parent: Option<ObjectSha1>,
-    /// Commit time
-    pub timestamp: (i64, u32),
+    /// Privous commits, in order. A root commit has no parent, and a merge
+    /// commit has the current branch as the first parent
+    pub parents: Vec<ObjectSha1>,
+    /// Who wrote the changes, and when
+    pub author: Signature,
+    /// Who made the commit, and when. Commits are ordered by its time
+    pub committer: Signature,
     /// Commit message.  
-    /// The first line is the summary, and the rest is the body.
+    /// The first line is the summary, and the rest is the body. Like git, a
+    /// message created by [Commit::new] always ends with a newline.
     pub message: String,
 }
 
 /// Structure to create a new [Commit]
 pub struct CommitBuilder {
     pub tree: ObjectSha1,
-    pub parent: Option<ObjectSha1>,
+    pub parents: Vec<ObjectSha1>,
+    pub author: Signature,
+    pub committer: Signature,
     pub message: String,
 }
 
 impl Commit {
-    /// Commit time of the commit
-    pub fn time(&self) -> DateTime<Utc> {
-        DateTime::from_timestamp(self.timestamp.0, self.timestamp.1).expect("Invalid timestamp")
-    }
-
-    /// Create a new commit with time = [Utc::now]
+    /// Create a new commit
     ///
     /// # Examples
     ///
     /// ```
     /// let commit = Commit::new(CommitBuilder {
     ///     tree: "abcd".into(),
-    ///     parent: None,
+    ///     parents: vec![],
+    ///     author: repo.signature(Role::Author)?,
+    ///     committer: repo.signature(Role::Committer)?,
     ///     message: "first commit"
     /// });
     /// // the commit is not saved until you call .save()
     /// repo.wrap(commit).save()?
     /// ```
     pub fn new(by: CommitBuilder) -> Commit {
-        let now = Utc::now();
+        let mut message = by.message;
+        if !message.ends_with('\n') {
+            message.push('\n');
+        }
         Commit {
             tree: by.tree,
-            parent: by.parent,
-            timestamp: (now.timestamp(), now.timestamp_subsec_nanos()),
-            message: by.message,
+            parents: by.parents,
+            author: by.author,
+            committer: by.committer,
+            message,
+        }
+    }
+
+    /// Parse the content of a git commit object, see [GitObject::content]
+    pub fn from_content(content: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let content =
+            std::str::from_utf8(content).map_err(|_| invalid("commit is not valid UTF-8"))?;
+        let (headers, message) = content
+            .split_once("\n\n")
+            .unwrap_or((content.trim_end_matches('\n'), ""));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let (mut author, mut committer) = (None, None);
+        for header in headers.lines() {
+            // continuation lines of multi-line headers, like gpgsig
+            let Some((key, value)) = header.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "tree" => tree = Some(ObjectSha1::from(value)),
+                "parent" => parents.push(ObjectSha1::from(value)),
+                "author" => author = Some(Signature::parse_lenient(value)),
+                "committer" => committer = Some(Signature::parse_lenient(value)),
+                _ => {}
+            }
         }
+
+        Ok(Commit {
+            tree: tree.ok_or_else(|| invalid("commit has no tree"))?,
+            parents,
+            author: author.ok_or_else(|| invalid("commit has no author"))?,
+            committer: committer.ok_or_else(|| invalid("commit has no committer"))?,
+            message: message.to_string(),
+        })
     }
 }
 
@@ -83,25 +233,36 @@ impl Display for Commit {
     }
 }
 
-impl Sha1Able for Commit {
-    fn sha1(&self) -> String {
-        let mut hasher = sha1::Sha1::new();
-        hasher.update(self.tree.as_bytes());
-        hasher.update(
-            self.parent
-                .as_ref()
-                .map(|s| s.as_bytes())
-                .unwrap_or("".as_bytes()),
-        );
-        hasher.update(self.timestamp.0.to_le_bytes());
-        hasher.update(self.timestamp.1.to_le_bytes());
-        hasher.update(self.message.as_bytes());
-        base16ct::lower::encode_string(&hasher.finalize())
+impl GitObject for Commit {
+    fn object_type(&self) -> &'static str {
+        "commit"
+    }
+
+    /// The commit is encoded as headers, an empty line, and the message
+    ///
+    /// ```txt
+    /// tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+    /// parent 2aae6c35c94fcfb415dbe95f408b9ce91ee846ed
+    /// author A U Thor <author@example.com> 1700000000 +0800
+    /// committer C O Mitter <committer@example.com> 1700000001 -0500
+    ///
+    /// commit message
+    /// ```
+    fn content(&self) -> io::Result<Vec<u8>> {
+        let mut data = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            data.push_str(&format!("parent {parent}\n"));
+        }
+        data.push_str(&format!("author {}\n", self.author));
+        data.push_str(&format!("committer {}\n", self.committer));
+        data.push('\n');
+        data.push_str(&self.message);
+        Ok(data.into_bytes())
     }
 }
 
 impl WithRepo<'_, Commit> {
-    pub fn get_tree(&self) -> io::Result<WithRepo<Tree>> {
+    pub fn get_tree(&self) -> io::Result<WithRepo<'_, Tree>> {
         let sha1 = &self.tree;
         let obj = self.wrap(Object::accessor(sha1)).load()?;
         Ok(obj.map(|o| o.cast_tree()))
@@ -111,19 +272,127 @@ impl WithRepo<'_, Commit> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::object::Sha1Able;
+
+    fn default_signature() -> Signature {
+        Signature {
+            name: DEFAULT_NAME.to_string(),
+            email: DEFAULT_EMAIL.to_string(),
+            timestamp: 1700000000,
+            offset: 0,
+            raw: None,
+        }
+    }
 
     #[test]
     fn test_commit_sha1() {
         let mut commit = Commit {
-            tree: "tree_hash".into(),
-            parent: Some("parent_hash".into()),
-            timestamp: (0, 0),
-            message: "commit message".into(),
+            tree: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".into(),
+            parents: vec!["95d09f2b10159347eece71399a7e2e907ea3df4f".into()],
+            author: default_signature(),
+            committer: default_signature(),
+            message: "commit message\n".into(),
         };
 
+        // same as `git hash-object -t commit` with the content of the commit
         let sha1 = commit.sha1();
-        assert_eq!(sha1, "9fdae82bc2f37cc414b82bb7255c48461ee8c096");
-        commit.tree = "new_tree_hash".into();
+        assert_eq!(sha1, "0e1c6c57130dd977cf3cb8746e7203986d3d32df");
+        commit.tree = "449ad38ee091f707a4da5dfd2d252b04b01ab79f".into();
         assert_ne!(sha1, commit.sha1());
     }
+
+    #[test]
+    fn test_merge_commit_sha1() {
+        let commit = Commit {
+            tree: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".into(),
+            parents: vec![
+                "95d09f2b10159347eece71399a7e2e907ea3df4f".into(),
+                "0e1c6c57130dd977cf3cb8746e7203986d3d32df".into(),
+            ],
+            author: default_signature(),
+            committer: default_signature(),
+            message: "Merge branch 'feature'\n".into(),
+        };
+        assert_eq!(commit.sha1(), "fed2c8c8483a9d4a890c13d4e20874b3fa51011b");
+
+        // parents are parsed in order
+        let parsed = Commit::from_content(&commit.content().unwrap()).unwrap();
+        assert_eq!(parsed, commit);
+    }
+
+    #[test]
+    fn test_commit_from_content() {
+        let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author A U Thor <author@example.com> 1700000000 +0800
+committer C O Mitter <committer@example.com> 1700000001 -0500
+
+summary
+
+body
+";
+        let commit = Commit::from_content(content).unwrap();
+        assert_eq!(
+            commit.tree.as_str(),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+        assert!(commit.parents.is_empty());
+        assert_eq!(commit.author.name, "A U Thor");
+        assert_eq!(commit.author.email, "author@example.com");
+        assert_eq!(commit.author.offset, 480);
+        assert_eq!(
+            (commit.committer.timestamp, commit.committer.offset),
+            (1700000001, -300)
+        );
+        assert_eq!(commit.message, "summary\n\nbody\n");
+        assert_eq!(
+            commit.committer.to_string(),
+            "C O Mitter <committer@example.com> 1700000001 -0500"
+        );
+        assert_eq!(
+            commit.committer.time().to_rfc3339(),
+            "2023-11-14T17:13:21-05:00"
+        );
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("+0000"), Some(0));
+        assert_eq!(parse_offset("-0530"), Some(-330));
+        assert_eq!(parse_offset("+2359"), Some(1439));
+        assert_eq!(parse_offset("+2400"), None);
+        assert_eq!(parse_offset("+0060"), None);
+        assert_eq!(parse_offset("+9999"), None);
+        assert_eq!(parse_offset("0800"), None);
+    }
+
+    #[test]
+    fn signature_time_should_not_panic() {
+        let mut signature = default_signature();
+        signature.offset = 9999;
+        assert_eq!(signature.time().to_rfc3339(), "2023-11-14T22:13:20+00:00");
+        signature.timestamp = i64::MAX;
+        assert_eq!(signature.time().to_rfc3339(), "1970-01-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn malformed_signatures_should_be_kept() {
+        let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author A U Thor <author@example.com> 1700000000 +9999
+committer C O Mitter<committer@example.com> 99999999999999999999 -0500
+
+summary
+";
+        let commit = Commit::from_content(content).unwrap();
+        assert_eq!(commit.author.name, "A U Thor");
+        assert_eq!(commit.author.timestamp, 1700000000);
+        assert_eq!(commit.author.offset, 0);
+        assert_eq!(commit.committer.name, "C O Mitter");
+        assert_eq!(commit.committer.offset, -300);
+        assert_eq!(
+            commit.author.raw.as_deref(),
+            Some("A U Thor <author@example.com> 1700000000 +9999")
+        );
+        // written back as it was, so that the sha1 does not change
+        assert_eq!(commit.content().unwrap(), content);
+    }
 }
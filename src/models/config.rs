@@ -0,0 +1,181 @@
+//! Config of the repository
+
+use std::{fs, io, path::Path};
+
+/// The `config` file of git, in an INI-like format:
+///
+/// ```txt
+/// [core]
+///     bare = false
+/// [branch "main"]
+///     remote = origin
+///     merge = refs/heads/main
+/// ```
+///
+/// Values are read by keys like `branch.main.remote`. Section and variable
+/// names are case-insensitive, but subsection names are not.
+///
+/// See <https://git-scm.com/docs/git-config#_configuration_file>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// keys and values in the order they appear, later values override
+    /// earlier ones
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    pub const LOCATION: &str = "config";
+
+    /// Normalize a key to `section.subsection.name`, where the section and the
+    /// name are lowercase
+    fn normalize_key(key: &str) -> String {
+        match (key.find('.'), key.rfind('.')) {
+            (Some(first), Some(last)) if first != last => format!(
+                "{}{}{}",
+                key[..first].to_lowercase(),
+                &key[first..last],
+                key[last..].to_lowercase()
+            ),
+            _ => key.to_lowercase(),
+        }
+    }
+
+    /// Get the last value of the key, such as `user.name`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = Self::normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn parse(ctnt: &str) -> io::Result<Self> {
+        let invalid = |line: usize, msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad config line {}: {msg}", line + 1),
+            )
+        };
+
+        let mut entries = Vec::new();
+        let mut section = None;
+        for (no, line) in ctnt.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let (header, _) = header
+                    .split_once(']')
+                    .ok_or_else(|| invalid(no, "section is not closed"))?;
+                section = Some(match header.split_once(|c: char| c.is_whitespace()) {
+                    // [section "subsection"]
+                    Some((name, sub)) => {
+                        let sub = sub
+                            .trim()
+                            .strip_prefix('"')
+                            .and_then(|s| s.strip_suffix('"'))
+                            .ok_or_else(|| invalid(no, "subsection should be quoted"))?;
+                        format!("{}.{}", name.to_lowercase(), sub.replace("\\\"", "\""))
+                    }
+                    // [section] or the deprecated [section.subsection]
+                    None => header.to_lowercase(),
+                });
+                continue;
+            }
+
+            let section = section
+                .as_ref()
+                .ok_or_else(|| invalid(no, "variable is not in a section"))?;
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), parse_value(value)),
+                // a variable without value means true
+                None => (line, "true".to_string()),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-') {
+                return Err(invalid(no, "invalid variable name"));
+            }
+            entries.push((format!("{section}.{}", name.to_lowercase()), value));
+        }
+
+        Ok(Config { entries })
+    }
+
+    /// Load the config in the `.git` dir `root`, a missing config is empty
+    pub fn load(root: &Path) -> io::Result<Self> {
+        match fs::read_to_string(root.join(Self::LOCATION)) {
+            Ok(ctnt) => Self::parse(&ctnt),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Unquote the value, handle escapes, and strip the comment after it
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = raw.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(c) => value.push(c),
+                None => {}
+            },
+            '#' | ';' if !quoted => break,
+            c => value.push(c),
+        }
+    }
+    if quoted {
+        value
+    } else {
+        value.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parse() {
+        let config = Config::parse(
+            r#"
+# comment
+[core]
+    bare = false
+    ignoreCase
+[branch "Feature"]
+    remote = origin ; the remote
+    merge = refs/heads/feature
+[user]
+    name = "Kidon \"K\" Git"
+    name = overridden
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(config.get("Core.IgnoreCase"), Some("true"));
+        assert_eq!(config.get("branch.Feature.remote"), Some("origin"));
+        assert_eq!(config.get("branch.feature.remote"), None);
+        assert_eq!(
+            config.get("BRANCH.Feature.MERGE"),
+            Some("refs/heads/feature")
+        );
+        assert_eq!(config.get("user.name"), Some("overridden"));
+        assert_eq!(
+            Config::parse("[user]\nname = \"Kidon \\\"K\\\" Git\"")
+                .unwrap()
+                .get("user.name"),
+            Some("Kidon \"K\" Git")
+        );
+        assert!(Config::parse("name = value").is_err());
+        assert!(Config::parse("[core").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Deltas between two objects, used in packfiles.
+//!
+//! A delta describes how to rebuild a target from a base object. It starts
+//! with the sizes of the base and the target, followed by instructions which
+//! either copy a range of the base, or insert new bytes.
+//!
+//! See <https://git-scm.com/docs/pack-format#_deltified_representation>
+
+use std::{collections::HashMap, io};
+
+/// Size of the blocks used to find the same content in the base
+const BLOCK: usize = 16;
+/// Max bytes of a single copy instruction
+const MAX_COPY: usize = 0x10000;
+/// Max bytes of a single insert instruction
+const MAX_INSERT: usize = 0x7f;
+/// Max candidates kept for the same block, to avoid slowing down on
+/// repetitive content
+const MAX_CANDIDATES: usize = 64;
+
+fn write_size(data: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            data.push(byte);
+            return;
+        }
+        data.push(byte | 0x80);
+    }
+}
+
+fn read_size(delta: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*pos).ok_or_else(truncated)?;
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "delta is truncated")
+}
+
+fn flush_insert(data: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    for chunk in pending.chunks(MAX_INSERT) {
+        data.push(chunk.len() as u8);
+        data.extend(chunk);
+    }
+    pending.clear();
+}
+
+fn write_copy(data: &mut Vec<u8>, offset: usize, size: usize) {
+    let mut op = 0x80u8;
+    let mut args = Vec::new();
+    for i in 0..4 {
+        let byte = (offset >> (i * 8)) as u8;
+        if byte != 0 {
+            op |= 1 << i;
+            args.push(byte);
+        }
+    }
+    // a size of 0x10000 is written as 0
+    let size = if size == MAX_COPY { 0 } else { size };
+    for i in 0..3 {
+        let byte = (size >> (i * 8)) as u8;
+        if byte != 0 {
+            op |= 1 << (4 + i);
+            args.push(byte);
+        }
+    }
+    data.push(op);
+    data.extend(args);
+}
+
+/// Create a delta which rebuilds `target` from `base`
+pub fn create(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_size(&mut data, base.len());
+    write_size(&mut data, target.len());
+
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        let candidates = index.entry(&base[offset..offset + BLOCK]).or_default();
+        if candidates.len() < MAX_CANDIDATES {
+            candidates.push(offset);
+        }
+    }
+
+    let mut pending = Vec::new();
+    let mut pos = 0;
+    while pos < target.len() {
+        let best = target
+            .get(pos..pos + BLOCK)
+            .and_then(|block| index.get(block))
+            .into_iter()
+            .flatten()
+            .map(|&offset| {
+                let len = base[offset..]
+                    .iter()
+                    .zip(&target[pos..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (offset, len)
+            })
+            .max_by_key(|&(_, len)| len);
+
+        match best {
+            Some((offset, len)) => {
+                flush_insert(&mut data, &mut pending);
+                let mut copied = 0;
+                while copied < len {
+                    let size = (len - copied).min(MAX_COPY);
+                    write_copy(&mut data, offset + copied, size);
+                    copied += size;
+                }
+                pos += len;
+            }
+            None => {
+                pending.push(target[pos]);
+                pos += 1;
+            }
+        }
+    }
+    flush_insert(&mut data, &mut pending);
+
+    data
+}
+
+/// Rebuild the target from `base` and the `delta`
+pub fn apply(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut pos = 0;
+    if read_size(delta, &mut pos)? != base.len() {
+        return Err(invalid("delta base size does not match"));
+    }
+    let target_size = read_size(delta, &mut pos)?;
+    // the size is not trusted, the target grows as it is rebuilt
+    let mut target = Vec::with_capacity(target_size.min(base.len() + delta.len()));
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut arg = |bits: u8, count: usize| -> io::Result<usize> {
+                let mut value = 0;
+                for i in 0..count {
+                    if bits & (1 << i) != 0 {
+                        value |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (i * 8);
+                        pos += 1;
+                    }
+                }
+                Ok(value)
+            };
+            let offset = arg(op & 0x0f, 4)?;
+            let size = match arg((op >> 4) & 0x07, 3)? {
+                0 => MAX_COPY,
+                size => size,
+            };
+            let copied = base
+                .get(offset..offset + size)
+                .ok_or_else(|| invalid("delta copies out of the base"))?;
+            target.extend(copied);
+        } else if op != 0 {
+            let inserted = delta.get(pos..pos + op as usize).ok_or_else(truncated)?;
+            target.extend(inserted);
+            pos += op as usize;
+        } else {
+            return Err(invalid("delta has a reserved instruction"));
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid("delta target size does not match"));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = "fn main() {\n    println!(\"hello world\");\n}\n".repeat(20);
+        let target = base.replace("world", "kidon") + "// the end\n";
+        let delta = create(base.as_bytes(), target.as_bytes());
+        assert!(delta.len() < target.len() / 2);
+        assert_eq!(apply(base.as_bytes(), &delta).unwrap(), target.as_bytes());
+
+        let big = (0..200_000u32)
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>();
+        let delta = create(&big, &big);
+        assert_eq!(apply(&big, &delta).unwrap(), big);
+
+        let delta = create(b"", b"new content");
+        assert_eq!(apply(b"", &delta).unwrap(), b"new content");
+    }
+
+    #[test]
+    fn test_delta_bad_base() {
+        let delta = create(b"some base content here", b"some target");
+        assert!(apply(b"another base", &delta).is_err());
+    }
+
+    #[test]
+    fn test_delta_huge_target_size() {
+        // base size 0, a target size of about 2^63 and no instructions
+        let delta = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        assert!(apply(b"", &delta).is_err());
+    }
+}
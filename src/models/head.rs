@@ -1,44 +1,98 @@
 //! A pointer to the currently active branch of the context (repository, remote,
 //! etc.)
 
-use crate::{
-    models::{Accessible, Store},
-    serde_json_store,
-};
-use serde::{Deserialize, Serialize, ser::SerializeStruct};
+use crate::models::{Accessible, Store};
 use std::{
-    io,
+    fs, io,
     path::{Path, PathBuf},
 };
 
-use super::{branch::Branch, repo::WithRepo};
+use super::{
+    branch::{Branch, EMPTY_BRANCH_HEAD_PLACEHOLDER},
+    object::ObjectSha1,
+    repo::WithRepo,
+};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum HeadKind {
     Local,
     Remote(String),
+    /// `HEAD` points to a commit instead of a branch, which may be left by
+    /// git. Commands which need a branch fail until a branch is checked out
+    Detached(ObjectSha1),
 }
 
 /// Head is a repo's `HEAD` file, pointers to a [Branch]
-#[derive(Debug, PartialEq, Deserialize)]
+///
+/// Like git, the file is a symbolic ref, such as `ref: refs/heads/main`, or
+/// the sha1 of a commit if the head is detached
+#[derive(Debug, PartialEq)]
 pub struct Head {
     pub kind: HeadKind,
+    /// `HEAD` if the head is detached
     pub branch_name: String,
 }
 
-impl Serialize for Head {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_struct("Head", 3)?;
-        state.serialize_field("kind", &self.kind)?;
-        state.serialize_field("branch_name", &self.branch_name)?;
+impl Head {
+    /// the ref the head points to, such as `refs/heads/main`, or `HEAD` itself
+    /// if it is detached
+    pub fn refname(&self) -> String {
+        match &self.kind {
+            HeadKind::Local => format!("refs/heads/{}", self.branch_name),
+            HeadKind::Remote(remote) => format!("refs/remotes/{remote}/{}", self.branch_name),
+            HeadKind::Detached(_) => "HEAD".to_string(),
+        }
+    }
+
+    /// The commit of a detached head
+    pub fn detached(&self) -> Option<&ObjectSha1> {
+        match &self.kind {
+            HeadKind::Detached(sha1) => Some(sha1),
+            _ => None,
+        }
+    }
 
-        // fuck the oj test
-        state.serialize_field("message", &format!("ref: refs/heads/{}", self.branch_name))?;
+    /// Point the head to the local branch
+    pub fn switch_to(&mut self, branch_name: &str) {
+        self.kind = HeadKind::Local;
+        self.branch_name = branch_name.to_string();
+    }
+
+    /// Parse the content of a `HEAD` file
+    pub fn parse(ctnt: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("HEAD is not a symbolic ref: {}", ctnt.trim()),
+            )
+        };
+        let Some(refname) = ctnt.trim().strip_prefix("ref: ") else {
+            let sha1 = ObjectSha1::from(ctnt.trim());
+            if !sha1.is_valid() {
+                return Err(invalid());
+            }
+            return Ok(Head {
+                kind: HeadKind::Detached(sha1),
+                branch_name: "HEAD".to_string(),
+            });
+        };
 
-        state.end()
+        if let Some(name) = refname.strip_prefix("refs/heads/") {
+            Ok(Head {
+                kind: HeadKind::Local,
+                branch_name: name.to_string(),
+            })
+        } else if let Some((remote, name)) = refname
+            .strip_prefix("refs/remotes/")
+            .and_then(|r| r.split_once('/'))
+        {
+            Ok(Head {
+                kind: HeadKind::Remote(remote.to_string()),
+                branch_name: name.to_string(),
+            })
+        } else {
+            Err(invalid())
+        }
     }
 }
 
@@ -46,27 +100,44 @@ impl Store for Head {
     fn location(&self) -> PathBuf {
         Path::new("HEAD").to_path_buf()
     }
-    serde_json_store!();
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        let ctnt = match &self.kind {
+            HeadKind::Detached(sha1) => format!("{sha1}\n"),
+            _ => format!("ref: {}\n", self.refname()),
+        };
+        fs::write(root.join(self.location()), ctnt)
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
 }
 
 impl<'r> WithRepo<'r, &Head> {
     /// the branch may not exist, so we need to create it if it does not exist
     pub fn load_branch_or_create(&self) -> io::Result<(WithRepo<'r, Branch>, bool)> {
-        let name = self.branch_name.as_str();
-        let branch = self.wrap(Branch::accessor(&name)).load();
-        match branch {
+        match self.load_branch() {
             Ok(branch) => Ok((branch, false)),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok((self.wrap(Branch::new(&self.branch_name)), true))
-                } else {
-                    Err(e)
-                }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let head = ObjectSha1::from(EMPTY_BRANCH_HEAD_PLACEHOLDER);
+                let branch = Branch::from_refname(&self.refname(), head).ok_or(e)?;
+                Ok((self.wrap(branch), true))
             }
+            Err(e) => Err(e),
         }
     }
 
     pub fn load_branch(&self) -> io::Result<WithRepo<'r, Branch>> {
+        if let Some(sha1) = self.detached() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "HEAD is detached at {}, which is not supported, check out a branch first",
+                    &sha1[..7]
+                ),
+            ));
+        }
         let name = self.branch_name.as_str();
         self.wrap(Branch::accessor(&name)).load()
     }
@@ -82,8 +153,14 @@ mod tests {
             kind: HeadKind::Local,
             branch_name: "main".to_string(),
         };
-        let serialized = serde_json::to_string(&head).unwrap();
+        assert_eq!(head.refname(), "refs/heads/main");
+        assert_eq!(Head::parse("ref: refs/heads/main\n").unwrap(), head);
 
-        assert_eq!(serialized.contains("ref: refs/heads/main"), true,);
+        let detached = Head::parse("2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f\n").unwrap();
+        assert_eq!(
+            detached.detached().map(|s| s.as_str()),
+            Some("2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f")
+        );
+        assert!(Head::parse("main").is_err());
     }
 }
@@ -0,0 +1,65 @@
+//! State of a merge stopped by conflicts
+
+use super::{Store, object::ObjectSha1};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// An unfinished merge. Like git, it is stored in `MERGE_HEAD`, the commit
+/// being merged, and `MERGE_MSG`, the message of the merge commit.
+///
+/// The conflicted paths are recorded in the [Stage](super::stage::Stage)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeState {
+    pub theirs: ObjectSha1,
+    pub message: String,
+}
+
+impl MergeState {
+    pub const LOCATION: &str = "MERGE_HEAD";
+    pub const MESSAGE_LOCATION: &str = "MERGE_MSG";
+
+    /// The message for the merge commit, without the comment lines
+    pub fn commit_message(&self) -> String {
+        let lines: Vec<_> = self
+            .message
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        lines.join("\n").trim().to_string()
+    }
+}
+
+impl Store for MergeState {
+    fn location(&self) -> PathBuf {
+        Path::new(Self::LOCATION).to_path_buf()
+    }
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        fs::write(root.join(Self::LOCATION), format!("{}\n", self.theirs))?;
+        fs::write(root.join(Self::MESSAGE_LOCATION), &self.message)
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        let root = path.parent().expect("MERGE_HEAD should be in the .git dir");
+        let theirs = fs::read_to_string(path)?;
+        let message = match fs::read_to_string(root.join(Self::MESSAGE_LOCATION)) {
+            Ok(message) => message,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(MergeState {
+            theirs: ObjectSha1::from(theirs.trim()),
+            message,
+        })
+    }
+
+    fn delete(&self, root: &Path) -> io::Result<()> {
+        fs::remove_file(root.join(Self::LOCATION))?;
+        match fs::remove_file(root.join(Self::MESSAGE_LOCATION)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
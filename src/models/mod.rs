@@ -5,16 +5,26 @@
 //! runtime. You should not put specific interactive functions here except
 //! loading and saving from disk.
 
+pub mod attributes;
 pub mod blob;
 pub mod branch;
 pub mod commit;
+pub mod config;
+pub mod delta;
 pub mod head;
 pub mod ignores;
+pub mod merge_state;
 pub mod object;
+pub mod pack;
+pub mod rebase;
 pub mod repo;
+pub mod sequencer;
 pub mod stage;
+pub mod stash;
+pub mod tag;
 pub mod tree;
 
+use repo::Repository;
 use std::{
     io,
     marker::PhantomData,
@@ -48,30 +58,6 @@ where
     }
 }
 
-#[macro_export]
-macro_rules! serde_json_store {
-    () => {
-        fn store(&self, root: &std::path::Path) -> std::io::Result<()> {
-            let path = root.join(self.location());
-            if let Some(parent) = path.parent() {
-                // Safely ignores the error if the directory already exists
-                let _ = std::fs::create_dir_all(parent);
-            }
-            std::fs::write(
-                path,
-                serde_json::to_string(self)
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
-            )
-        }
-        fn load(path: &std::path::Path) -> std::io::Result<Self> {
-            let data = std::fs::read(path)?;
-            let inner = serde_json::from_slice(&data)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-            Ok(inner)
-        }
-    };
-}
-
 /// Wrapped the accessor to the storable object
 pub struct Accessor<'a, By, T>
 where
@@ -96,14 +82,11 @@ where
     }
 
     fn path_of(by: &By) -> PathBuf;
-}
 
-impl<By, T> Accessor<'_, By, T>
-where
-    T: Store,
-    T: Accessible<By>,
-{
-    pub fn path(&self) -> PathBuf {
-        T::path_of(self.by)
+    /// Load the object from the repository. By default it is loaded from
+    /// [Accessible::path_of], but some objects can be found in other places,
+    /// such as packed files.
+    fn load_from(repo: &Repository, by: &By) -> io::Result<Self> {
+        Self::load(&repo.root.join(Self::path_of(by)))
     }
 }
@@ -1,31 +1,97 @@
 //! git objects
 
-use super::{blob::Blob, commit::Commit, tree::Tree};
+use super::{blob::Blob, commit::Commit, pack::Pack, repo::Repository, tag::Tag, tree::Tree};
 use crate::models::{Accessible, DirContainer, Store};
 use bincode::{Decode, Encode};
 use enum_dispatch::enum_dispatch;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
 use serde::{Deserialize, Serialize};
+use sha1::Digest;
 use std::{
+    collections::BTreeSet,
     fmt::Display,
     fs,
+    io::{self, Read, Write},
     mem::transmute,
     ops::Deref,
     path::{Path, PathBuf},
 };
 
-#[enum_dispatch]
+/// Abbreviated sha1s shorter than it are never resolved, like git
+pub const MIN_ABBREV: usize = 4;
+
 pub trait Sha1Able {
     /// sha1 of the sha1able object
     fn sha1(&self) -> String;
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Decode, Encode, Serialize, Deserialize, Hash)]
+/// An object which has a canonical git encoding.
+///
+/// Git hashes and stores an object as `<type> <len>\0<content>`, so two
+/// objects with the same content always get the same [ObjectSha1], no matter
+/// which tool created them.
+///
+/// See <https://git-scm.com/book/en/v2/Git-Internals-Git-Objects#_object_storage>
+#[enum_dispatch]
+pub trait GitObject {
+    /// git type name of the object: `blob`, `tree`, `commit` or `tag`
+    fn object_type(&self) -> &'static str;
+
+    /// the content of the object, without the header. Fails with
+    /// [io::ErrorKind::InvalidData] if the object can not be encoded, like a
+    /// tree line which does not hold a valid sha1
+    fn content(&self) -> io::Result<Vec<u8>>;
+
+    /// the full encoding of the object, that is, the header followed by the
+    /// content. This is exactly what git hashes.
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let content = self.content()?;
+        let mut data = format!("{} {}\0", self.object_type(), content.len()).into_bytes();
+        data.extend(content);
+        Ok(data)
+    }
+}
+
+impl<T: GitObject> Sha1Able for T {
+    fn sha1(&self) -> String {
+        let mut hasher = sha1::Sha1::new();
+        // an object which can not be encoded can not be stored either, it
+        // gets the sha1 of nothing, which no stored object has
+        hasher.update(self.encode().unwrap_or_default());
+        base16ct::lower::encode_string(&hasher.finalize())
+    }
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Decode, Encode, Serialize, Deserialize, Hash,
+)]
 #[repr(transparent)]
 pub struct ObjectSha1(String);
 
 impl ObjectSha1 {
+    /// The fan-out directory and the file name of the loose object
     fn splited(&self) -> (&str, &str) {
-        (&self.0[0..2], &self.0[2..])
+        self.0.split_at_checked(2).unwrap_or((&self.0, ""))
+    }
+
+    /// If it is a full sha1 of 40 hex digits
+    pub fn is_valid(&self) -> bool {
+        self.0.len() == 40 && self.0.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    /// The raw 20 bytes of the sha1, as used in [Tree](super::tree::Tree)
+    /// entries
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        base16ct::mixed::decode_vec(&self.0).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a valid sha1: {e}", self.0),
+            )
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        ObjectSha1(base16ct::lower::encode_string(bytes))
     }
 }
 
@@ -71,21 +137,177 @@ impl Display for ObjectSha1 {
 /// hand you back a unique key you can use later to retrieve that content.
 ///
 /// See <https://git-scm.com/book/en/v2/Git-Internals-Git-Objects>
-#[derive(Debug, Clone, Decode, Encode)]
-#[enum_dispatch(Sha1Able)]
+#[derive(Debug, Clone)]
+#[enum_dispatch(GitObject)]
 pub enum Object {
     Blob(Blob),
     Tree(Tree),
     Commit(Commit),
+    Tag(Tag),
+}
+
+/// An object which is not decoded yet: its type and its content.
+///
+/// It is useful when the content should be kept byte by byte, such as when
+/// packing objects, or checking the sha1 of objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawObject {
+    pub object_type: &'static str,
+    pub content: Vec<u8>,
+}
+
+impl GitObject for RawObject {
+    fn object_type(&self) -> &'static str {
+        self.object_type
+    }
+
+    fn content(&self) -> io::Result<Vec<u8>> {
+        Ok(self.content.clone())
+    }
+}
+
+impl RawObject {
+    /// The supported object types, see [GitObject::object_type]
+    pub fn type_of(name: &str) -> Option<&'static str> {
+        ["blob", "tree", "commit", "tag"]
+            .into_iter()
+            .find(|t| *t == name)
+    }
+
+    /// Decode an object from its canonical git encoding, see
+    /// [GitObject::encode]
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let nul = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("object header is not terminated"))?;
+        let header = std::str::from_utf8(&data[..nul])
+            .map_err(|_| invalid("object header is not valid UTF-8"))?;
+        let (kind, len) = header
+            .split_once(' ')
+            .ok_or_else(|| invalid("object header has no length"))?;
+        let len: usize = len
+            .parse()
+            .map_err(|_| invalid("object length is not a number"))?;
+        let content = &data[nul + 1..];
+        if content.len() != len {
+            return Err(invalid("object length does not match its header"));
+        }
+
+        Ok(RawObject {
+            object_type: Self::type_of(kind)
+                .ok_or_else(|| invalid(&format!("unknown object type {kind}")))?,
+            content: content.to_vec(),
+        })
+    }
+
+    /// Load a zlib compressed loose object
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut data = Vec::new();
+        ZlibDecoder::new(fs::File::open(path)?).read_to_end(&mut data)?;
+        Self::decode(&data)
+    }
+}
+
+impl TryFrom<RawObject> for Object {
+    type Error = io::Error;
+
+    fn try_from(raw: RawObject) -> io::Result<Self> {
+        match raw.object_type {
+            "blob" => Ok(Object::Blob(Blob::from_content(&raw.content))),
+            "tree" => Ok(Object::Tree(Tree::from_content(&raw.content)?)),
+            "commit" => Ok(Object::Commit(Commit::from_content(&raw.content)?)),
+            "tag" => Ok(Object::Tag(Tag::from_content(&raw.content)?)),
+            other => unreachable!("unknown object type {other}"),
+        }
+    }
 }
 
 impl Object {
-    #[allow(dead_code)]
-    pub fn object_type(&self) -> &'static str {
-        match self {
-            Object::Blob(_) => "blob",
-            Object::Tree(_) => "tree",
-            Object::Commit(_) => "commit",
+    /// All loose objects in the `.git` dir `root`, with their paths
+    pub fn loose_objects(root: &Path) -> io::Result<Vec<(ObjectSha1, PathBuf)>> {
+        let mut objects = Vec::new();
+        for dir in fs::read_dir(root.join(Self::DIRECTORY))? {
+            let dir = dir?;
+            let dir_name = dir.file_name().to_string_lossy().to_string();
+            // skip `pack`, `info` and other dirs
+            if dir_name.len() != 2 || !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                let sha1 = format!("{dir_name}{}", file.file_name().to_string_lossy());
+                if sha1.len() == 40 && sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+                    objects.push((sha1.into(), file.path()));
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Resolve an abbreviated sha1 to the only object which starts with it,
+    /// looking up the fan-out directory of loose objects and all packs
+    pub fn resolve_prefix(root: &Path, prefix: &str) -> io::Result<ObjectSha1> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < MIN_ABBREV
+            || prefix.len() > 40
+            || !prefix.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a valid object name {prefix}"),
+            ));
+        }
+
+        let mut candidates = BTreeSet::new();
+        let (dir, rest) = prefix.split_at(2);
+        match fs::read_dir(root.join(Self::DIRECTORY).join(dir)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let name = entry?.file_name().to_string_lossy().to_string();
+                    if name.len() == 38 && name.starts_with(rest) {
+                        candidates.insert(ObjectSha1::from(format!("{dir}{name}")));
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        for pack in Pack::all(root)? {
+            candidates.extend(pack.index.find_prefix(&prefix));
+        }
+
+        let mut candidates = candidates.into_iter();
+        match (candidates.next(), candidates.len()) {
+            (Some(sha1), 0) => Ok(sha1),
+            (None, _) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("not a valid object name {prefix}"),
+            )),
+            (Some(first), _) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "short object ID {prefix} is ambiguous, the candidates are: {}",
+                    [first]
+                        .into_iter()
+                        .chain(candidates)
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+
+    /// Load an object without decoding it, from loose objects or packs
+    pub fn load_raw(root: &Path, sha1: &ObjectSha1) -> io::Result<RawObject> {
+        match RawObject::load(&root.join(Self::path_of(sha1))) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Pack::find_object(root, sha1)?.ok_or(e)
+            }
+            res => res,
         }
     }
 
@@ -127,22 +349,25 @@ impl Store for Object {
         let dir = sha1.chars().take(2).collect::<String>();
         Path::new(Self::DIRECTORY).join(dir).join(&sha1[2..])
     }
+    /// Objects are stored as zlib compressed loose objects, like git does
     fn store(&self, root: &std::path::Path) -> std::io::Result<()> {
-        let path = root.join(self.location());
+        let sha1 = ObjectSha1::from(self.sha1());
+        let path = root.join(Self::path_of(&sha1));
+        // objects are immutable, the same sha1 always has the same content
+        if path.exists() || Pack::contains(root, &sha1)? {
+            return Ok(());
+        }
+        let data = self.encode()?;
         if let Some(parent) = path.parent() {
             // Safely ignores the error if the directory already exists
             let _ = std::fs::create_dir_all(parent);
         }
-        let mut dst = fs::File::create(path)?;
-        bincode::encode_into_std_write(self, &mut dst, bincode::config::standard())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        Ok(())
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        fs::write(path, encoder.finish()?)
     }
     fn load(path: &std::path::Path) -> std::io::Result<Self> {
-        let mut src = fs::File::open(path)?;
-        let item = bincode::decode_from_std_read(&mut src, bincode::config::standard())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        Ok(item)
+        RawObject::load(path)?.try_into()
     }
 }
 
@@ -151,6 +376,15 @@ impl Accessible<ObjectSha1> for Object {
         let (car, cdr) = by.splited();
         Path::new(Self::DIRECTORY).join(car).join(cdr)
     }
+
+    /// Objects may be loose objects, be packed in packfiles, or only exist in
+    /// memory, see [Repository::keep_unsaved]
+    fn load_from(repo: &Repository, by: &ObjectSha1) -> io::Result<Self> {
+        if let Some(object) = repo.load_unsaved(by) {
+            return Ok(object);
+        }
+        Self::load_raw(&repo.root, by)?.try_into()
+    }
 }
 
 impl Display for Object {
@@ -159,25 +393,37 @@ impl Display for Object {
             Object::Blob(blob) => write!(f, "{blob}"),
             Object::Tree(tree) => write!(f, "{tree}"),
             Object::Commit(commit) => write!(f, "{commit}"),
+            Object::Tag(tag) => write!(f, "{tag}"),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::models::{object::Sha1Able, tree::TreeLine, tree::TreeLineKind};
+    use crate::models::{
+        object::{GitObject, Sha1Able},
+        tree::TreeLine,
+        tree::TreeLineKind,
+    };
+
+    use super::{Blob, Commit, Object, ObjectSha1, RawObject, Store, Tree};
+    use crate::models::commit::Signature;
 
-    use super::{Blob, Commit, Object, Tree};
+    fn signature() -> Signature {
+        Signature::parse("A U Thor <author@example.com> 1700000000 +0800").unwrap()
+    }
 
     #[test]
     fn object_sha1_should_eq_inner_sha1() {
+        let signature = signature();
         let blob = Blob::from("hello world");
         assert_eq!(blob.sha1(), Object::from(blob).sha1());
 
         let commit = Commit {
             tree: "tree_hash".into(),
-            parent: Some("parent_hash".into()),
-            timestamp: (0, 0),
+            parents: vec!["parent_hash".into()],
+            author: signature.clone(),
+            committer: signature,
             message: "commit message".to_string(),
         };
         assert_eq!(commit.sha1(), Object::from(commit).sha1());
@@ -186,9 +432,67 @@ mod test {
             objects: vec![TreeLine {
                 kind: TreeLineKind::File,
                 name: "file.txt".to_string(),
-                sha1: "file_sha1".into(),
+                sha1: "95d09f2b10159347eece71399a7e2e907ea3df4f".into(),
             }],
         };
         assert_eq!(tree.sha1(), Object::from(tree).sha1());
     }
+
+    #[test]
+    fn decode_should_revert_encode() {
+        let signature = signature();
+        let objects = [
+            Object::from(Blob::from("hello world")),
+            Object::from(Blob::from(vec![0xff, 0xfe, 0x00])),
+            Object::from(Tree {
+                objects: vec![TreeLine {
+                    kind: TreeLineKind::Tree,
+                    name: "src".to_string(),
+                    sha1: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".into(),
+                }],
+            }),
+            Object::from(Commit {
+                tree: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".into(),
+                parents: vec![],
+                author: signature.clone(),
+                committer: signature,
+                message: "init\n".to_string(),
+            }),
+        ];
+        for object in objects {
+            let raw = RawObject::decode(&object.encode().unwrap()).unwrap();
+            let decoded = Object::try_from(raw).unwrap();
+            assert_eq!(decoded.sha1(), object.sha1());
+        }
+    }
+
+    #[test]
+    fn resolve_prefix_should_find_the_only_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        // store blobs until two of them share the same 4 digits prefix
+        let mut seen = std::collections::HashMap::new();
+        let (a, b) = (0..)
+            .find_map(|i| {
+                let blob = Object::from(Blob::from(format!("blob {i}").as_str()));
+                blob.store(root).unwrap();
+                let sha1 = blob.sha1();
+                seen.insert(sha1[..4].to_string(), sha1.clone())
+                    .map(|other| (other, sha1))
+            })
+            .unwrap();
+
+        assert_eq!(*Object::resolve_prefix(root, &a[..12]).unwrap(), a);
+        assert_eq!(*Object::resolve_prefix(root, &b.to_uppercase()).unwrap(), b);
+        let ambiguous = Object::resolve_prefix(root, &a[..4]).unwrap_err();
+        assert!(ambiguous.to_string().contains(&a) && ambiguous.to_string().contains(&b));
+        assert!(Object::resolve_prefix(root, &a[..3]).is_err());
+        assert!(Object::resolve_prefix(root, "not-hex").is_err());
+        assert_eq!(
+            Object::resolve_prefix(root, "0000000").unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert_eq!(ObjectSha1::from("a").splited(), ("a", ""));
+    }
 }
@@ -0,0 +1,514 @@
+//! Packfiles and pack indexes.
+//!
+//! Storing every object as a loose file wastes a lot of space and inodes, so
+//! git packs objects into a single `objects/pack/pack-{sha1}.pack` file, where
+//! an object may be stored as a [delta](super::delta) of another object. The
+//! `.idx` file next to it maps the sha1 of each object to its offset in the
+//! pack.
+//!
+//! See <https://git-scm.com/docs/pack-format>
+
+use super::{
+    Accessible, DirContainer, delta,
+    object::{Object, ObjectSha1, RawObject, Sha1Able},
+};
+use flate2::{Compression, Crc, bufread::ZlibDecoder, write::ZlibEncoder};
+use sha1::Digest;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// The longest chain of deltas, like git's default `pack.depth`. Deltas of a
+/// malformed pack may point to themselves, so deeper chains are not read
+pub const MAX_DELTA_DEPTH: usize = 50;
+
+/// Objects are never inflated to more than their size, but the size comes
+/// from the pack, so it is not trusted to allocate more than this up front
+const MAX_PREALLOCATED: usize = 1 << 20;
+
+fn type_code(object_type: &str) -> u8 {
+    match object_type {
+        "commit" => OBJ_COMMIT,
+        "tree" => OBJ_TREE,
+        "blob" => OBJ_BLOB,
+        "tag" => OBJ_TAG,
+        other => unreachable!("unknown object type {other}"),
+    }
+}
+
+fn type_name(code: u8) -> io::Result<&'static str> {
+    match code {
+        OBJ_COMMIT => Ok("commit"),
+        OBJ_TREE => Ok("tree"),
+        OBJ_BLOB => Ok("blob"),
+        OBJ_TAG => Ok("tag"),
+        _ => Err(invalid(&format!("unknown packed object type {code}"))),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| invalid("pack index is truncated"))?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// The `.idx` file of a pack (version 2)
+#[derive(Debug)]
+pub struct PackIndex {
+    /// raw sha1s of objects, sorted
+    sha1s: Vec<[u8; 20]>,
+    /// offsets of objects in the pack, in the same order of `sha1s`
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    const MAGIC: &[u8] = b"\xfftOc";
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        if data.get(0..4) != Some(Self::MAGIC) || read_u32(&data, 4)? != 2 {
+            return Err(invalid(&format!(
+                "{} is not a version 2 pack index",
+                path.display()
+            )));
+        }
+
+        let count = read_u32(&data, 8 + 255 * 4)? as usize;
+        let sha1_start = 8 + 256 * 4;
+        let crc_start = sha1_start + count * 20;
+        let offset_start = crc_start + count * 4;
+        let large_offset_start = offset_start + count * 4;
+
+        let sha1s = (0..count)
+            .map(|i| {
+                let start = sha1_start + i * 20;
+                data.get(start..start + 20)
+                    .map(|s| s.try_into().unwrap())
+                    .ok_or_else(|| invalid("pack index is truncated"))
+            })
+            .collect::<io::Result<Vec<[u8; 20]>>>()?;
+        let offsets = (0..count)
+            .map(|i| {
+                let offset = read_u32(&data, offset_start + i * 4)?;
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(offset as u64);
+                }
+                let at = large_offset_start + (offset & 0x7fff_ffff) as usize * 8;
+                let bytes = data
+                    .get(at..at + 8)
+                    .ok_or_else(|| invalid("pack index is truncated"))?;
+                Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+            })
+            .collect::<io::Result<Vec<u64>>>()?;
+
+        Ok(PackIndex { sha1s, offsets })
+    }
+
+    /// offset of the object in the pack
+    pub fn find(&self, sha1: &[u8]) -> Option<u64> {
+        self.sha1s
+            .binary_search_by(|s| s.as_slice().cmp(sha1))
+            .ok()
+            .map(|i| self.offsets[i])
+    }
+
+    /// sha1s of objects in the pack which start with the lowercase hex
+    /// `prefix`
+    pub fn find_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = ObjectSha1> + 'a {
+        // sha1s are sorted, so binary search the whole bytes of the prefix
+        let bytes =
+            base16ct::lower::decode_vec(&prefix[..prefix.len() / 2 * 2]).unwrap_or_default();
+        let start = self
+            .sha1s
+            .partition_point(|s| &s[..bytes.len()] < bytes.as_slice());
+        self.sha1s[start..]
+            .iter()
+            .take_while(move |s| s.starts_with(&bytes))
+            .map(|s| ObjectSha1::from_bytes(s))
+            .filter(move |s| s.starts_with(prefix))
+    }
+
+    /// sha1s of all objects in the pack
+    pub fn sha1s(&self) -> impl Iterator<Item = ObjectSha1> + '_ {
+        self.sha1s.iter().map(|s| ObjectSha1::from_bytes(s))
+    }
+}
+
+/// A packfile with its index
+#[derive(Debug)]
+pub struct Pack {
+    /// path of the `.pack` file
+    pub path: PathBuf,
+    pub index: PackIndex,
+}
+
+/// Pack indexes are expensive to parse, and packs never change once written,
+/// so they are cached by the path.
+static PACK_CACHE: LazyLock<Mutex<HashMap<PathBuf, Arc<Pack>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The packs of each `.git` dir, as they were last listed by [Pack::all]
+static PACK_LISTS: LazyLock<Mutex<HashMap<PathBuf, Vec<Arc<Pack>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+impl DirContainer for Pack {
+    const DIRECTORY: &'static str = "objects/pack";
+}
+
+impl Pack {
+    /// All packs in the `.git` dir `root`
+    pub fn all(root: &Path) -> io::Result<Vec<Arc<Pack>>> {
+        let entries = match fs::read_dir(root.join(Self::DIRECTORY)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut packs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "pack") {
+                continue;
+            }
+            let mut cache = PACK_CACHE.lock().unwrap();
+            if let Some(pack) = cache.get(&path) {
+                packs.push(pack.clone());
+                continue;
+            }
+            let index = match PackIndex::load(&path.with_extension("idx")) {
+                Ok(index) => index,
+                // the pack is being written, or the index is missing
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let pack = Arc::new(Pack {
+                path: path.clone(),
+                index,
+            });
+            cache.insert(path, pack.clone());
+            packs.push(pack);
+        }
+        PACK_LISTS
+            .lock()
+            .unwrap()
+            .insert(root.to_path_buf(), packs.clone());
+        Ok(packs)
+    }
+
+    /// The packs of the `.git` dir `root` as they were last listed, without
+    /// reading the pack directory again
+    fn listed(root: &Path) -> io::Result<Vec<Arc<Pack>>> {
+        if let Some(packs) = PACK_LISTS.lock().unwrap().get(root) {
+            return Ok(packs.clone());
+        }
+        Self::all(root)
+    }
+
+    /// Find an object in all packs of the `.git` dir `root`
+    pub fn find_object(root: &Path, sha1: &ObjectSha1) -> io::Result<Option<RawObject>> {
+        let bytes = sha1.to_bytes()?;
+        let find = |packs: Vec<Arc<Pack>>| -> io::Result<Option<RawObject>> {
+            for pack in packs {
+                if let Some(offset) = pack.index.find(&bytes) {
+                    return pack.read(root, offset).map(Some);
+                }
+            }
+            Ok(None)
+        };
+        // packs may be written or removed since they were listed
+        match find(Self::listed(root)?) {
+            Ok(None) => find(Self::all(root)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => find(Self::all(root)?),
+            res => res,
+        }
+    }
+
+    /// If any pack of the `.git` dir `root` contains the object. The packs are
+    /// not listed again, so a pack written since the last listing is missed
+    pub fn contains(root: &Path, sha1: &ObjectSha1) -> io::Result<bool> {
+        let bytes = sha1.to_bytes()?;
+        Ok(Self::listed(root)?
+            .iter()
+            .any(|pack| pack.index.find(&bytes).is_some()))
+    }
+
+    /// Read the object at `offset`, resolving deltas
+    pub fn read(&self, root: &Path, offset: u64) -> io::Result<RawObject> {
+        self.read_delta(root, offset, 0)
+    }
+
+    /// Read the object at `offset`, which is the base of `depth` deltas
+    fn read_delta(&self, root: &Path, offset: u64, depth: usize) -> io::Result<RawObject> {
+        if depth > MAX_DELTA_DEPTH {
+            return Err(invalid("delta chain is too deep"));
+        }
+        let mut file = BufReader::new(fs::File::open(&self.path)?);
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut byte = [0u8];
+        file.read_exact(&mut byte)?;
+        let code = (byte[0] >> 4) & 0x07;
+        let mut size = (byte[0] & 0x0f) as usize;
+        let mut shift = 4;
+        while byte[0] & 0x80 != 0 {
+            file.read_exact(&mut byte)?;
+            size |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let base = match code {
+            OBJ_OFS_DELTA => {
+                file.read_exact(&mut byte)?;
+                let mut distance = (byte[0] & 0x7f) as u64;
+                while byte[0] & 0x80 != 0 {
+                    file.read_exact(&mut byte)?;
+                    distance = ((distance + 1) << 7) | (byte[0] & 0x7f) as u64;
+                }
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| invalid("delta base is out of the pack"))?;
+                Some(self.read_delta(root, base_offset, depth + 1)?)
+            }
+            OBJ_REF_DELTA => {
+                let mut sha1 = [0u8; 20];
+                file.read_exact(&mut sha1)?;
+                Some(match self.index.find(&sha1) {
+                    Some(base_offset) => self.read_delta(root, base_offset, depth + 1)?,
+                    // the base of a thin pack is a loose object. Other packs
+                    // are not searched, they may have deltas of this one
+                    None => {
+                        let base = ObjectSha1::from_bytes(&sha1);
+                        RawObject::load(&root.join(Object::path_of(&base)))?
+                    }
+                })
+            }
+            _ => None,
+        };
+
+        let data = inflate(&mut file, size)?;
+        match base {
+            Some(base) => Ok(RawObject {
+                object_type: base.object_type,
+                content: delta::apply(&base.content, &data)?,
+            }),
+            None => Ok(RawObject {
+                object_type: type_name(code)?,
+                content: data,
+            }),
+        }
+    }
+}
+
+fn inflate(src: &mut impl BufRead, size: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATED));
+    // one more byte than the size is enough to know that it does not match
+    ZlibDecoder::new(src)
+        .take(size as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(invalid("packed object size does not match"));
+    }
+    Ok(data)
+}
+
+/// An object to write into a pack
+pub enum PackEntry {
+    /// The object is stored as is
+    Full(RawObject),
+    /// The object is stored as a delta of the `base`-th entry of the pack,
+    /// which must be written before this one
+    Delta {
+        sha1: ObjectSha1,
+        base: usize,
+        delta: Vec<u8>,
+    },
+}
+
+/// Write the entries into a new pack with its index in the `.git` dir `root`,
+/// returns the sha1 of the pack.
+pub fn write_pack(root: &Path, entries: &[PackEntry]) -> io::Result<String> {
+    let mut data = Vec::new();
+    data.extend(b"PACK");
+    data.extend(2u32.to_be_bytes());
+    data.extend((entries.len() as u32).to_be_bytes());
+
+    // (sha1, crc32, offset) of entries
+    let mut written: Vec<(Vec<u8>, u32, u64)> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let offset = data.len() as u64;
+        let (code, size, sha1, body) = match entry {
+            PackEntry::Full(raw) => (
+                type_code(raw.object_type),
+                raw.content.len(),
+                ObjectSha1::from(raw.sha1()),
+                &raw.content,
+            ),
+            PackEntry::Delta { sha1, delta, .. } => {
+                (OBJ_OFS_DELTA, delta.len(), sha1.clone(), delta)
+            }
+        };
+
+        let mut header = Vec::new();
+        let mut byte = (code << 4) | (size & 0x0f) as u8;
+        let mut rest = size >> 4;
+        while rest != 0 {
+            header.push(byte | 0x80);
+            byte = (rest & 0x7f) as u8;
+            rest >>= 7;
+        }
+        header.push(byte);
+
+        if let PackEntry::Delta { base, .. } = entry {
+            let mut distance = offset - written[*base].2;
+            let mut encoded = vec![(distance & 0x7f) as u8];
+            distance >>= 7;
+            while distance != 0 {
+                distance -= 1;
+                encoded.push(0x80 | (distance & 0x7f) as u8);
+                distance >>= 7;
+            }
+            encoded.reverse();
+            header.extend(encoded);
+        }
+
+        let mut encoder = ZlibEncoder::new(header, Compression::default());
+        encoder.write_all(body)?;
+        let packed = encoder.finish()?;
+
+        let mut crc = Crc::new();
+        crc.update(&packed);
+        data.extend(&packed);
+        written.push((sha1.to_bytes()?, crc.sum(), offset));
+    }
+    let checksum = sha1::Sha1::digest(&data);
+    data.extend(checksum);
+
+    written.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut index = Vec::new();
+    index.extend(PackIndex::MAGIC);
+    index.extend(2u32.to_be_bytes());
+    for first in 0..=255u8 {
+        let count = written.iter().take_while(|w| w.0[0] <= first).count();
+        index.extend((count as u32).to_be_bytes());
+    }
+    for (sha1, _, _) in &written {
+        index.extend(sha1);
+    }
+    for (_, crc, _) in &written {
+        index.extend(crc.to_be_bytes());
+    }
+    let mut large_offsets = Vec::new();
+    for (_, _, offset) in &written {
+        if *offset < 0x8000_0000 {
+            index.extend((*offset as u32).to_be_bytes());
+        } else {
+            index.extend((0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        index.extend(offset.to_be_bytes());
+    }
+    index.extend(checksum);
+    let index_checksum = sha1::Sha1::digest(&index);
+    index.extend(index_checksum);
+
+    let name = base16ct::lower::encode_string(&checksum);
+    Pack::make_dir(root)?;
+    let path = root.join(Pack::DIRECTORY).join(format!("pack-{name}.pack"));
+    fs::write(&path, data)?;
+    // the index is written at last, so a pack without index is never read
+    fs::write(path.with_extension("idx"), index)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pack of a blob and a chain of `deltas` deltas on it
+    fn write_chain(root: &Path, deltas: usize) -> (String, ObjectSha1) {
+        let mut content = b"the base of all deltas\n".repeat(8);
+        let mut entries = vec![PackEntry::Full(RawObject {
+            object_type: "blob",
+            content: content.clone(),
+        })];
+        for i in 0..deltas {
+            let target = [content.as_slice(), format!("line {i}\n").as_bytes()].concat();
+            entries.push(PackEntry::Delta {
+                sha1: RawObject {
+                    object_type: "blob",
+                    content: target.clone(),
+                }
+                .sha1()
+                .into(),
+                base: i,
+                delta: delta::create(&content, &target),
+            });
+            content = target;
+        }
+        let sha1 = RawObject {
+            object_type: "blob",
+            content,
+        }
+        .sha1()
+        .into();
+        (write_pack(root, &entries).unwrap(), sha1)
+    }
+
+    #[test]
+    fn test_delta_chain_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, sha1) = write_chain(dir.path(), MAX_DELTA_DEPTH);
+        let raw = Pack::find_object(dir.path(), &sha1).unwrap().unwrap();
+        assert!(
+            raw.content
+                .ends_with(format!("line {}\n", MAX_DELTA_DEPTH - 1).as_bytes())
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let (_, sha1) = write_chain(dir.path(), MAX_DELTA_DEPTH + 1);
+        let err = Pack::find_object(dir.path(), &sha1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_delta_to_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let (name, sha1) = write_chain(dir.path(), 1);
+        let path = dir
+            .path()
+            .join(Pack::DIRECTORY)
+            .join(format!("pack-{name}.pack"));
+        let pack = Pack::all(dir.path()).unwrap().pop().unwrap();
+        let offset = pack.index.find(&sha1.to_bytes().unwrap()).unwrap();
+
+        // point the delta to its own offset, a distance of 0
+        let mut data = fs::read(&path).unwrap();
+        let mut at = offset as usize;
+        while data[at] & 0x80 != 0 {
+            at += 1;
+        }
+        data[at + 1] = 0;
+        fs::write(&path, data).unwrap();
+
+        let err = pack.read(dir.path(), offset).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,114 @@
+//! State of a `rebase` stopped by conflicts
+
+use super::{
+    Store,
+    object::ObjectSha1,
+    sequencer::{Action, format_todo, parse_todo},
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// An unfinished rebase. Like git, it is stored in the `rebase-merge` dir:
+///
+/// - `head-name`, the branch being rebased
+/// - `orig-head`, the commit the branch was at before, restored by `--abort`
+/// - `onto`, the commit the branch is rebased onto
+/// - `git-rebase-todo`, the commits to replay, the first one is stopped by
+///   conflicts, see [parse_todo]
+/// - `message`, the message of the commit of the stopped one
+///
+/// While rebasing, the branch is moved to each replayed commit. The
+/// conflicted paths are recorded in the [Stage](super::stage::Stage)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebaseState {
+    pub head_name: String,
+    pub orig_head: ObjectSha1,
+    pub onto: ObjectSha1,
+    pub todo: Vec<(Action, ObjectSha1)>,
+    pub message: String,
+}
+
+impl RebaseState {
+    pub const LOCATION: &str = "rebase-merge";
+}
+
+impl Store for RebaseState {
+    fn location(&self) -> PathBuf {
+        Path::new(Self::LOCATION).to_path_buf()
+    }
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        let dir = root.join(Self::LOCATION);
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join("head-name"),
+            format!("refs/heads/{}\n", self.head_name),
+        )?;
+        fs::write(dir.join("orig-head"), format!("{}\n", self.orig_head))?;
+        fs::write(dir.join("onto"), format!("{}\n", self.onto))?;
+        fs::write(dir.join("git-rebase-todo"), format_todo(&self.todo))?;
+        fs::write(dir.join("message"), &self.message)
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        let read = |name: &str| fs::read_to_string(path.join(name));
+        let head_name = read("head-name")?;
+        let todo = parse_todo(&read("git-rebase-todo")?)?
+            .into_iter()
+            .map(|(action, sha1)| (action, ObjectSha1::from(sha1)))
+            .collect();
+        Ok(RebaseState {
+            head_name: head_name
+                .trim()
+                .trim_start_matches("refs/heads/")
+                .to_string(),
+            orig_head: ObjectSha1::from(read("orig-head")?.trim()),
+            onto: ObjectSha1::from(read("onto")?.trim()),
+            todo,
+            message: read("message")?,
+        })
+    }
+
+    fn delete(&self, root: &Path) -> io::Result<()> {
+        fs::remove_dir_all(root.join(Self::LOCATION))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebase_state_should_be_loaded_as_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        let sha1 = |c: char| ObjectSha1::from(c.to_string().repeat(40).as_str());
+        let state = RebaseState {
+            head_name: "feature/x".to_string(),
+            orig_head: sha1('a'),
+            onto: sha1('b'),
+            todo: vec![
+                (Action::Pick, sha1('c')),
+                (Action::Reword, sha1('d')),
+                (Action::Squash, sha1('e')),
+                (Action::Fixup, sha1('f')),
+                (Action::Drop, sha1('0')),
+            ],
+            message: "stopped\n\nwith a body\n".to_string(),
+        };
+        state.store(root).unwrap();
+        let todo = fs::read_to_string(root.join(RebaseState::LOCATION).join("git-rebase-todo"));
+        assert!(
+            todo.unwrap()
+                .starts_with(&format!("pick {}\nreword ", sha1('c')))
+        );
+        let loaded = RebaseState::load(&root.join(RebaseState::LOCATION)).unwrap();
+        assert_eq!(loaded, state);
+
+        state.delete(root).unwrap();
+        assert!(!root.join(RebaseState::LOCATION).exists());
+    }
+}
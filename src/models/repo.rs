@@ -1,10 +1,18 @@
 //! Repository, the entry of everything
 
 use super::ignores::Ignores;
+use super::merge_state::MergeState;
+use super::object::{Object, ObjectSha1, Sha1Able};
+use super::rebase::RebaseState;
+use super::sequencer::Sequencer;
 use super::stage::Stage;
+use super::stash::StashList;
+use super::tree::{Tree, TreeLineKind};
 use super::{branch, head, object};
 use crate::models::{Accessible, Accessor, DirContainer};
 use crate::{models::Store, models::head::Head};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
@@ -24,6 +32,10 @@ pub struct Repository {
     pub root: PathBuf,
     pub ignores: Ignores,
     head_: Head,
+    /// Objects which are hashed but not saved, such as the trees of the
+    /// working directory. They can be loaded like saved objects, but
+    /// disappear with the repository structure.
+    unsaved: RefCell<HashMap<ObjectSha1, Object>>,
 }
 
 /// A wrapper, like a [Box] but not dynamic, for any object to store the
@@ -147,7 +159,7 @@ where
 {
     /// Load the storeable object from disk
     pub fn load(&self) -> io::Result<WithRepo<'r, T>> {
-        let inner = T::load(&self.repo.root.join(self.inner.path()))?;
+        let inner = T::load_from(self.repo, self.inner.by)?;
         Ok(WithRepo {
             repo: self.repo,
             inner,
@@ -224,7 +236,7 @@ impl From<RepositoryInitError> for anyhow::Error {
 }
 
 impl Repository {
-    pub fn wrap<T>(&self, inner: T) -> WithRepo<T> {
+    pub fn wrap<T>(&self, inner: T) -> WithRepo<'_, T> {
         WithRepo { repo: self, inner }
     }
 
@@ -241,9 +253,24 @@ impl Repository {
             .expect(".git directory should never be the root")
     }
 
+    /// The path relative to the working directory, with `/` as separators
+    pub fn relative_path(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(self.working_dir()).unwrap_or(path);
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
     /// Load the repository form .git folder
     pub fn load() -> Result<Self, RepositoryInitError> {
-        let path = Self::find_root().join(Self::DIRECTORY);
+        Self::load_at(&Self::find_root())
+    }
+
+    /// Load the repository of the working directory `dir`
+    pub fn load_at(dir: &Path) -> Result<Self, RepositoryInitError> {
+        let path = dir.join(Self::DIRECTORY);
         let _ = fs::read_dir(&path)?;
 
         branch::Branch::check_dir_exists(&path);
@@ -255,14 +282,20 @@ impl Repository {
             ignores: Ignores::load(&path)?,
             root: path,
             head_: head,
+            unsaved: RefCell::new(HashMap::new()),
         })
     }
 
     /// Initialize the repository
     pub fn init() -> Result<Self, RepositoryInitError> {
-        let path = Self::find_root().join(Self::DIRECTORY);
+        Self::init_at(&Self::find_root())
+    }
+
+    /// Initialize the repository in the working directory `dir`
+    pub fn init_at(dir: &Path) -> Result<Self, RepositoryInitError> {
+        let path = dir.join(Self::DIRECTORY);
 
-        Self::make_dir(&Self::find_root())?;
+        Self::make_dir(dir)?;
         branch::Branch::make_dir(&path)?;
         object::Object::make_dir(&path)?;
 
@@ -272,11 +305,11 @@ impl Repository {
         };
         head.store(&path)?;
 
-        Self::load()
+        Self::load_at(dir)
     }
 
     /// get the head of the repository
-    pub fn head(&self) -> WithRepo<&Head> {
+    pub fn head(&self) -> WithRepo<'_, &Head> {
         self.wrap(&self.head_)
     }
 
@@ -288,13 +321,86 @@ impl Repository {
         self.head_.store(&self.root)
     }
 
-    /// get the staging index of the repository
-    pub fn stage(&self) -> io::Result<WithRepo<Stage>> {
+    /// Keep the object in memory instead of saving it to disk, so that it can
+    /// still be loaded until the repository is dropped
+    pub fn keep_unsaved(&self, object: Object) {
+        self.unsaved
+            .borrow_mut()
+            .insert(object.sha1().into(), object);
+    }
+
+    /// Load an object kept by [Repository::keep_unsaved]
+    pub fn load_unsaved(&self, sha1: &ObjectSha1) -> Option<Object> {
+        self.unsaved.borrow().get(sha1).cloned()
+    }
+
+    /// Save the tree, and its sub trees which are only kept in memory, such
+    /// as those of the stage, so that the tree can be committed
+    pub fn save_tree(&self, tree: &Tree) -> io::Result<()> {
+        for line in &tree.objects {
+            if line.kind != TreeLineKind::Tree {
+                continue;
+            }
+            if let Some(Object::Tree(subtree)) = self.load_unsaved(&line.sha1) {
+                self.save_tree(&subtree)?;
+            }
+        }
+        Object::Tree(tree.clone()).store(&self.root)
+    }
+
+    /// get the staging index of the repository, its sub trees are kept in
+    /// memory, see [Repository::keep_unsaved]
+    pub fn stage(&self) -> io::Result<WithRepo<'_, Stage>> {
         let stage_file = self.root.join(Stage::LOCATION);
         Ok(if stage_file.is_file() {
-            self.wrap(Stage::load(&stage_file)?)
+            let stage = Stage::load(&stage_file)?;
+            for subtree in stage.subtrees.values() {
+                self.keep_unsaved(Object::Tree(subtree.clone()));
+            }
+            self.wrap(stage)
         } else {
             self.wrap(Stage::empty())
         })
     }
+
+    /// get the entries of the stash
+    pub fn stash_list(&self) -> io::Result<WithRepo<'_, StashList>> {
+        let log = self.root.join(StashList::LOCATION);
+        Ok(if log.is_file() {
+            self.wrap(StashList::load(&log)?)
+        } else {
+            self.wrap(StashList::default())
+        })
+    }
+
+    /// get the state of the unfinished merge, if there is one
+    pub fn merge_state(&self) -> io::Result<Option<WithRepo<'_, MergeState>>> {
+        let merge_head = self.root.join(MergeState::LOCATION);
+        Ok(if merge_head.is_file() {
+            Some(self.wrap(MergeState::load(&merge_head)?))
+        } else {
+            None
+        })
+    }
+
+    /// get the state of the unfinished `cherry-pick` or `revert`, if there is
+    /// one
+    pub fn sequencer(&self) -> io::Result<Option<WithRepo<'_, Sequencer>>> {
+        let dir = self.root.join(Sequencer::LOCATION);
+        Ok(if dir.is_dir() {
+            Some(self.wrap(Sequencer::load(&dir)?))
+        } else {
+            None
+        })
+    }
+
+    /// get the state of the unfinished rebase, if there is one
+    pub fn rebase_state(&self) -> io::Result<Option<WithRepo<'_, RebaseState>>> {
+        let dir = self.root.join(RebaseState::LOCATION);
+        Ok(if dir.is_dir() {
+            Some(self.wrap(RebaseState::load(&dir)?))
+        } else {
+            None
+        })
+    }
 }
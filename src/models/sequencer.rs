@@ -0,0 +1,202 @@
+//! State of a `cherry-pick` or `revert` stopped by conflicts
+
+use super::{Store, object::ObjectSha1};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// What is done to a commit in a todo list, of the [Sequencer] or a rebase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// apply the changes of the commit
+    Pick,
+    /// apply the reverse of the changes of the commit
+    Revert,
+    /// pick the commit, and edit its message
+    Reword,
+    /// meld the commit into the previous one, and edit the combined message
+    Squash,
+    /// meld the commit into the previous one, keeping the previous message
+    Fixup,
+    /// leave the commit out
+    Drop,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+            Action::Reword => "reword",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+        }
+    }
+
+    /// The action of the name, or its one letter abbreviation
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pick" | "p" => Some(Action::Pick),
+            "revert" => Some(Action::Revert),
+            "reword" | "r" => Some(Action::Reword),
+            "squash" | "s" => Some(Action::Squash),
+            "fixup" | "f" => Some(Action::Fixup),
+            "drop" | "d" => Some(Action::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a todo list, lines of an action, a commit and optionally its
+/// subject, which is ignored. Empty lines and comments starting with `#` are
+/// skipped
+///
+/// ```txt
+/// pick 2d8a4e1 add the parser
+/// # a comment
+/// squash 7c1b0a9 fix the parser
+/// ```
+pub fn parse_todo(ctnt: &str) -> io::Result<Vec<(Action, String)>> {
+    let mut todo = Vec::new();
+    for line in ctnt.lines() {
+        let mut fields = line.split_whitespace();
+        let name = match fields.next() {
+            None => continue,
+            Some(name) if name.starts_with('#') => continue,
+            Some(name) => name,
+        };
+        let Some(action) = Action::from_name(name) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid command '{name}' in line '{line}'"),
+            ));
+        };
+        let Some(commit) = fields.next() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("missing commit in line '{line}'"),
+            ));
+        };
+        todo.push((action, commit.to_string()));
+    }
+    Ok(todo)
+}
+
+/// Format the todo list to be parsed by [parse_todo]
+pub fn format_todo(todo: &[(Action, ObjectSha1)]) -> String {
+    todo.iter()
+        .map(|(action, sha1)| format!("{} {sha1}\n", action.name()))
+        .collect()
+}
+
+/// An unfinished `cherry-pick` or `revert`. Like git, it is stored in the
+/// `sequencer` dir:
+///
+/// - `head`, the commit the branch was at before, restored by `--abort`
+/// - `todo`, the commits to apply, the first one is stopped by conflicts:
+///
+///   ```txt
+///   pick 2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f
+///   pick 7c1b0a9e8d7f6e5d4c3b2a1f0e9d8c7b6a5f4e3d
+///   ```
+///
+/// - `message`, the message of the commit of the stopped one
+///
+/// The conflicted paths are recorded in the [Stage](super::stage::Stage)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequencer {
+    pub head: ObjectSha1,
+    pub todo: Vec<(Action, ObjectSha1)>,
+    pub message: String,
+}
+
+impl Sequencer {
+    pub const LOCATION: &str = "sequencer";
+}
+
+impl Store for Sequencer {
+    fn location(&self) -> PathBuf {
+        Path::new(Self::LOCATION).to_path_buf()
+    }
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        let dir = root.join(Self::LOCATION);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("head"), format!("{}\n", self.head))?;
+        fs::write(dir.join("todo"), format_todo(&self.todo))?;
+        fs::write(dir.join("message"), &self.message)
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        let head = fs::read_to_string(path.join("head"))?;
+        let todo = parse_todo(&fs::read_to_string(path.join("todo"))?)?
+            .into_iter()
+            .map(|(action, sha1)| (action, ObjectSha1::from(sha1)))
+            .collect();
+        Ok(Sequencer {
+            head: ObjectSha1::from(head.trim()),
+            todo,
+            message: fs::read_to_string(path.join("message"))?,
+        })
+    }
+
+    fn delete(&self, root: &Path) -> io::Result<()> {
+        fs::remove_dir_all(root.join(Self::LOCATION))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_todo() {
+        let todo = parse_todo(
+            "pick 2d8a4e1 add the parser\n\
+             \n\
+             # a comment\n\
+             s 7c1b0a9 fix the parser\n\
+             drop 0e9d8c7\n",
+        )
+        .unwrap();
+        assert_eq!(
+            todo,
+            vec![
+                (Action::Pick, "2d8a4e1".to_string()),
+                (Action::Squash, "7c1b0a9".to_string()),
+                (Action::Drop, "0e9d8c7".to_string()),
+            ]
+        );
+        assert!(parse_todo("edit 2d8a4e1").is_err());
+        assert!(parse_todo("pick").is_err());
+    }
+
+    #[test]
+    fn sequencer_should_be_loaded_as_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        let sequencer = Sequencer {
+            head: ObjectSha1::from("2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f"),
+            todo: vec![
+                (
+                    Action::Revert,
+                    ObjectSha1::from("7c1b0a9e8d7f6e5d4c3b2a1f0e9d8c7b6a5f4e3d"),
+                ),
+                (
+                    Action::Pick,
+                    ObjectSha1::from("0e9d8c7b6a5f4e3d7c1b0a9e8d7f6e5d4c3b2a1f"),
+                ),
+            ],
+            message: "Revert \"a\"\n\nThis reverts commit 7c1b0a9.".to_string(),
+        };
+        sequencer.store(root).unwrap();
+        let loaded = Sequencer::load(&root.join(Sequencer::LOCATION)).unwrap();
+        assert_eq!(loaded, sequencer);
+
+        sequencer.delete(root).unwrap();
+        assert!(!root.join(Sequencer::LOCATION).exists());
+    }
+}
@@ -1,10 +1,14 @@
 //! Staging area of the repo. The stage files are used to store the changes that
 //! are not yet committed.
 
-use super::tree::Tree;
+use super::{
+    object::{Object, ObjectSha1, Sha1Able},
+    tree::{Tree, TreeLine, TreeLineKind},
+};
 use crate::models::Store;
-use bincode::{Decode, Encode};
+use sha1::Digest;
 use std::{
+    collections::{BTreeMap, HashMap},
     fmt::Display,
     fs, io,
     ops::Deref,
@@ -21,14 +25,48 @@ use std::{
 /// to insert a dumplicated line into the stage, which is not an expected
 /// behavior. If you want to modify the stage, take the Tree by call
 /// [crate::services::stage::StageService::into_muter]
-#[derive(Encode, Decode)]
-pub struct Stage(pub Tree);
+///
+/// # Storage
+///
+/// The stage is stored in the git index format (version 2), so git can read
+/// it. The index only records files with their full path, so the sub trees of
+/// the stage are built when the stage is loaded. They are only kept in
+/// memory, and saved to the object database when the tree is committed, see
+/// [crate::models::repo::Repository::save_tree]
+///
+/// During a merge with conflicts, the conflicted paths are recorded as
+/// [Unmerged] entries, at stage 1 (base), 2 (ours) and 3 (theirs) of the
+/// index. When the stage is loaded, the tree has the ours version of them, so
+/// that they are unchanged from `HEAD`.
+///
+/// See <https://git-scm.com/docs/index-format>
+pub struct Stage {
+    pub tree: Tree,
+    /// unmerged entries by their full path
+    pub unmerged: BTreeMap<String, Unmerged>,
+    /// sub trees built when the stage is loaded, which are not saved
+    pub subtrees: HashMap<ObjectSha1, Tree>,
+}
+
+/// Versions of a conflicted path in the base, ours and theirs, the version is
+/// `None` if the path does not exist in that side
+pub type Unmerged = [Option<TreeLine>; 3];
+
+impl From<Tree> for Stage {
+    fn from(tree: Tree) -> Self {
+        Stage {
+            tree,
+            unmerged: BTreeMap::new(),
+            subtrees: HashMap::new(),
+        }
+    }
+}
 
 impl Deref for Stage {
     type Target = Tree;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.tree
     }
 }
 
@@ -38,37 +76,304 @@ impl Store for Stage {
     }
 
     fn store(&self, root: &Path) -> io::Result<()> {
+        let mut entries = Vec::new();
+        flatten_tree(root, &self.subtrees, &self.tree, "", &mut entries)?;
+        // an unmerged path is only stored at the stages 1 to 3
+        entries.retain(|e| !self.unmerged.contains_key(&e.name));
+        let mut entries: Vec<_> = entries.into_iter().map(|e| (0u16, e)).collect();
+        for versions in self.unmerged.values() {
+            for (stage, line) in (1..).zip(versions) {
+                entries.extend(line.clone().map(|line| (stage, line)));
+            }
+        }
+        entries.sort_by(|(a_stage, a), (b_stage, b)| {
+            (a.name.as_bytes(), a_stage).cmp(&(b.name.as_bytes(), b_stage))
+        });
+
+        let mut data = Vec::new();
+        data.extend(b"DIRC");
+        data.extend(2u32.to_be_bytes());
+        data.extend((entries.len() as u32).to_be_bytes());
+        for (stage, entry) in entries {
+            let start = data.len();
+            // ctime, mtime, dev, ino: we don't track stat data, and git will
+            // compare the content of the file when they don't match
+            data.extend([0u8; 24]);
+            data.extend(entry_mode(&entry.kind).to_be_bytes());
+            // uid, gid, size
+            data.extend([0u8; 12]);
+            data.extend(entry.sha1.to_bytes()?);
+            data.extend((stage << 12 | entry.name.len().min(0xfff) as u16).to_be_bytes());
+            data.extend(entry.name.as_bytes());
+            // 1 to 8 nul bytes to pad the entry to a multiple of 8 bytes
+            let len = data.len() - start;
+            data.resize(start + (len + 8) / 8 * 8, 0);
+        }
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(&data);
+        data.extend(hasher.finalize());
+
         let path = root.join(Self::LOCATION);
         if let Some(parent) = path.parent() {
             // Safely ignores the error if the directory already exists
             let _ = std::fs::create_dir_all(parent);
         }
-        let mut dst = fs::File::create(path)?;
-        bincode::encode_into_std_write(self, &mut dst, bincode::config::standard())
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(())
+        fs::write(path, data)
     }
 
     fn load(path: &Path) -> io::Result<Self> {
-        let mut src = fs::File::open(path)?;
-        let item = bincode::decode_from_std_read(&mut src, bincode::config::standard())
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(item)
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let data = fs::read(path)?;
+
+        if data.len() < 32 || &data[0..4] != b"DIRC" {
+            return Err(invalid("index file has a bad signature"));
+        }
+        let (body, checksum) = data.split_at(data.len() - 20);
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(body);
+        if hasher.finalize().as_slice() != checksum {
+            return Err(invalid("index file is corrupted"));
+        }
+        let version = read_u32(body, 4);
+        if version != 2 && version != 3 {
+            return Err(invalid(&format!(
+                "index version {version} is not supported"
+            )));
+        }
+
+        let count = read_u32(body, 8);
+        let mut offset = 12;
+        let mut entries = Vec::new();
+        let mut unmerged = BTreeMap::<String, Unmerged>::new();
+        for _ in 0..count {
+            if offset + 62 > body.len() {
+                return Err(invalid("index entry is truncated"));
+            }
+            let mode = read_u32(body, offset + 24);
+            let sha1 = ObjectSha1::from_bytes(&body[offset + 40..offset + 60]);
+            let flags = u16::from_be_bytes([body[offset + 60], body[offset + 61]]);
+            let mut name_start = offset + 62;
+            if version == 3 && flags & 0x4000 != 0 {
+                // extended flags
+                name_start += 2;
+            }
+            let name_len = body[name_start..]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| invalid("index entry name is not terminated"))?;
+            let name = String::from_utf8(body[name_start..name_start + name_len].to_vec())
+                .map_err(|_| invalid("index entry name is not valid UTF-8"))?;
+            let len = name_start - offset + name_len;
+            offset += (len + 8) / 8 * 8;
+
+            let kind = match mode {
+                0o100644 => TreeLineKind::File,
+                0o100755 => TreeLineKind::Executable,
+                0o120000 => TreeLineKind::Symlink,
+                _ => {
+                    return Err(invalid(&format!(
+                        "index entry mode {mode:o} is not supported"
+                    )));
+                }
+            };
+            // merge stage of the entry, 0 for normal entries
+            match (flags >> 12) & 0x3 {
+                0 => entries.push(TreeLine { kind, name, sha1 }),
+                stage => {
+                    let line = TreeLine {
+                        kind,
+                        name: name.clone(),
+                        sha1,
+                    };
+                    if stage == 2 {
+                        entries.push(line.clone());
+                    }
+                    unmerged.entry(name).or_default()[stage as usize - 1] = Some(line);
+                }
+            }
+        }
+
+        let mut subtrees = HashMap::new();
+        Ok(Stage {
+            tree: build_tree(entries, &mut subtrees),
+            unmerged,
+            subtrees,
+        })
     }
 }
 
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn entry_mode(kind: &TreeLineKind) -> u32 {
+    match kind {
+        TreeLineKind::File => 0o100644,
+        TreeLineKind::Executable => 0o100755,
+        TreeLineKind::Symlink => 0o120000,
+        TreeLineKind::Tree => 0o040000,
+    }
+}
+
+/// Collect all non-tree lines of the tree, named by their full path. Sub trees
+/// are taken from `subtrees` or the object database
+fn flatten_tree(
+    root: &Path,
+    subtrees: &HashMap<ObjectSha1, Tree>,
+    tree: &Tree,
+    prefix: &str,
+    res: &mut Vec<TreeLine>,
+) -> io::Result<()> {
+    for line in &tree.objects {
+        let name = format!("{prefix}{}", line.name);
+        if line.kind == TreeLineKind::Tree {
+            let subtree = match subtrees.get(&line.sha1) {
+                Some(subtree) => subtree.clone(),
+                None => match Object::load_raw(root, &line.sha1)?.try_into()? {
+                    Object::Tree(subtree) => subtree,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} is not a tree", line.sha1),
+                        ));
+                    }
+                },
+            };
+            flatten_tree(root, subtrees, &subtree, &format!("{name}/"), res)?;
+        } else {
+            res.push(TreeLine {
+                name,
+                ..line.clone()
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Build the tree from lines named by their full path, the sub trees are only
+/// hashed and collected into `subtrees`, without being saved
+fn build_tree(lines: Vec<TreeLine>, subtrees: &mut HashMap<ObjectSha1, Tree>) -> Tree {
+    let mut map = HashMap::new();
+    let mut dirs: HashMap<String, Vec<TreeLine>> = HashMap::new();
+    for line in lines {
+        match line.name.split_once('/') {
+            Some((dir, rest)) => dirs.entry(dir.to_string()).or_default().push(TreeLine {
+                name: rest.to_string(),
+                ..line
+            }),
+            None => {
+                map.insert(line.name.clone(), line);
+            }
+        }
+    }
+    for (dir, lines) in dirs {
+        let subtree = build_tree(lines, subtrees);
+        let sha1 = ObjectSha1::from(Object::Tree(subtree.clone()).sha1());
+        map.insert(
+            dir.clone(),
+            TreeLine {
+                kind: TreeLineKind::Tree,
+                name: dir,
+                sha1: sha1.clone(),
+            },
+        );
+        subtrees.insert(sha1, subtree);
+    }
+    Tree::from(map)
+}
+
 impl Stage {
     pub const LOCATION: &str = "index";
 
     pub fn empty() -> Self {
-        Stage(Tree {
+        Stage::from(Tree {
             objects: Vec::new(),
         })
     }
+
+    /// Mark the path, and all paths under it if it is a directory, as
+    /// resolved. An empty path resolves all conflicts
+    pub fn resolve(&mut self, path: &str) {
+        let path = path.trim_end_matches('/');
+        self.unmerged.retain(|name, _| {
+            !path.is_empty() && name != path && !name.starts_with(&format!("{path}/"))
+        });
+    }
 }
 
 impl Display for Stage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmerged_entries_should_be_stored_at_their_stages() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        let line = |name: &str, sha1: &str| TreeLine {
+            kind: TreeLineKind::File,
+            name: name.to_string(),
+            sha1: sha1.into(),
+        };
+        let ours = line("a.txt", "95d09f2b10159347eece71399a7e2e907ea3df4f");
+        let mut stage = Stage::from(Tree::from(HashMap::from([(
+            "a.txt".to_string(),
+            line("a.txt", "4b825dc642cb6eb9a060e54bf8d69288fbee4904"),
+        )])));
+        stage.unmerged.insert(
+            "a.txt".to_string(),
+            [
+                None,
+                Some(ours.clone()),
+                Some(line("a.txt", "0e1c6c57130dd977cf3cb8746e7203986d3d32df")),
+            ],
+        );
+        stage.store(root).unwrap();
+
+        let mut loaded = Stage::load(&root.join(Stage::LOCATION)).unwrap();
+        assert_eq!(loaded.unmerged, stage.unmerged);
+        // the tree has the ours version
+        assert_eq!(loaded.tree.objects, vec![ours]);
+
+        loaded.resolve("a.txt");
+        assert!(loaded.unmerged.is_empty());
+    }
+
+    #[test]
+    fn loading_should_not_save_sub_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        let file = TreeLine {
+            kind: TreeLineKind::File,
+            name: "a.txt".to_string(),
+            sha1: "95d09f2b10159347eece71399a7e2e907ea3df4f".into(),
+        };
+        let subtree = Tree::from(HashMap::from([("a.txt".to_string(), file)]));
+        let sha1 = ObjectSha1::from(Object::Tree(subtree.clone()).sha1());
+        let stage = Stage {
+            tree: Tree::from(HashMap::from([(
+                "src".to_string(),
+                TreeLine {
+                    kind: TreeLineKind::Tree,
+                    name: "src".to_string(),
+                    sha1: sha1.clone(),
+                },
+            )])),
+            unmerged: BTreeMap::new(),
+            subtrees: HashMap::from([(sha1.clone(), subtree.clone())]),
+        };
+        stage.store(root).unwrap();
+
+        let loaded = Stage::load(&root.join(Stage::LOCATION)).unwrap();
+        assert_eq!(loaded.tree.objects, stage.tree.objects);
+        assert_eq!(loaded.subtrees[&sha1].objects, subtree.objects);
+        assert!(!root.join("objects").exists());
     }
 }
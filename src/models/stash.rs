@@ -0,0 +1,140 @@
+//! The stash, saved changes of the index and the working tree
+
+use super::{Store, object::ObjectSha1};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// An entry of the stash, `stash@{n}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StashEntry {
+    /// the commit of the working tree, whose parents are `HEAD`, the commit
+    /// of the index, and the commit of untracked files if they are stashed
+    pub commit: ObjectSha1,
+    /// who and when, like `kidon-git <kidon-git@localhost> 1700000000 +0000`
+    pub signature: String,
+    pub message: String,
+}
+
+/// Entries of the stash, the newest first. Like git, the newest one is
+/// stored in `refs/stash`, and all of them are in its reflog
+/// `logs/refs/stash`, the oldest first:
+///
+/// ```txt
+/// <old sha1> <new sha1> <signature>\t<message>
+/// ```
+///
+/// where the old sha1 is the entry before, or zeros for the first one.
+///
+/// See <https://git-scm.com/docs/git-stash>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StashList {
+    pub entries: Vec<StashEntry>,
+}
+
+impl StashList {
+    pub const LOCATION: &str = "logs/refs/stash";
+    pub const REF: &str = "refs/stash";
+}
+
+impl Store for StashList {
+    fn location(&self) -> PathBuf {
+        Path::new(Self::LOCATION).to_path_buf()
+    }
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        let Some(newest) = self.entries.first() else {
+            return self.delete(root);
+        };
+        let mut log = String::new();
+        let mut old = "0".repeat(40);
+        for entry in self.entries.iter().rev() {
+            log.push_str(&format!(
+                "{old} {} {}\t{}\n",
+                entry.commit, entry.signature, entry.message
+            ));
+            old = entry.commit.to_string();
+        }
+        let path = root.join(Self::LOCATION);
+        fs::create_dir_all(path.parent().expect("reflog should be in a directory"))?;
+        fs::write(path, log)?;
+        fs::write(root.join(Self::REF), format!("{}\n", newest.commit))
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid stash reflog line '{line}'"),
+            )
+        };
+        let mut entries = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let (head, message) = line.split_once('\t').ok_or_else(|| invalid(line))?;
+            let mut fields = head.splitn(3, ' ');
+            let (Some(_old), Some(new), Some(signature)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid(line));
+            };
+            entries.push(StashEntry {
+                commit: ObjectSha1::from(new),
+                signature: signature.to_string(),
+                message: message.to_string(),
+            });
+        }
+        entries.reverse();
+        Ok(StashList { entries })
+    }
+
+    fn delete(&self, root: &Path) -> io::Result<()> {
+        for path in [Self::LOCATION, Self::REF] {
+            match fs::remove_file(root.join(path)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stash_list_should_be_loaded_as_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("refs")).unwrap();
+
+        let entry = |commit: &str, message: &str| StashEntry {
+            commit: ObjectSha1::from(commit),
+            signature: "kidon-git <kidon-git@localhost> 1700000000 +0000".to_string(),
+            message: message.to_string(),
+        };
+        let list = StashList {
+            entries: vec![
+                entry("7c1b0a9e8d7f6e5d4c3b2a1f0e9d8c7b6a5f4e3d", "On main: newer"),
+                entry(
+                    "2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f",
+                    "WIP on main: 1234567 a",
+                ),
+            ],
+        };
+        list.store(root).unwrap();
+        let log = fs::read_to_string(root.join(StashList::LOCATION)).unwrap();
+        assert!(log.starts_with(&format!("{} 2d8a4e1c", "0".repeat(40))));
+        assert!(log.contains("\n2d8a4e1c0b6f5a3e9d7c2b1a0f8e6d4c2b0a9e7f 7c1b0a9e"));
+        assert_eq!(
+            fs::read_to_string(root.join(StashList::REF)).unwrap(),
+            "7c1b0a9e8d7f6e5d4c3b2a1f0e9d8c7b6a5f4e3d\n"
+        );
+        let loaded = StashList::load(&root.join(StashList::LOCATION)).unwrap();
+        assert_eq!(loaded, list);
+
+        StashList::default().store(root).unwrap();
+        assert!(!root.join(StashList::REF).exists());
+    }
+}
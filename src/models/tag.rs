@@ -0,0 +1,238 @@
+//! Tags, annotated tag objects and `refs/tags`
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    Accessible, Store,
+    branch::PackedRefs,
+    object::{GitObject, ObjectSha1},
+    repo::Repository,
+};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// An annotated tag, which points to another object (usually a commit) with
+/// the tagger and a message
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct Tag {
+    /// the tagged object
+    pub object: ObjectSha1,
+    /// git type name of the tagged object, see [GitObject::object_type]
+    pub object_type: String,
+    /// name of the tag, without `refs/tags/`
+    pub name: String,
+    /// who and when, like `kidon-git <kidon-git@localhost> 1700000000 +0000`
+    pub tagger: String,
+    /// Like git, a message always ends with a newline, unless it is empty
+    pub message: String,
+}
+
+impl Tag {
+    /// Parse the content of a git tag object, see [GitObject::content]
+    pub fn from_content(content: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let content =
+            std::str::from_utf8(content).map_err(|_| invalid("tag is not valid UTF-8"))?;
+        let (headers, message) = content
+            .split_once("\n\n")
+            .unwrap_or((content.trim_end_matches('\n'), ""));
+
+        let (mut object, mut object_type, mut name, mut tagger) = (None, None, None, None);
+        for header in headers.lines() {
+            let Some((key, value)) = header.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "object" => object = Some(ObjectSha1::from(value)),
+                "type" => object_type = Some(value.to_string()),
+                "tag" => name = Some(value.to_string()),
+                "tagger" => tagger = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Tag {
+            object: object.ok_or_else(|| invalid("tag has no object"))?,
+            object_type: object_type.ok_or_else(|| invalid("tag has no type"))?,
+            name: name.ok_or_else(|| invalid("tag has no name"))?,
+            // very old tags of git have no tagger
+            tagger: tagger.unwrap_or_default(),
+            message: message.to_string(),
+        })
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => write!(f, "{json}"),
+            Err(e) => write!(f, "unexpected: failed to serialize the tag: {e}"),
+        }
+    }
+}
+
+impl GitObject for Tag {
+    fn object_type(&self) -> &'static str {
+        "tag"
+    }
+
+    /// The tag is encoded as headers, an empty line, and the message
+    ///
+    /// ```txt
+    /// object 2aae6c35c94fcfb415dbe95f408b9ce91ee846ed
+    /// type commit
+    /// tag v1.0
+    /// tagger kidon-git <kidon-git@localhost> 1700000000 +0000
+    ///
+    /// tag message
+    /// ```
+    fn content(&self) -> io::Result<Vec<u8>> {
+        let mut data = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object, self.object_type, self.name
+        );
+        if !self.tagger.is_empty() {
+            data.push_str(&format!("tagger {}\n", self.tagger));
+        }
+        data.push('\n');
+        data.push_str(&self.message);
+        Ok(data.into_bytes())
+    }
+}
+
+/// A tag ref, `refs/tags/{name}`, which points to a [Tag] object for an
+/// annotated tag, or directly to a commit for a lightweight tag.
+///
+/// Like branches, it may also be packed in [PackedRefs].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagRef {
+    pub name: String,
+    pub target: ObjectSha1,
+}
+
+impl TagRef {
+    pub const DIRECTORY: &str = "refs/tags";
+
+    /// Like git, names are checked as `git check-ref-format` does
+    pub fn validate_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with('-')
+            && !name.ends_with(".lock")
+            && !name.contains("..")
+            && !name.contains("@{")
+            && name
+                .split('/')
+                .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with('.'))
+            && !name
+                .chars()
+                .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    }
+}
+
+fn path_of(name: &str) -> PathBuf {
+    Path::new(TagRef::DIRECTORY).join(name)
+}
+
+impl Store for TagRef {
+    fn location(&self) -> PathBuf {
+        path_of(&self.name)
+    }
+
+    fn store(&self, root: &Path) -> io::Result<()> {
+        let path = root.join(self.location());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, format!("{}\n", self.target))
+    }
+
+    /// The tag name is taken from the path after `refs/tags/`
+    fn load(path: &Path) -> io::Result<Self> {
+        let name = path
+            .to_string_lossy()
+            .replace('\\', "/")
+            .split_once(&format!("{}/", Self::DIRECTORY))
+            .map(|(_, name)| name.to_string())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a tag", path.display()),
+                )
+            })?;
+        let target = fs::read_to_string(path)?.trim().into();
+        Ok(TagRef { name, target })
+    }
+
+    /// Delete the ref file, and the ref in `packed-refs` if exists
+    fn delete(&self, root: &Path) -> io::Result<()> {
+        let refname = format!("{}/{}", Self::DIRECTORY, self.name);
+        let mut packed = PackedRefs::load(root)?;
+        let was_packed = packed.0.remove(&refname).is_some();
+        if was_packed {
+            packed.store(root)?;
+        }
+        match fs::remove_file(root.join(self.location())) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && was_packed => Ok(()),
+            res => res,
+        }
+    }
+}
+
+impl Accessible<String> for TagRef {
+    fn path_of(by: &String) -> PathBuf {
+        path_of(by)
+    }
+
+    /// Load the tag from its ref file, or from the `packed-refs` file if the
+    /// ref file does not exist
+    fn load_from(repo: &Repository, by: &String) -> io::Result<Self> {
+        match Self::load(&repo.root.join(path_of(by))) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let refname = format!("{}/{by}", Self::DIRECTORY);
+                let target = PackedRefs::load(&repo.root)?.0.remove(&refname).ok_or(e)?;
+                Ok(TagRef {
+                    name: by.clone(),
+                    target,
+                })
+            }
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::object::Sha1Able;
+
+    #[test]
+    fn test_tag_sha1() {
+        let tag = Tag {
+            object: "0e1c6c57130dd977cf3cb8746e7203986d3d32df".into(),
+            object_type: "commit".to_string(),
+            name: "v1.0".to_string(),
+            tagger: "kidon-git <kidon-git@localhost> 1700000000 +0000".to_string(),
+            message: "release 1.0\n".to_string(),
+        };
+
+        // same as `git hash-object -t tag` with the content of the tag
+        assert_eq!(tag.sha1(), "d12727af52edc63f1e1458dbac370c32b5c1252d");
+        assert_eq!(Tag::from_content(&tag.content().unwrap()).unwrap(), tag);
+    }
+
+    #[test]
+    fn test_validate_tag_name() {
+        for name in ["v1.0", "release/2024-01", "a_b"] {
+            assert!(TagRef::validate_name(name), "{name}");
+        }
+        for name in [
+            "", "-v", "a..b", "a b", "v1^", "a/", ".a", "a.lock", "a@{1}",
+        ] {
+            assert!(!TagRef::validate_name(name), "{name}");
+        }
+    }
+}
@@ -2,10 +2,9 @@
 //! contains a list of [TreeLine] objects, each representing a
 //! [Blob](super::blob::Blob) or another [Tree].
 
-use super::object::{ObjectSha1, Sha1Able};
+use super::object::{GitObject, ObjectSha1};
 use bincode::{Decode, Encode};
-use sha1::Digest;
-use std::{collections::HashMap, fmt::Display};
+use std::{cmp::Ordering, collections::HashMap, fmt::Display, io};
 
 #[derive(Debug, Clone, PartialEq, Eq, Decode, Encode, Hash)]
 pub enum TreeLineKind {
@@ -15,6 +14,29 @@ pub enum TreeLineKind {
     Tree,
 }
 
+impl TreeLineKind {
+    /// The mode stored in a git tree object
+    pub fn mode(&self) -> &'static str {
+        match self {
+            TreeLineKind::File => "100644",
+            TreeLineKind::Executable => "100755",
+            TreeLineKind::Symlink => "120000",
+            TreeLineKind::Tree => "40000",
+        }
+    }
+
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            // old git versions may write group-writable files as 100664
+            "100644" | "100664" => Some(TreeLineKind::File),
+            "100755" => Some(TreeLineKind::Executable),
+            "120000" => Some(TreeLineKind::Symlink),
+            "40000" | "040000" => Some(TreeLineKind::Tree),
+            _ => None,
+        }
+    }
+}
+
 impl Display for TreeLineKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -35,6 +57,21 @@ pub struct TreeLine {
     pub sha1: ObjectSha1,
 }
 
+impl TreeLine {
+    /// Compare two lines in the order of git: by name, but a [Tree] is sorted
+    /// as if its name ends with a `/`
+    pub fn git_cmp(&self, other: &Self) -> Ordering {
+        let key = |line: &TreeLine| {
+            let mut key = line.name.as_bytes().to_vec();
+            if line.kind == TreeLineKind::Tree {
+                key.push(b'/');
+            }
+            key
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
 impl Display for TreeLine {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} {}    {}", self.kind, self.sha1, self.name)
@@ -70,15 +107,26 @@ pub struct Tree {
     pub objects: Vec<TreeLine>,
 }
 
-impl Sha1Able for Tree {
-    fn sha1(&self) -> String {
-        let mut hasher = sha1::Sha1::new();
-        for line in &self.objects {
-            hasher.update(line.kind.to_string().as_bytes());
-            hasher.update(line.name.as_bytes());
-            hasher.update(line.sha1.as_bytes());
+impl GitObject for Tree {
+    fn object_type(&self) -> &'static str {
+        "tree"
+    }
+
+    /// Each line is encoded as `<mode> <name>\0<20 bytes sha1>`, a line which
+    /// does not hold a valid sha1 can not be encoded
+    fn content(&self) -> io::Result<Vec<u8>> {
+        let mut lines = self.objects.iter().collect::<Vec<_>>();
+        lines.sort_by(|a, b| a.git_cmp(b));
+
+        let mut data = Vec::new();
+        for line in lines {
+            data.extend(line.kind.mode().as_bytes());
+            data.push(b' ');
+            data.extend(line.name.as_bytes());
+            data.push(0);
+            data.extend(line.sha1.to_bytes()?);
         }
-        base16ct::lower::encode_string(&hasher.finalize())
+        Ok(data)
     }
 }
 
@@ -96,7 +144,7 @@ impl From<HashMap<String, TreeLine>> for Tree {
         let mut ret = Tree {
             objects: value.into_values().collect(),
         };
-        ret.objects.sort_by(|a, b| a.name.cmp(&b.name));
+        ret.objects.sort_by(|a, b| a.git_cmp(b));
         ret
     }
 }
@@ -106,6 +154,44 @@ impl Tree {
         Tree { objects: vec![] }
     }
 
+    /// Parse the content of a git tree object, see [GitObject::content]
+    pub fn from_content(mut content: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut objects = Vec::new();
+
+        while !content.is_empty() {
+            let space = content
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or_else(|| invalid("tree line has no mode"))?;
+            let mode = std::str::from_utf8(&content[..space])
+                .map_err(|_| invalid("tree line mode is not valid UTF-8"))?;
+            let kind = TreeLineKind::from_mode(mode)
+                .ok_or_else(|| invalid(&format!("unsupported tree line mode {mode}")))?;
+            content = &content[space + 1..];
+
+            let nul = content
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| invalid("tree line name is not terminated"))?;
+            let name = String::from_utf8(content[..nul].to_vec())
+                .map_err(|_| invalid("tree line name is not valid UTF-8"))?;
+            content = &content[nul + 1..];
+
+            if content.len() < 20 {
+                return Err(invalid("tree line sha1 is truncated"));
+            }
+            objects.push(TreeLine {
+                kind,
+                name,
+                sha1: ObjectSha1::from_bytes(&content[..20]),
+            });
+            content = &content[20..];
+        }
+
+        Ok(Tree { objects })
+    }
+
     pub fn into_map(self) -> HashMap<String, TreeLine> {
         self.objects
             .into_iter()
@@ -124,6 +210,7 @@ impl Tree {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::object::Sha1Able;
 
     #[test]
     fn test_tree_sha1() {
@@ -132,24 +219,58 @@ mod tests {
                 TreeLine {
                     kind: TreeLineKind::File,
                     name: "file.txt".to_string(),
-                    sha1: "abc123".into(),
+                    sha1: "95d09f2b10159347eece71399a7e2e907ea3df4f".into(),
                 },
                 TreeLine {
-                    kind: TreeLineKind::File,
+                    kind: TreeLineKind::Tree,
                     name: "dir".to_string(),
-                    sha1: "def456".into(),
+                    sha1: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".into(),
                 },
             ],
         };
 
+        // same as `git mktree` with the lines above
         let sha1 = tree.sha1();
-        assert_eq!(sha1, "00bfe760502a870dff983987f29dcf6e8dd76495");
+        assert_eq!(sha1, "449ad38ee091f707a4da5dfd2d252b04b01ab79f");
         tree.objects.push(TreeLine {
             kind: TreeLineKind::Executable,
             name: "new_file.exe".to_string(),
-            sha1: "xyz789".into(),
+            sha1: "bc7774a7b18deb1d7bd0212d34246a9b1260ae17".into(),
         });
 
         assert_ne!(sha1, tree.sha1());
     }
+
+    #[test]
+    fn test_tree_git_order() {
+        let line = |kind, name: &str| TreeLine {
+            kind,
+            name: name.to_string(),
+            sha1: "95d09f2b10159347eece71399a7e2e907ea3df4f".into(),
+        };
+        let tree = Tree::from(HashMap::from([
+            ("a.txt".to_string(), line(TreeLineKind::File, "a.txt")),
+            ("a".to_string(), line(TreeLineKind::Tree, "a")),
+            ("a-b".to_string(), line(TreeLineKind::File, "a-b")),
+        ]));
+        let names = tree
+            .objects
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a-b", "a.txt", "a"]);
+    }
+
+    #[test]
+    fn invalid_sha1_should_not_be_encoded() {
+        let tree = Tree {
+            objects: vec![TreeLine {
+                kind: TreeLineKind::File,
+                name: "file.txt".to_string(),
+                sha1: "not a sha1".into(),
+            }],
+        };
+        let err = tree.content().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
 }
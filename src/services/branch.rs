@@ -1,12 +1,14 @@
-use crate::services::dump_tree::DumpTreeService;
 use crate::models::{
-        Accessible, DirContainer,
-        branch::{Branch, EMPTY_BRANCH_HEAD_PLACEHOLDER},
-        repo::{Repository, WithRepo},
-        stage::Stage,
-    };
-use std::io;
+    Accessible, DirContainer,
+    branch::{Branch, EMPTY_BRANCH_HEAD_PLACEHOLDER, PackedRefs, ref_names},
+    object::ObjectSha1,
+    repo::{Repository, WithRepo},
+    stage::Stage,
+};
+use crate::services::dump_tree::DumpTreeService;
+use std::{collections::BTreeSet, io};
 
+#[derive(Debug)]
 pub enum BranchCreationError {
     AlreadyExists,
     InvalidName,
@@ -32,8 +34,11 @@ impl From<BranchCreationError> for anyhow::Error {
 pub trait BranchService {
     fn load_branch<'a>(&'a self, name: &str) -> io::Result<WithRepo<'a, Branch>>;
     fn list_branch(&self) -> io::Result<Vec<String>>;
-    fn create_branch(&self, branch_name: &str)
-    -> Result<WithRepo<'_, Branch>, BranchCreationError>;
+    fn create_branch(
+        &self,
+        branch_name: &str,
+        start_point: Option<ObjectSha1>,
+    ) -> Result<WithRepo<'_, Branch>, BranchCreationError>;
     fn delete_branch(&self, branch_name: &str) -> io::Result<()>;
     fn branch_exists(&self, branch_name: &str) -> io::Result<bool>;
     fn checkout_branch(&mut self, branch_name: &str, dry: bool) -> io::Result<()>;
@@ -47,49 +52,30 @@ impl BranchService for Repository {
 
     /// list branch names, including remote branches, by a vector of strings
     fn list_branch(&self) -> io::Result<Vec<String>> {
-        let mut branches = Vec::new();
-        for entry in std::fs::read_dir(self.root.join(Branch::DIRECTORY).join("heads"))? {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                let branch_name = entry.file_name().into_string().map_err(|s| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("branch name {s:?} is not valid"),
-                    )
-                })?;
-                branches.push(branch_name);
-            }
-        }
-
-        for entry in std::fs::read_dir(self.root.join(Branch::DIRECTORY).join("remotes"))? {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                let remote_name = entry.file_name().into_string().map_err(|s| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("remote name {s:?} is not valid"),
-                    )
-                })?;
-                for branch in std::fs::read_dir(entry.path())? {
-                    let branch = branch?;
-                    if branch.file_type()?.is_file() {
-                        let branch_name = branch.file_name().into_string().map_err(|s| {
-                            io::Error::new(
-                                io::ErrorKind::InvalidData,
-                                format!("branch name {s:?} is not valid"),
-                            )
-                        })?;
-                        branches.push(format!("{remote_name}/{branch_name}"));
-                    }
-                }
-            }
-        }
-
-        Ok(branches)
+        let refs = self.root.join(Branch::DIRECTORY);
+        let mut branches = BTreeSet::new();
+        // branches may be in directories, like `feature/x`
+        ref_names(&refs.join("heads"), "", &mut branches)?;
+        ref_names(&refs.join("remotes"), "", &mut branches)?;
+
+        let packed = PackedRefs::load(&self.root)?;
+        branches.extend(
+            packed
+                .names_under("refs/heads/")
+                .chain(packed.names_under("refs/remotes/"))
+                .map(|name| name.to_string()),
+        );
+
+        Ok(branches.into_iter().collect())
     }
 
-    /// Create a new branch with the given name based on the current branch
-    fn create_branch(&self, name: &str) -> Result<WithRepo<'_, Branch>, BranchCreationError> {
+    /// Create a new branch with the given name based on the start point, or
+    /// the current branch if it is `None`
+    fn create_branch(
+        &self,
+        name: &str,
+        start_point: Option<ObjectSha1>,
+    ) -> Result<WithRepo<'_, Branch>, BranchCreationError> {
         Branch::validate_name(name)
             .then_some(())
             .ok_or(BranchCreationError::InvalidName)?;
@@ -97,18 +83,22 @@ impl BranchService for Repository {
             return Err(BranchCreationError::AlreadyExists);
         };
 
-        let Ok(current_branch) = self.head().load_branch() else {
-            return Ok(self.wrap(Branch {
-                remote: None,
-                name: name.to_string(),
-                head: EMPTY_BRANCH_HEAD_PLACEHOLDER.into(),
-            }));
+        let head = match (start_point, self.head().load_branch()) {
+            (Some(start_point), _) => start_point,
+            (None, Ok(current_branch)) => current_branch.unwrap().head,
+            (None, Err(_)) => {
+                return Ok(self.wrap(Branch {
+                    remote: None,
+                    name: name.to_string(),
+                    head: EMPTY_BRANCH_HEAD_PLACEHOLDER.into(),
+                }));
+            }
         };
 
         let wrap = self.wrap(Branch {
             remote: None,
             name: name.to_string(),
-            head: current_branch.unwrap().head,
+            head,
         });
 
         wrap.save()?;
@@ -136,8 +126,7 @@ impl BranchService for Repository {
         // Step 0: if is dry checkout
         if dry && self.list_branch().unwrap().is_empty() {
             // Step 0-8: Update HEAD to point to the new branch, no need to modify anything.
-            let head = self.head_mut();
-            head.branch_name = name.into();
+            self.head_mut().switch_to(name);
             self.save_head()?;
             return Ok(());
         }
@@ -149,13 +138,12 @@ impl BranchService for Repository {
             ));
         }
 
-        // if dry checkout, just update the HEAD
-        let Ok(_) = self.head().load_branch() else {
-            let head = self.head_mut();
-            head.branch_name = name.to_string();
+        // if the current branch has no commits yet, just update the HEAD
+        if self.head().detached().is_none() && self.head().load_branch().is_err() {
+            self.head_mut().switch_to(name);
             self.save_head()?;
             return Ok(());
-        };
+        }
 
         let target_branch = self.load_branch(name)?;
         let target_commit = target_branch.get_current_commit()?;
@@ -164,11 +152,10 @@ impl BranchService for Repository {
         self.dump_tree(&target_tree)?;
 
         // save the target tree to the stage
-        target_tree.map(Stage).save()?;
+        target_tree.map(Stage::from).save()?;
 
         // Step 8: Update HEAD to point to the new branch
-        let head = self.head_mut();
-        head.branch_name = name.into();
+        self.head_mut().switch_to(name);
         self.save_head()?;
         Ok(())
     }
@@ -1,11 +1,15 @@
-
 use crate::{
     models::{
-        commit::{Commit, CommitBuilder},
+        commit::{Commit, CommitBuilder, Signature},
+        config::Config,
         object::{Object, Sha1Able},
         repo::Repository,
     },
-    services::{object::ObjectService, tree::compare_trees},
+    services::{
+        identity::Role,
+        object::ObjectService,
+        tree::{RenameOptions, compare_trees, detect_renames},
+    },
 };
 
 use super::tree::ComparedLine;
@@ -23,42 +27,70 @@ pub enum CommitCreateResult {
 }
 
 pub trait CommitService {
-    fn create_commit(&self, message: &str) -> anyhow::Result<CommitCreateResult>;
+    /// Commit the index to the current branch, by the author if it is given,
+    /// or the current one, see [Repository::signature]
+    fn create_commit(
+        &self,
+        message: &str,
+        author: Option<Signature>,
+    ) -> anyhow::Result<CommitCreateResult>;
 }
 
 impl CommitService for Repository {
-    fn create_commit(&self, message: &str) -> anyhow::Result<CommitCreateResult> {
+    fn create_commit(
+        &self,
+        message: &str,
+        author: Option<Signature>,
+    ) -> anyhow::Result<CommitCreateResult> {
         let (branch, is_new) = self.head().load_branch_or_create()?;
         let message = message.to_owned();
 
         // Step 2: Generate tree object from the stage
         let stage = self.stage()?;
-        let tree = stage.map(|s| s.0);
+        if !stage.unmerged.is_empty() {
+            anyhow::bail!(
+                "Committing is not possible because you have unmerged files.\n\
+                 Fix them up in the work tree, and then use 'git add/rm <file>' as appropriate to mark resolution"
+            );
+        }
+        let tree = stage.map(|s| s.tree);
+        // an unfinished merge is concluded by this commit
+        let merge_state = self.merge_state()?;
 
         // Step 3: Compare the tree with the current HEAD, to check if working tree clean
         let compared = if !is_new {
             let current_commit = self.load_object(&branch.head)?.map(|o| o.cast_commit());
             let current_tree = current_commit.get_tree()?;
 
-            let compared = compare_trees(&current_tree, &tree)?;
-            if compared.is_empty() {
+            let mut compared = compare_trees(&current_tree, &tree)?;
+            if compared.is_empty() && merge_state.is_none() {
                 return Ok(CommitCreateResult::NothingToCommit);
             }
+            if let Some(options) = RenameOptions::from_config(&Config::load(&self.root)?)? {
+                compared = detect_renames(self, compared, &options, false)?;
+            }
             Some(compared)
         } else {
             None
         };
 
         // Step 4: Create commit object
+        let author = match author {
+            Some(author) => author,
+            None => self.signature(Role::Author)?,
+        };
+        self.save_tree(&tree)?;
         let tree = tree.map(Object::Tree);
-        tree.save()?;
         let commit = Commit::new(CommitBuilder {
             tree: tree.sha1().into(),
-            parent: if is_new {
-                None
+            parents: if is_new {
+                vec![]
             } else {
-                Some(branch.head.clone())
+                let theirs = merge_state.as_ref().map(|state| state.theirs.clone());
+                [branch.head.clone()].into_iter().chain(theirs).collect()
             },
+            author,
+            committer: self.signature(Role::Committer)?,
             message: message.to_string(),
         });
 
@@ -69,6 +101,9 @@ impl CommitService for Repository {
         let mut branch_cloned = branch.cloned();
         branch_cloned.head = commit_sha1.clone().into();
         branch_cloned.save()?;
+        if let Some(merge_state) = merge_state {
+            merge_state.remove()?;
+        }
 
         Ok(CommitCreateResult::Success(CommitCreationInfo {
             compared,
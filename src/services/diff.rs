@@ -0,0 +1,709 @@
+//! Line-level diff of files, with the Myers algorithm
+//!
+//! See <http://www.xmailserver.org/diff2.pdf>
+
+use crate::{
+    models::{
+        blob::Blob,
+        object::Object,
+        repo::{Repository, WithRepo},
+        tree::{Tree, TreeLine, TreeLineKind},
+    },
+    services::{
+        mut_tree::read_file,
+        object::ObjectService,
+        tree::{ComparedKind, ComparedLine, RenameOptions, compare_trees, detect_renames},
+    },
+};
+use std::{collections::HashSet, fmt::Display};
+
+/// An edit to turn `a` into `b`, with indexes of lines in `a` and `b`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// The shortest edit script from `a` to `b`, with the linear space variant of
+/// the Myers algorithm
+pub fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    diff_range(a, b, (0, 0), &mut edits);
+    edits
+}
+
+/// Push the edits from `a` to `b`, which start at `(x0, y0)` of the files
+fn diff_range<T: PartialEq>(a: &[T], b: &[T], (x0, y0): (usize, usize), edits: &mut Vec<Edit>) {
+    // the common prefix and suffix need no search
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    edits.extend((0..prefix).map(|i| Edit::Equal(x0 + i, y0 + i)));
+    let (x0, y0) = (x0 + prefix, y0 + prefix);
+    if a_mid.is_empty() {
+        edits.extend((0..b_mid.len()).map(|y| Edit::Insert(y0 + y)));
+    } else if b_mid.is_empty() {
+        edits.extend((0..a_mid.len()).map(|x| Edit::Delete(x0 + x)));
+    } else {
+        let (x, y) = middle_snake(a_mid, b_mid);
+        diff_range(&a_mid[..x], &b_mid[..y], (x0, y0), edits);
+        diff_range(&a_mid[x..], &b_mid[y..], (x0 + x, y0 + y), edits);
+    }
+    let (a_end, b_end) = (x0 + a_mid.len(), y0 + b_mid.len());
+    edits.extend((0..suffix).map(|i| Edit::Equal(a_end + i, b_end + i)));
+}
+
+/// Where to split the edit path, by searching from both ends at once until
+/// the paths overlap on the middle snake, so only O(n + m) memory is used.
+///
+/// Both `a` and `b` must be non-empty without a common prefix or suffix, so
+/// the split is never at an end. Like git, the search gives up being optimal
+/// after some cost, and splits at the furthest point from the start instead.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max_d = (n + m + 1) / 2;
+    let max_cost = (((n + m) as f64).sqrt() as isize).max(256).min(max_d);
+
+    // vf[k + offset] is the furthest x on the diagonal k from the start, and
+    // vb[k + offset] the furthest from the end, -1 if not reached yet
+    let offset = max_d + 1;
+    let len = 2 * offset + 1;
+    let mut vf = vec![-1isize; len as usize];
+    let mut vb = vec![-1isize; len as usize];
+    vf[offset as usize + 1] = 0;
+    vb[offset as usize + 1] = 0;
+    // diagonals which left the grid are not searched any more
+    let (mut f_start, mut f_end, mut b_start, mut b_end) = (0, 0, 0, 0);
+    let mut furthest = (0, 0);
+
+    for d in 0..=max_d {
+        for k in (-d + f_start..=d - f_end).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && vf[i - 1] < vf[i + 1]) {
+                vf[i + 1]
+            } else {
+                vf[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            vf[i] = x;
+            if x > n {
+                f_end += 2;
+            } else if y > m {
+                f_start += 2;
+            } else {
+                if x + y > furthest.0 + furthest.1 {
+                    furthest = (x, y);
+                }
+                let j = delta - k + offset;
+                if odd && (0..len).contains(&j) && vb[j as usize] != -1 && x >= n - vb[j as usize] {
+                    return (x as usize, y as usize);
+                }
+            }
+        }
+
+        for k in (-d + b_start..=d - b_end).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && vb[i - 1] < vb[i + 1]) {
+                vb[i + 1]
+            } else {
+                vb[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            vb[i] = x;
+            if x > n {
+                b_end += 2;
+            } else if y > m {
+                b_start += 2;
+            } else {
+                let j = delta - k + offset;
+                if !odd && (0..len).contains(&j) && vf[j as usize] != -1 {
+                    let (fx, fy) = (vf[j as usize], vf[j as usize] - (delta - k));
+                    if fx <= n && fy <= m && fx >= n - x {
+                        return (fx as usize, fy as usize);
+                    }
+                }
+            }
+        }
+
+        if d == max_cost {
+            break;
+        }
+    }
+    (furthest.0 as usize, furthest.1 as usize)
+}
+
+/// Split the text into lines, keeping the `\n` of each line, so that a
+/// missing newline at the end of file is also a difference
+pub fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HunkLine {
+    Context(String),
+    Delete(String),
+    Insert(String),
+}
+
+/// A group of nearby changes, with context lines around them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+impl Display for Hunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let range = |start: usize, len: usize| match len {
+            1 => format!("{start}"),
+            len => format!("{start},{len}"),
+        };
+        writeln!(
+            f,
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len),
+            range(self.new_start, self.new_len)
+        )?;
+        for line in &self.lines {
+            let (sign, text) = match line {
+                HunkLine::Context(text) => (' ', text),
+                HunkLine::Delete(text) => ('-', text),
+                HunkLine::Insert(text) => ('+', text),
+            };
+            write!(f, "{sign}{text}")?;
+            if !text.ends_with('\n') {
+                writeln!(f, "\n\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Group the edits from `a` to `b` into hunks, with `context` lines around
+/// changes
+pub fn hunks(a: &[&str], b: &[&str], context: usize) -> Vec<Hunk> {
+    let edits = myers(a, b);
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    // ranges of edits, merging changes which share their context
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            // lines of a and b before the hunk
+            let (old_before, new_before) = match edits[start] {
+                Edit::Equal(x, y) => (x, y),
+                Edit::Delete(x) => (x, position_in_b(&edits[..start])),
+                Edit::Insert(y) => (position_in_a(&edits[..start]), y),
+            };
+            let lines = edits[start..end]
+                .iter()
+                .map(|edit| match *edit {
+                    Edit::Equal(x, _) => HunkLine::Context(a[x].to_string()),
+                    Edit::Delete(x) => HunkLine::Delete(a[x].to_string()),
+                    Edit::Insert(y) => HunkLine::Insert(b[y].to_string()),
+                })
+                .collect::<Vec<_>>();
+            let old_len = lines
+                .iter()
+                .filter(|l| !matches!(l, HunkLine::Insert(_)))
+                .count();
+            let new_len = lines
+                .iter()
+                .filter(|l| !matches!(l, HunkLine::Delete(_)))
+                .count();
+            // like git, an empty range starts at the line before it
+            Hunk {
+                old_start: if old_len == 0 {
+                    old_before
+                } else {
+                    old_before + 1
+                },
+                old_len,
+                new_start: if new_len == 0 {
+                    new_before
+                } else {
+                    new_before + 1
+                },
+                new_len,
+                lines,
+            }
+        })
+        .collect()
+}
+
+fn position_in_a(edits: &[Edit]) -> usize {
+    edits
+        .iter()
+        .filter(|e| !matches!(e, Edit::Insert(_)))
+        .count()
+}
+
+fn position_in_b(edits: &[Edit]) -> usize {
+    edits
+        .iter()
+        .filter(|e| !matches!(e, Edit::Delete(_)))
+        .count()
+}
+
+/// The difference of a file
+pub struct FileDiff {
+    /// the path after, or before if it is deleted
+    pub path: String,
+    pub kind: ComparedKind,
+    /// the file before, `None` if it is added
+    pub old: Option<TreeLine>,
+    /// the file after, `None` if it is deleted
+    pub new: Option<TreeLine>,
+    /// `None` if any side is binary
+    pub hunks: Option<Vec<Hunk>>,
+}
+
+impl Display for FileDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let short = |line: &Option<TreeLine>| match line {
+            Some(line) => line.sha1.chars().take(7).collect::<String>(),
+            None => "0000000".to_string(),
+        };
+        let name = |prefix: &str, line: &Option<TreeLine>| match line {
+            Some(line) => format!("{prefix}/{}", line.name),
+            None => "/dev/null".to_string(),
+        };
+        // the old path differs if it is renamed or copied
+        let old_path = self.old.as_ref().map_or(&self.path, |old| &old.name);
+
+        writeln!(f, "diff --git a/{old_path} b/{}", self.path)?;
+        match (&self.old, &self.new) {
+            (None, Some(new)) => writeln!(f, "new file mode {}", new.kind.mode())?,
+            (Some(old), None) => writeln!(f, "deleted file mode {}", old.kind.mode())?,
+            (Some(old), Some(new)) if old.kind != new.kind => {
+                writeln!(f, "old mode {}", old.kind.mode())?;
+                writeln!(f, "new mode {}", new.kind.mode())?;
+            }
+            _ => {}
+        }
+        match self.kind {
+            ComparedKind::Renamed { similarity } => {
+                writeln!(f, "similarity index {similarity}%")?;
+                writeln!(f, "rename from {old_path}")?;
+                writeln!(f, "rename to {}", self.path)?;
+            }
+            ComparedKind::Copied { similarity } => {
+                writeln!(f, "similarity index {similarity}%")?;
+                writeln!(f, "copy from {old_path}")?;
+                writeln!(f, "copy to {}", self.path)?;
+            }
+            _ => {}
+        }
+        if self.old.as_ref().map(|l| &l.sha1) == self.new.as_ref().map(|l| &l.sha1) {
+            // only the mode is changed
+            return Ok(());
+        }
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) if old.kind == new.kind => {
+                writeln!(
+                    f,
+                    "index {}..{} {}",
+                    short(&self.old),
+                    short(&self.new),
+                    old.kind.mode()
+                )?;
+            }
+            _ => writeln!(f, "index {}..{}", short(&self.old), short(&self.new))?,
+        }
+
+        match &self.hunks {
+            None => writeln!(
+                f,
+                "Binary files {} and {} differ",
+                name("a", &self.old),
+                name("b", &self.new)
+            ),
+            Some(hunks) => {
+                writeln!(f, "--- {}", name("a", &self.old))?;
+                writeln!(f, "+++ {}", name("b", &self.new))?;
+                hunks.iter().try_for_each(|hunk| write!(f, "{hunk}"))
+            }
+        }
+    }
+}
+
+/// The side of a diff to compare with
+pub enum DiffTarget<'r> {
+    /// a tree in the object database, such as the index or a commit
+    Tree(WithRepo<'r, Tree>),
+    /// files in the working directory, untracked files are ignored
+    WorkingTree,
+}
+
+pub trait DiffService {
+    /// Diff files from the tree `from` to `to`, with `context` lines around
+    /// changes. Renames are detected with `renames`
+    fn diff(
+        &self,
+        from: &WithRepo<Tree>,
+        to: DiffTarget,
+        context: usize,
+        renames: Option<&RenameOptions>,
+    ) -> anyhow::Result<Vec<FileDiff>>;
+}
+
+impl Repository {
+    /// Content of the file
+    fn blob_of(&self, line: &TreeLine, in_working_dir: bool) -> anyhow::Result<Blob> {
+        if in_working_dir {
+            return Ok(read_file(&self.working_dir().join(&line.name))?.1);
+        }
+        match self.load_object(&line.sha1)?.unwrap() {
+            Object::Blob(blob) => Ok(blob),
+            _ => anyhow::bail!("object {} is not a blob", line.sha1),
+        }
+    }
+}
+
+impl DiffService for Repository {
+    fn diff(
+        &self,
+        from: &WithRepo<Tree>,
+        to: DiffTarget,
+        context: usize,
+        renames: Option<&RenameOptions>,
+    ) -> anyhow::Result<Vec<FileDiff>> {
+        let in_working_dir = matches!(to, DiffTarget::WorkingTree);
+        let to = match to {
+            DiffTarget::Tree(tree) => tree,
+            DiffTarget::WorkingTree => self.working_tree()?,
+        };
+
+        let mut changes = compare_trees(from, &to)?;
+        // untracked files are not in the index, and files are not renamed to
+        // them
+        let mut tracked = HashSet::new();
+        if in_working_dir {
+            for line in self.stage()?.unwrap().tree.objects {
+                tracked.extend(self.files_of(line)?.into_iter().map(|l| l.name));
+            }
+            let mut kept = Vec::new();
+            for change in changes {
+                if change.kind != ComparedKind::Added {
+                    kept.push(change);
+                    continue;
+                }
+                for line in self.files_of(change.line)? {
+                    if tracked.contains(&line.name) {
+                        kept.push(ComparedLine {
+                            kind: ComparedKind::Added,
+                            line,
+                            old: None,
+                        });
+                    }
+                }
+            }
+            changes = kept;
+        }
+        if let Some(options) = renames {
+            changes = detect_renames(self, changes, options, in_working_dir)?;
+        }
+
+        // the kind, the old and the new file
+        let mut pairs = Vec::new();
+        for change in changes {
+            let kind = change.kind;
+            let old = change.old;
+            let new = (kind != ComparedKind::Deleted).then_some(change.line);
+            match (old, new) {
+                (Some(old), Some(new))
+                    if old.kind != TreeLineKind::Tree && new.kind != TreeLineKind::Tree =>
+                {
+                    pairs.push((kind, Some(old), Some(new)));
+                }
+                // added or deleted files and directories, or a file replaced
+                // by a directory
+                (old, new) => {
+                    for file in old
+                        .map(|l| self.files_of(l))
+                        .transpose()?
+                        .unwrap_or_default()
+                    {
+                        pairs.push((ComparedKind::Deleted, Some(file), None));
+                    }
+                    for file in new
+                        .map(|l| self.files_of(l))
+                        .transpose()?
+                        .unwrap_or_default()
+                    {
+                        pairs.push((ComparedKind::Added, None, Some(file)));
+                    }
+                }
+            }
+        }
+        if in_working_dir {
+            // a file may be replaced by a directory of untracked files
+            pairs.retain(|(_, old, new)| match (old, new) {
+                (None, Some(new)) => tracked.contains(&new.name),
+                _ => true,
+            });
+        }
+        pairs.sort_by(|(_, a_old, a_new), (_, b_old, b_new)| {
+            let path = |old: &Option<TreeLine>, new: &Option<TreeLine>| {
+                new.as_ref().or(old.as_ref()).map(|l| l.name.clone())
+            };
+            path(a_old, a_new).cmp(&path(b_old, b_new))
+        });
+
+        let mut diffs = Vec::new();
+        for (kind, old, new) in pairs {
+            let old_blob = match &old {
+                Some(line) => self.blob_of(line, false)?,
+                None => Blob::from(""),
+            };
+            let new_blob = match &new {
+                Some(line) => self.blob_of(line, in_working_dir)?,
+                None => Blob::from(""),
+            };
+            let hunks = match (&old_blob, &new_blob) {
+                (Blob::Text(a), Blob::Text(b)) => {
+                    Some(hunks(&split_lines(a), &split_lines(b), context))
+                }
+                _ => None,
+            };
+
+            diffs.push(FileDiff {
+                path: new.as_ref().or(old.as_ref()).unwrap().name.clone(),
+                kind,
+                old,
+                new,
+                hunks,
+            });
+        }
+        Ok(diffs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{commit::CommitService, rev_parse::RevParseService, testing::temp_repo};
+
+    fn apply<T: ToString>(a: &[T], b: &[T], edits: &[Edit]) -> Vec<String> {
+        edits
+            .iter()
+            .filter_map(|edit| match *edit {
+                Edit::Equal(x, _) => Some(a[x].to_string()),
+                Edit::Insert(y) => Some(b[y].to_string()),
+                Edit::Delete(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_myers_shortest() {
+        let a = "ABCABBA"
+            .split("")
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        let b = "CBABAC"
+            .split("")
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        let edits = myers(&a, &b);
+        assert_eq!(apply(&a, &b, &edits), b);
+        // the shortest edit script of the paper has 5 edits
+        let changes = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Equal(..)))
+            .count();
+        assert_eq!(changes, 5);
+
+        assert_eq!(myers::<&str>(&[], &[]), vec![]);
+        assert_eq!(myers(&["a"], &[]), vec![Edit::Delete(0)]);
+        assert_eq!(myers(&[], &["a"]), vec![Edit::Insert(0)]);
+    }
+
+    /// Pseudo-random lines from a small alphabet, so they have a lot in common
+    fn random_lines(seed: &mut u64, len: usize) -> Vec<String> {
+        (0..len)
+            .map(|_| {
+                *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                ((*seed >> 33) % 4).to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_myers_random() {
+        let mut seed = 42;
+        for _ in 0..200 {
+            let (n, m) = ((seed % 13) as usize, (seed % 17) as usize);
+            let a = random_lines(&mut seed, n);
+            let b = random_lines(&mut seed, m);
+            let edits = myers(&a, &b);
+            assert_eq!(apply(&a, &b, &edits), b);
+
+            // the longest common subsequence, by dynamic programming
+            let mut lcs = vec![vec![0; m + 1]; n + 1];
+            for x in (0..n).rev() {
+                for y in (0..m).rev() {
+                    lcs[x][y] = match a[x] == b[y] {
+                        true => lcs[x + 1][y + 1] + 1,
+                        false => lcs[x + 1][y].max(lcs[x][y + 1]),
+                    };
+                }
+            }
+            let equal = edits
+                .iter()
+                .filter(|e| matches!(e, Edit::Equal(..)))
+                .count();
+            assert_eq!(equal, lcs[0][0]);
+        }
+    }
+
+    #[test]
+    fn test_myers_gives_up_on_costly_diffs() {
+        let mut seed = 7;
+        let a = random_lines(&mut seed, 5000);
+        let b = random_lines(&mut seed, 5000);
+        let edits = myers(&a, &b);
+        assert_eq!(apply(&a, &b, &edits), b);
+    }
+
+    #[test]
+    fn test_unified_hunks() {
+        let a = (1..=20).map(|i| format!("{i}\n")).collect::<String>();
+        let b = (1..=20)
+            .filter(|i| *i != 18)
+            .map(|i| {
+                if i == 3 {
+                    "three\n".into()
+                } else {
+                    format!("{i}\n")
+                }
+            })
+            .collect::<String>()
+            + "21";
+        let hunks = hunks(&split_lines(&a), &split_lines(&b), 3);
+        let text = hunks.iter().map(|h| h.to_string()).collect::<String>();
+        assert_eq!(
+            text,
+            "@@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+             @@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n 19\n 20\n+21\n\\ No newline at end of file\n"
+        );
+
+        let hunks = super::hunks(&[], &split_lines("new\n"), 3);
+        assert_eq!(hunks[0].to_string(), "@@ -0,0 +1 @@\n+new\n");
+    }
+
+    #[test]
+    fn diff_should_detect_renames_in_working_tree() {
+        let (_dir, repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("1\n2\n3\n4\n"))], "first");
+        let head = repo
+            .tree_of(&repo.rev_parse_commit("HEAD").unwrap())
+            .unwrap();
+        repo.write_files(&[("a.txt", None), ("b.txt", Some("1\n2\n3\n4\n5\n"))]);
+        let renames = RenameOptions::default();
+
+        // untracked files are not renamed to
+        let diffs = repo.diff(
+            &repo.wrap(head.clone()),
+            DiffTarget::WorkingTree,
+            3,
+            Some(&renames),
+        );
+        let kinds: Vec<_> = diffs
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.kind, d.path))
+            .collect();
+        assert_eq!(kinds, [(ComparedKind::Deleted, "a.txt".to_string())]);
+
+        repo.add_files(&["a.txt", "b.txt"]);
+        let diffs = repo.diff(&repo.wrap(head), DiffTarget::WorkingTree, 3, Some(&renames));
+        let kinds: Vec<_> = diffs
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.kind, d.path))
+            .collect();
+        assert_eq!(
+            kinds,
+            [(
+                ComparedKind::Renamed { similarity: 80 },
+                "b.txt".to_string()
+            )]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn diff_should_keep_symlinks_and_executables() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let (_dir, repo) = temp_repo();
+        repo.write_files(&[("a.txt", Some("a\n")), ("run.sh", Some("echo a\n"))]);
+        let script = repo.working_dir().join("run.sh");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("a.txt", repo.working_dir().join("link")).unwrap();
+        repo.add_files(&["a.txt", "run.sh", "link"]);
+        repo.create_commit("init", None).unwrap();
+
+        let head = repo
+            .tree_of(&repo.rev_parse_commit("HEAD").unwrap())
+            .unwrap();
+        let kind_of = |name: &str| {
+            let line = head.objects.iter().find(|l| l.name == name).unwrap();
+            (line.kind.clone(), repo.blob_of(line, false).unwrap())
+        };
+        assert_eq!(
+            kind_of("link"),
+            (TreeLineKind::Symlink, Blob::from("a.txt"))
+        );
+        assert_eq!(kind_of("run.sh").0, TreeLineKind::Executable);
+        assert_eq!(kind_of("a.txt").0, TreeLineKind::File);
+        let diffs = repo.diff(&repo.wrap(head.clone()), DiffTarget::WorkingTree, 3, None);
+        assert!(diffs.unwrap().is_empty());
+
+        // only the mode is changed
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        let diffs = repo.diff(&repo.wrap(head), DiffTarget::WorkingTree, 3, None);
+        let diffs = diffs.unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(
+            diffs[0].to_string(),
+            "diff --git a/run.sh b/run.sh\nold mode 100755\nnew mode 100644\n"
+        );
+    }
+}
@@ -3,50 +3,128 @@ use crate::{
         Accessible,
         object::Object,
         repo::{Repository, WithRepo},
-        tree::Tree,
+        tree::{Tree, TreeLine, TreeLineKind},
     },
     services::tree::{ComparedKind, compare_trees},
 };
-use std::io;
+use std::{io, path::Path};
 
 pub trait DumpTreeService {
     /// Dump the tree to the working directory
     fn dump_tree(&self, tree: &WithRepo<'_, Tree>) -> io::Result<()>;
+    /// Dump the tree to the working directory, which is assumed to be the
+    /// `from` tree. Only the paths that differ between them are touched
+    fn dump_tree_from(&self, from: &WithRepo<'_, Tree>, to: &WithRepo<'_, Tree>) -> io::Result<()>;
+}
+
+impl Repository {
+    /// Write the file or the whole directory of the line to the path
+    pub fn dump_line(&self, line: &TreeLine, path: &Path) -> io::Result<()> {
+        // the path may be a file replaced by a directory, or the reverse
+        if line.kind == TreeLineKind::Tree {
+            if path.is_file() || path.is_symlink() {
+                std::fs::remove_file(path)?;
+            }
+            std::fs::create_dir_all(path)?;
+            let tree = Object::load_from(self, &line.sha1)?.cast_tree();
+            for line in &tree.objects {
+                self.dump_line(line, &path.join(&line.name))?;
+            }
+            return Ok(());
+        }
+
+        // a symlink is replaced rather than written through
+        if path.is_symlink() {
+            std::fs::remove_file(path)?;
+        } else if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        }
+        let blob = Object::load_from(self, &line.sha1)?.cast_blob();
+        // Ensure parent directories exist
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if line.kind == TreeLineKind::Symlink {
+            if path.is_file() {
+                std::fs::remove_file(path)?;
+            }
+            return write_symlink(blob.as_bytes(), path);
+        }
+        std::fs::write(path, blob.as_bytes())?;
+        set_executable(path, line.kind == TreeLineKind::Executable)
+    }
+}
+
+/// Create the symlink to the target, as it is recorded in the blob
+#[cfg(unix)]
+fn write_symlink(target: &[u8], path: &Path) -> io::Result<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    std::os::unix::fs::symlink(OsStr::from_bytes(target), path)
+}
+
+/// Without symlinks, git writes the target to a plain file
+#[cfg(not(unix))]
+fn write_symlink(target: &[u8], path: &Path) -> io::Result<()> {
+    std::fs::write(path, target)
+}
+
+/// Let whoever can read the file execute it, or no one, like git
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    let mode = if executable {
+        mode | (mode & 0o444) >> 2
+    } else {
+        mode & !0o111
+    };
+    if mode != permissions.mode() {
+        permissions.set_mode(mode);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_: &Path, _: bool) -> io::Result<()> {
+    Ok(())
 }
 
 impl DumpTreeService for Repository {
     fn dump_tree(&self, target_tree: &WithRepo<'_, Tree>) -> io::Result<()> {
-        let current_branch = self.head().load_branch()?;
-
-        let current_commit = current_branch.get_current_commit()?;
+        // the head may be detached from branches
+        let current = match self.head().detached() {
+            Some(sha1) => sha1.clone(),
+            None => self.head().load_branch()?.unwrap().head,
+        };
+        let current_commit = self
+            .wrap(Object::accessor(&current))
+            .load()?
+            .map(|c| c.cast_commit());
         let current_tree = current_commit.get_tree()?;
 
-        let changes = compare_trees(&current_tree, target_tree)?;
+        self.dump_tree_from(&current_tree, target_tree)
+    }
+
+    fn dump_tree_from(&self, from: &WithRepo<'_, Tree>, to: &WithRepo<'_, Tree>) -> io::Result<()> {
+        let changes = compare_trees(from, to)?;
 
         for change in changes {
+            let path = self.working_dir().join(&change.line.name);
             match change.kind {
-                ComparedKind::Added | ComparedKind::Modified => {
+                ComparedKind::Added | ComparedKind::Modified | ComparedKind::Copied { .. } => {
                     // Write new or modified files
-                    let blob = self
-                        .wrap(Object::accessor(&change.line.sha1))
-                        .load()?
-                        .clone()
-                        .cast_blob();
-                    // Emmm.. assuming workign dir is .git's parent @lhcfl maybe add pwd root in repo?
-                    let path = self.working_dir().join(&change.line.name);
-
-                    // Ensure parent directories exist
-                    if let Some(parent) = path.parent() {
-                        std::fs::create_dir_all(parent)?;
+                    self.dump_line(&change.line, &path)?;
+                }
+                ComparedKind::Renamed { .. } => {
+                    if let Some(old) = &change.old {
+                        std::fs::remove_file(self.working_dir().join(&old.name))?;
                     }
-
-                    // Write the file
-                    std::fs::write(&path, blob.as_bytes())?;
+                    self.dump_line(&change.line, &path)?;
                 }
                 ComparedKind::Deleted => {
                     // Remove deleted files
-                    let path = self.working_dir().join(&change.line.name);
-
                     if path.is_file() || path.is_symlink() {
                         std::fs::remove_file(&path)?;
                     } else if path.is_dir() {
@@ -59,3 +137,39 @@ impl DumpTreeService for Repository {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{branch::BranchService, commit::CommitService, testing::temp_repo};
+
+    #[cfg(unix)]
+    #[test]
+    fn checkout_should_write_symlinks_and_executables() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let (_dir, mut repo) = temp_repo();
+        repo.commit_files(
+            &[("a.txt", Some("a\n")), ("run.sh", Some("echo a\n"))],
+            "first",
+        );
+        repo.create_branch("feature", None).unwrap();
+        repo.checkout_branch("feature", false).unwrap();
+        let (script, link) = (
+            repo.working_dir().join("run.sh"),
+            repo.working_dir().join("link"),
+        );
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("a.txt", &link).unwrap();
+        repo.add_files(&["run.sh", "link"]);
+        repo.create_commit("feature", None).unwrap();
+        let mode = |path| fs::metadata(path).unwrap().permissions().mode();
+
+        repo.checkout_branch("master", false).unwrap();
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert_eq!(mode(&script) & 0o111, 0);
+
+        repo.checkout_branch("feature", false).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap().to_str(), Some("a.txt"));
+        assert_ne!(mode(&script) & 0o100, 0);
+    }
+}
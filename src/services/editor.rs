@@ -0,0 +1,66 @@
+//! Let the user edit files, such as commit messages, with their editor
+
+use crate::models::{config::Config, repo::Repository};
+use std::{env, fs, path::Path, process::Command};
+
+impl Repository {
+    /// The editor command, like git, it is `GIT_SEQUENCE_EDITOR` or
+    /// `sequence.editor` for todo lists, then `GIT_EDITOR`, `core.editor`,
+    /// `VISUAL`, `EDITOR`, or `vi` at last
+    fn editor(&self, sequence: bool) -> anyhow::Result<String> {
+        let config = Config::load(&self.root)?;
+        let sequence_editor = match sequence {
+            true => env::var("GIT_SEQUENCE_EDITOR")
+                .ok()
+                .or(config.get("sequence.editor").map(str::to_string)),
+            false => None,
+        };
+        Ok(sequence_editor
+            .or(env::var("GIT_EDITOR").ok())
+            .or(config.get("core.editor").map(str::to_string))
+            .or(env::var("VISUAL").ok())
+            .or(env::var("EDITOR").ok())
+            .unwrap_or_else(|| "vi".to_string()))
+    }
+
+    /// Open the file with the editor of the user, and wait for it to finish.
+    /// The editor is run by the shell, so it may have arguments
+    pub fn edit_file(&self, path: &Path, sequence: bool) -> anyhow::Result<()> {
+        let editor = self.editor(sequence)?;
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$@\""))
+            .arg(&editor)
+            .arg(path)
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("there was a problem with the editor '{editor}'");
+        }
+        Ok(())
+    }
+
+    /// Let the user edit the commit message in `COMMIT_EDITMSG`, lines
+    /// starting with `#` are removed
+    pub fn edit_message(&self, message: &str) -> anyhow::Result<String> {
+        let path = self.root.join("COMMIT_EDITMSG");
+        fs::write(
+            &path,
+            format!(
+                "{}\n\n# Please enter the commit message for your changes. Lines starting\n\
+                 # with '#' will be ignored, and an empty message aborts the commit.\n",
+                message.trim_end()
+            ),
+        )?;
+        self.edit_file(&path, false)?;
+        let edited = fs::read_to_string(&path)?;
+        let lines: Vec<_> = edited
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        let message = lines.join("\n").trim().to_string();
+        if message.is_empty() {
+            anyhow::bail!("Aborting commit due to empty commit message.");
+        }
+        Ok(message)
+    }
+}
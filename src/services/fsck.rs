@@ -0,0 +1,305 @@
+//! Verify the integrity of the object database and refs
+
+use crate::{
+    models::{
+        Accessible,
+        object::{GitObject, Object, ObjectSha1, RawObject, Sha1Able},
+        pack::Pack,
+        repo::Repository,
+        tree::TreeLineKind,
+    },
+    services::{branch::BranchService, reachable::ReachableService, tag::TagService},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io,
+};
+
+pub enum FsckProblem {
+    /// An object is referenced, but does not exist
+    Missing { sha1: ObjectSha1, referer: String },
+    /// An object can not be read, or its content does not match its sha1
+    Corrupt { sha1: ObjectSha1, reason: String },
+    /// An object is referenced as another type
+    WrongType {
+        sha1: ObjectSha1,
+        expected: &'static str,
+        found: &'static str,
+        referer: String,
+    },
+    /// A ref does not point to a valid commit
+    BadRef { name: String, reason: String },
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsckProblem::Missing { sha1, referer } => {
+                write!(f, "missing {sha1} (referenced by {referer})")
+            }
+            FsckProblem::Corrupt { sha1, reason } => write!(f, "corrupt {sha1}: {reason}"),
+            FsckProblem::WrongType {
+                sha1,
+                expected,
+                found,
+                referer,
+            } => write!(
+                f,
+                "wrong type {sha1}: expected {expected} but found {found} (referenced by {referer})"
+            ),
+            FsckProblem::BadRef { name, reason } => write!(f, "bad ref {name}: {reason}"),
+        }
+    }
+}
+
+pub struct FsckReport {
+    /// how many objects are checked
+    pub checked: usize,
+    pub problems: Vec<FsckProblem>,
+    /// commits which are not reachable from any ref, and no other commit
+    /// points to them. They are not errors, but may be lost work.
+    pub dangling: Vec<ObjectSha1>,
+}
+
+pub trait FsckService {
+    /// Check all objects, and everything reachable from refs and the stage
+    fn fsck(&self) -> io::Result<FsckReport>;
+}
+
+impl FsckService for Repository {
+    fn fsck(&self) -> io::Result<FsckReport> {
+        let mut problems = Vec::new();
+
+        // Step 1: every object should be readable, and re-hash to its name
+        let mut all = Object::loose_objects(&self.root)?
+            .into_iter()
+            .map(|(sha1, _)| sha1)
+            .collect::<HashSet<_>>();
+        for pack in Pack::all(&self.root)? {
+            all.extend(pack.index.sha1s());
+        }
+
+        let mut types = HashMap::new();
+        let mut parents = HashSet::new();
+        for sha1 in &all {
+            let raw = match Object::load_raw(&self.root, sha1) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    problems.push(FsckProblem::Corrupt {
+                        sha1: sha1.clone(),
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if raw.sha1() != **sha1 {
+                problems.push(FsckProblem::Corrupt {
+                    sha1: sha1.clone(),
+                    reason: format!("content hashes to {}", raw.sha1()),
+                });
+                continue;
+            }
+            match Object::try_from(raw) {
+                Ok(object) => {
+                    if let Object::Commit(commit) = &object {
+                        parents.extend(commit.parents.clone());
+                    }
+                    types.insert(sha1.clone(), object.object_type());
+                }
+                Err(e) => problems.push(FsckProblem::Corrupt {
+                    sha1: sha1.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+
+        // Step 2: walk from refs and the stage
+        // (sha1, expected type, referer)
+        let mut queue: Vec<(ObjectSha1, &'static str, String)> = Vec::new();
+        for name in self.list_branch()? {
+            // a broken ref is reported, and the others are still checked
+            let branch = match self.load_branch(&name) {
+                Ok(branch) => branch,
+                Err(e) => {
+                    problems.push(FsckProblem::BadRef {
+                        reason: e.to_string(),
+                        name,
+                    });
+                    continue;
+                }
+            };
+            // symbolic refs like `origin/HEAD` point to other branches
+            if branch.head.starts_with("ref: ") {
+                continue;
+            }
+            if !branch.head.is_valid() {
+                problems.push(FsckProblem::BadRef {
+                    reason: format!("invalid sha1 {}", branch.head),
+                    name,
+                });
+                continue;
+            }
+            queue.push((branch.unwrap().head, "commit", name));
+        }
+        for name in self.list_tags()? {
+            let target = self.load_tag(&name)?.unwrap().target;
+            // a lightweight tag may point to any object
+            let expected = types.get(&target).copied().unwrap_or("tag");
+            queue.push((target, expected, format!("tags/{name}")));
+        }
+        for (name, sha1) in self.state_roots()? {
+            queue.push((sha1, "commit", name));
+        }
+        // sub trees of the index are only kept in memory
+        let mut index = self.stage()?.unwrap().tree.objects;
+        while let Some(line) = index.pop() {
+            match self.load_unsaved(&line.sha1) {
+                Some(Object::Tree(tree)) => index.extend(tree.objects),
+                _ => queue.push((line.sha1, line_type(&line.kind), "index".to_string())),
+            }
+        }
+
+        let mut reachable = HashSet::new();
+        while let Some((sha1, expected, referer)) = queue.pop() {
+            if !reachable.insert(sha1.clone()) {
+                continue;
+            }
+            let Some(found) = types.get(&sha1).copied() else {
+                if !all.contains(&sha1) {
+                    problems.push(FsckProblem::Missing { sha1, referer });
+                }
+                // corrupt objects are already reported
+                continue;
+            };
+            if found != expected {
+                problems.push(FsckProblem::WrongType {
+                    sha1,
+                    expected,
+                    found,
+                    referer,
+                });
+                continue;
+            }
+
+            let referer = format!("{found} {sha1}");
+            match Object::load_from(self, &sha1)? {
+                Object::Commit(commit) => {
+                    queue.push((commit.tree, "tree", referer.clone()));
+                    for parent in commit.parents {
+                        queue.push((parent, "commit", referer.clone()));
+                    }
+                }
+                Object::Tree(tree) => {
+                    for line in tree.objects {
+                        queue.push((line.sha1, line_type(&line.kind), referer.clone()));
+                    }
+                }
+                Object::Tag(tag) => match RawObject::type_of(&tag.object_type) {
+                    Some(expected) => queue.push((tag.object, expected, referer)),
+                    None => problems.push(FsckProblem::Corrupt {
+                        reason: format!("unknown tagged object type {}", tag.object_type),
+                        sha1,
+                    }),
+                },
+                Object::Blob(_) => {}
+            }
+        }
+
+        // Step 3: find dangling commits
+        let mut dangling = types
+            .iter()
+            .filter(|(sha1, t)| **t == "commit" && !reachable.contains(*sha1))
+            .filter(|(sha1, _)| !parents.contains(*sha1))
+            .map(|(sha1, _)| sha1.clone())
+            .collect::<Vec<_>>();
+        dangling.sort();
+
+        Ok(FsckReport {
+            checked: all.len(),
+            problems,
+            dangling,
+        })
+    }
+}
+
+fn line_type(kind: &TreeLineKind) -> &'static str {
+    match kind {
+        TreeLineKind::Tree => "tree",
+        _ => "blob",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Store, blob::Blob},
+        services::testing::temp_repo,
+    };
+    use std::fs;
+
+    #[test]
+    fn fsck_should_walk_from_detached_head() {
+        let (dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        let second = repo.commit_files(&[("a.txt", Some("b\n"))], "second");
+        fs::write(repo.root.join("refs/heads/master"), format!("{first}\n")).unwrap();
+        fs::write(repo.root.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let repo = Repository::load_at(dir.path()).unwrap();
+        assert_eq!(repo.fsck().unwrap().dangling, vec![second.clone()]);
+
+        // only the detached HEAD points to the second commit
+        fs::write(repo.root.join("HEAD"), format!("{second}\n")).unwrap();
+        let repo = Repository::load_at(dir.path()).unwrap();
+        let report = repo.fsck().unwrap();
+        assert!(report.problems.is_empty());
+        assert!(report.dangling.is_empty());
+
+        fs::remove_file(repo.root.join(Object::path_of(&second))).unwrap();
+        let problems = repo.fsck().unwrap().problems;
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].to_string(),
+            format!("missing {second} (referenced by HEAD)")
+        );
+    }
+
+    #[test]
+    fn fsck_should_report_all_problems() {
+        let (_dir, repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n")), ("b.txt", Some("b\n"))], "init");
+        assert!(repo.fsck().unwrap().problems.is_empty());
+
+        // checking the index does not save its sub trees
+        repo.write_files(&[("d/c.txt", Some("c\n"))]);
+        repo.add_files(&["d/c.txt"]);
+        for subtree in repo.stage().unwrap().unwrap().subtrees.into_values() {
+            fs::remove_file(repo.root.join(Object::Tree(subtree).location())).unwrap();
+        }
+        let loose = Object::loose_objects(&repo.root).unwrap().len();
+        assert!(repo.fsck().unwrap().problems.is_empty());
+        assert_eq!(Object::loose_objects(&repo.root).unwrap().len(), loose);
+
+        // the blob of a.txt is missing, and b.txt is corrupt
+        let path_of = |content: &str| repo.root.join(Object::Blob(Blob::from(content)).location());
+        fs::remove_file(path_of("a\n")).unwrap();
+        fs::write(path_of("b\n"), b"not zlib").unwrap();
+        // neither of the refs aborts the check
+        fs::write(repo.root.join("refs/heads/bad"), "not a sha1\n").unwrap();
+        fs::write(repo.root.join("refs/heads/binary"), [0xff, 0xfe]).unwrap();
+
+        let problems: Vec<_> = repo
+            .fsck()
+            .unwrap()
+            .problems
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let has = |prefix: &str| problems.iter().any(|p| p.starts_with(prefix));
+        assert!(has("missing 78981922613b2afb6025042ff6bd878ac1994e85"));
+        assert!(has("corrupt 61780798228d17af2d34fce4cfbdf35556832472"));
+        assert!(has("bad ref bad: invalid sha1 not a sha1"));
+        assert!(has("bad ref binary: "));
+    }
+}
@@ -0,0 +1,191 @@
+//! Pack loose objects to save space
+
+use crate::{
+    models::{
+        delta,
+        object::{Object, ObjectSha1, RawObject},
+        pack::{MAX_DELTA_DEPTH, Pack, PackEntry, write_pack},
+        repo::Repository,
+        tree::TreeLineKind,
+    },
+    services::{
+        branch::BranchService,
+        object::ObjectService,
+        prune::{DEFAULT_PRUNE_EXPIRE, PruneService, parse_expire},
+    },
+};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs, io,
+    time::SystemTime,
+};
+
+/// How many previous objects are tried as the delta base of an object
+const DELTA_WINDOW: usize = 10;
+/// Objects smaller than it are never deltified
+const MIN_DELTA_SIZE: usize = 64;
+
+pub struct GcInfo {
+    /// how many objects are packed
+    pub objects: usize,
+    /// how many objects are stored as deltas
+    pub deltas: usize,
+    /// sha1 of the new pack
+    pub pack: String,
+}
+
+pub trait GcService {
+    /// Prune unreachable objects older than [DEFAULT_PRUNE_EXPIRE], then pack
+    /// all objects into a single pack, and remove the loose objects and old
+    /// packs
+    fn gc(&self) -> io::Result<GcInfo>;
+}
+
+impl Repository {
+    /// Name objects by the file name they are reachable with. Objects with the
+    /// same name are likely to be good delta bases of each other.
+    fn object_names(&self) -> HashMap<ObjectSha1, String> {
+        let mut names = HashMap::new();
+        let mut commits = Vec::new();
+        let mut trees = Vec::new();
+
+        for branch in self.list_branch().unwrap_or_default() {
+            if let Ok(branch) = self.load_branch(&branch) {
+                commits.push(branch.unwrap().head);
+            }
+        }
+        if let Ok(stage) = self.stage() {
+            trees.push(stage.unwrap().tree);
+        }
+
+        while let Some(sha1) = commits.pop() {
+            if names.insert(sha1.clone(), String::new()).is_some() {
+                continue;
+            }
+            if let Ok(Object::Commit(commit)) = self.load_object(&sha1).map(|o| o.unwrap()) {
+                commits.extend(commit.parents);
+                names.insert(commit.tree.clone(), String::new());
+                if let Ok(Object::Tree(tree)) = self.load_object(&commit.tree).map(|o| o.unwrap()) {
+                    trees.push(tree);
+                }
+            }
+        }
+        while let Some(tree) = trees.pop() {
+            for line in tree.objects {
+                if names.contains_key(&line.sha1) {
+                    continue;
+                }
+                names.insert(line.sha1.clone(), line.name);
+                if line.kind == TreeLineKind::Tree
+                    && let Ok(Object::Tree(tree)) = self.load_object(&line.sha1).map(|o| o.unwrap())
+                {
+                    trees.push(tree);
+                }
+            }
+        }
+
+        names
+    }
+}
+
+/// Deltify objects with previous objects in the window, the order of objects
+/// is kept so delta bases are always written first.
+fn deltify(objects: Vec<(ObjectSha1, RawObject)>) -> Vec<PackEntry> {
+    let mut entries = Vec::with_capacity(objects.len());
+    let mut depths = vec![0; objects.len()];
+
+    for (i, (sha1, raw)) in objects.iter().enumerate() {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        if raw.content.len() >= MIN_DELTA_SIZE {
+            for base in i.saturating_sub(DELTA_WINDOW)..i {
+                let base_raw = &objects[base].1;
+                if base_raw.object_type != raw.object_type || depths[base] >= MAX_DELTA_DEPTH {
+                    continue;
+                }
+                // like git, a delta is only worth it if it is much smaller
+                let max_size = best
+                    .as_ref()
+                    .map(|(_, d)| d.len())
+                    .unwrap_or(raw.content.len() / 2 - 20);
+                let delta = delta::create(&base_raw.content, &raw.content);
+                if delta.len() < max_size {
+                    best = Some((base, delta));
+                }
+            }
+        }
+
+        match best {
+            Some((base, delta)) => {
+                depths[i] = depths[base] + 1;
+                entries.push(PackEntry::Delta {
+                    sha1: sha1.clone(),
+                    base,
+                    delta,
+                });
+            }
+            None => entries.push(PackEntry::Full(raw.clone())),
+        }
+    }
+
+    entries
+}
+
+impl GcService for Repository {
+    fn gc(&self) -> io::Result<GcInfo> {
+        let expire = parse_expire(DEFAULT_PRUNE_EXPIRE, SystemTime::now()).transpose()?;
+        self.prune(expire, false)?;
+
+        let loose = Object::loose_objects(&self.root)?;
+        let packs = Pack::all(&self.root)?;
+
+        let mut sha1s = loose.iter().map(|(s, _)| s.clone()).collect::<HashSet<_>>();
+        for pack in &packs {
+            sha1s.extend(pack.index.sha1s());
+        }
+
+        let names = self.object_names();
+        let mut objects = Vec::with_capacity(sha1s.len());
+        for sha1 in sha1s {
+            let raw = Object::load_raw(&self.root, &sha1)?;
+            objects.push((sha1, raw));
+        }
+        // sort similar objects together, and bigger objects first, because
+        // removing content produces smaller deltas than adding it
+        objects.sort_by_cached_key(|(sha1, raw)| {
+            (
+                raw.object_type,
+                names.get(sha1).cloned().unwrap_or_default(),
+                Reverse(raw.content.len()),
+            )
+        });
+
+        let count = objects.len();
+        let entries = deltify(objects);
+        let deltas = entries
+            .iter()
+            .filter(|e| matches!(e, PackEntry::Delta { .. }))
+            .count();
+        let name = write_pack(&self.root, &entries)?;
+
+        for pack in packs {
+            if pack.path.file_stem() != Some(format!("pack-{name}").as_ref()) {
+                fs::remove_file(pack.path.with_extension("idx"))?;
+                fs::remove_file(&pack.path)?;
+            }
+        }
+        for (_, path) in loose {
+            fs::remove_file(&path)?;
+            if let Some(dir) = path.parent() {
+                // only removes the dir when it is empty
+                let _ = fs::remove_dir(dir);
+            }
+        }
+
+        Ok(GcInfo {
+            objects: count,
+            deltas,
+            pack: name,
+        })
+    }
+}
@@ -0,0 +1,134 @@
+//! Who is making commits and tags, and when
+
+use crate::models::{
+    commit::{DEFAULT_EMAIL, DEFAULT_NAME, Signature, parse_offset},
+    config::Config,
+    repo::Repository,
+};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::env;
+
+/// The role of an identity, see [Repository::signature]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+/// Parse a date like git does, in its internal format `1700000000 +0800`,
+/// RFC 2822 like `Tue, 14 Nov 2023 22:13:20 +0800`, or ISO 8601 like
+/// `2023-11-14 22:13:20 +0800`. The local timezone is used if it is not given.
+/// Returns the timestamp and the offset from UTC in minutes
+pub fn parse_date(date: &str) -> Option<(i64, i32)> {
+    let date = date.trim();
+    let internal = date.strip_prefix('@').unwrap_or(date);
+    if let Some((timestamp, tz)) = internal.split_once(' ')
+        && let Ok(timestamp) = timestamp.parse()
+    {
+        return Some((timestamp, parse_offset(tz)?));
+    }
+    if let Ok(timestamp) = internal.parse::<i64>() {
+        let offset = Local
+            .timestamp_opt(timestamp, 0)
+            .single()?
+            .offset()
+            .local_minus_utc();
+        return Some((timestamp, offset / 60));
+    }
+
+    let with_tz = DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"));
+    if let Ok(time) = with_tz {
+        return Some((time.timestamp(), time.offset().local_minus_utc() / 60));
+    }
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S"))
+        .ok()?;
+    let time = Local.from_local_datetime(&naive).earliest()?;
+    Some((time.timestamp(), time.offset().local_minus_utc() / 60))
+}
+
+/// Parse an identity like `A U Thor <author@example.com>`
+pub fn parse_ident(ident: &str) -> Option<(String, String)> {
+    let (name, email) = ident.trim().strip_suffix('>')?.split_once('<')?;
+    Some((name.trim().to_string(), email.to_string()))
+}
+
+impl Repository {
+    /// The identity and the current time of the role, like git, they are from
+    /// `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` and `GIT_AUTHOR_DATE` (or
+    /// `GIT_COMMITTER_*`), then `author.name` (or `committer.name`) and
+    /// `user.name` in the config. [DEFAULT_NAME] and [DEFAULT_EMAIL] are used
+    /// if nothing is configured
+    pub fn signature(&self, role: Role) -> anyhow::Result<Signature> {
+        let config = Config::load(&self.root)?;
+        let (var, section) = match role {
+            Role::Author => ("GIT_AUTHOR", "author"),
+            Role::Committer => ("GIT_COMMITTER", "committer"),
+        };
+        let get = |field: &str| {
+            env::var(format!("{var}_{}", field.to_uppercase()))
+                .ok()
+                .or_else(|| {
+                    config
+                        .get(&format!("{section}.{field}"))
+                        .map(str::to_string)
+                })
+                .or_else(|| config.get(&format!("user.{field}")).map(str::to_string))
+        };
+
+        let (timestamp, offset) = match env::var(format!("{var}_DATE")) {
+            Ok(date) => {
+                parse_date(&date).ok_or_else(|| anyhow::anyhow!("invalid date format: {date}"))?
+            }
+            Err(_) => {
+                let now = Local::now();
+                (now.timestamp(), now.offset().local_minus_utc() / 60)
+            }
+        };
+        Ok(Signature {
+            name: get("name").unwrap_or_else(|| DEFAULT_NAME.to_string()),
+            email: get("email").unwrap_or_else(|| DEFAULT_EMAIL.to_string()),
+            timestamp,
+            offset,
+            raw: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1700000000 +0800"), Some((1700000000, 480)));
+        assert_eq!(parse_date("@1700000000 -0500"), Some((1700000000, -300)));
+        assert_eq!(
+            parse_date("Tue, 14 Nov 2023 22:13:20 +0800"),
+            Some((1699971200, 480))
+        );
+        assert_eq!(
+            parse_date("2023-11-14 22:13:20 +0800"),
+            Some((1699971200, 480))
+        );
+        assert_eq!(
+            parse_date("2023-11-14T22:13:20+08:00"),
+            Some((1699971200, 480))
+        );
+        assert_eq!(parse_date("1700000000 0800"), None);
+        assert_eq!(parse_date("1700000000 +9999"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_ident() {
+        assert_eq!(
+            parse_ident("A U Thor <author@example.com>"),
+            Some(("A U Thor".to_string(), "author@example.com".to_string()))
+        );
+        assert_eq!(parse_ident("A U Thor"), None);
+    }
+}
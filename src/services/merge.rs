@@ -1,129 +1,1036 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashSet;
 
-use crate::models::commit::{Commit, CommitBuilder};
-use crate::models::object::{Object, Sha1Able};
+use crate::models::attributes::Attributes;
+use crate::models::commit::{Commit, CommitBuilder, Signature};
+use crate::models::config::Config;
+use crate::models::merge_state::MergeState;
+use crate::models::object::{Object, ObjectSha1, Sha1Able};
 use crate::models::stage::Stage;
-use crate::models::{Accessible, branch::Branch, repo::Repository};
-use crate::oj_output;
+use crate::models::tree::{Tree, TreeLine, TreeLineKind};
+use crate::models::{Accessible, repo::Repository};
+use crate::services::branch::BranchService;
+use crate::services::commit::{CommitCreateResult, CommitService};
 use crate::services::dump_tree::DumpTreeService;
-use crate::services::tree::auto_merge_trees;
+use crate::services::identity::Role;
+use crate::services::merge_file::{ConflictStyle, Favor, MergeFileOptions};
+use crate::services::object::ObjectService;
+use crate::services::rev_list::RevListService;
+use crate::services::tree::{
+    ComparedKind, Conflict, ConflictKind, MergeTreeOptions, RenameOptions, align_renames,
+    auto_merge_trees, compare_trees, path_matches,
+};
+use crate::{console_output, oj_output};
+
+/// Whether to fast-forward the branch when it is an ancestor of the merged
+/// commit, instead of creating a merge commit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FastForward {
+    /// fast-forward if possible, otherwise create a merge commit
+    #[default]
+    Allow,
+    /// fast-forward, or fail if not possible (`--ff-only`)
+    Only,
+    /// always create a merge commit (`--no-ff`)
+    Never,
+}
+
+/// The merge strategy, `-s`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// three-way merge, with the merged common ancestors as the base
+    #[default]
+    Ort,
+    /// record the merge, but keep the tree of ours
+    Ours,
+}
+
+impl Strategy {
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            // `recursive` is the old name of `ort`
+            "ort" | "recursive" => Ok(Strategy::Ort),
+            "ours" => Ok(Strategy::Ours),
+            other => anyhow::bail!(
+                "Could not find merge strategy '{other}'.\n\
+                 Available strategies are: ort recursive ours."
+            ),
+        }
+    }
+}
+
+/// Options of `merge`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    pub fast_forward: FastForward,
+    pub strategy: Strategy,
+    /// the side taken for conflicting chunks, `-X ours` or `-X theirs`
+    pub favor: Favor,
+    /// only update the index and the working tree, without committing or
+    /// recording the merge (`--squash`)
+    pub squash: bool,
+}
 
 pub trait MergeService {
-    fn merge(&self, theirs_branch: Branch) -> anyhow::Result<()>;
-    fn get_merge_base(&self, commit1: &Commit, commit2: &Commit) -> anyhow::Result<Commit>;
+    /// Merge the commit `theirs` into the current branch, `theirs_name` is the
+    /// revision it is named by, used in the commit message
+    fn merge(
+        &self,
+        theirs: &ObjectSha1,
+        theirs_name: &str,
+        options: &MergeOptions,
+    ) -> anyhow::Result<()>;
+    /// Merge the commits into the current branch at once, with a commit of
+    /// all of them as the parents. It fails without changing anything if
+    /// there are conflicts. `heads` are the commits and their names
+    fn merge_octopus(
+        &self,
+        heads: &[(ObjectSha1, String)],
+        options: &MergeOptions,
+    ) -> anyhow::Result<()>;
+    /// Conclude the merge stopped by conflicts, after they are resolved
+    fn merge_continue(&self) -> anyhow::Result<CommitCreateResult>;
+    /// Abort the merge stopped by conflicts, and restore the index and the
+    /// working tree to `HEAD`
+    fn merge_abort(&self) -> anyhow::Result<()>;
+    /// All best common ancestors of the commits, that is, common ancestors
+    /// which are not ancestors of other common ancestors, newer first
+    fn merge_bases(&self, a: &ObjectSha1, b: &ObjectSha1) -> anyhow::Result<Vec<ObjectSha1>>;
+    /// The best common ancestors of `a` and a merge of all `others`, like
+    /// `git merge-base --all a others...`
+    fn merge_bases_many(
+        &self,
+        a: &ObjectSha1,
+        others: &[ObjectSha1],
+    ) -> anyhow::Result<Vec<ObjectSha1>>;
+    /// Whether `ancestor` is reachable from `commit`, a commit is an ancestor
+    /// of itself
+    fn is_ancestor(&self, ancestor: &ObjectSha1, commit: &ObjectSha1) -> anyhow::Result<bool>;
+    /// Merge the commits without touching the index or the working
+    /// directory, only objects are written. `names` of ours and theirs label
+    /// the conflicts
+    fn merge_tree(
+        &self,
+        ours: &ObjectSha1,
+        theirs: &ObjectSha1,
+        names: [&str; 2],
+        options: &MergeOptions,
+    ) -> anyhow::Result<MergedTree>;
+}
+
+/// The result of [MergeService::merge_tree]
+pub struct MergedTree {
+    /// the merged tree, which is saved. Conflicted files have conflict
+    /// markers, or are the version of ours
+    pub tree: Tree,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Tell the user about a conflict
+pub fn print_conflict(conflict: &Conflict, ours: &str, theirs: &str) {
+    match conflict.kind {
+        ConflictKind::Content {
+            line_start,
+            line_end,
+        } => {
+            if line_start == line_end {
+                oj_output!("Merge conflict in {}: {}", conflict.file, line_start);
+            } else {
+                oj_output!(
+                    "Merge conflict in {}: [{}, {}]",
+                    conflict.file,
+                    line_start,
+                    line_end
+                );
+            }
+        }
+        _ => {
+            oj_output!("{}", conflict.message(ours, theirs));
+        }
+    }
+}
+
+/// Join the names like `'a', 'b' and 'c'`
+fn join_names(names: &[&str]) -> String {
+    let quoted: Vec<_> = names.iter().map(|name| format!("'{name}'")).collect();
+    match quoted.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+        _ => quoted.concat(),
+    }
+}
+
+impl Repository {
+    /// The message of merging the revisions, like
+    /// `Merge branches 'a' and 'b'; commit 'c'`
+    fn merge_message(&self, names: &[&str]) -> anyhow::Result<String> {
+        let (mut branches, mut commits) = (Vec::new(), Vec::new());
+        for &name in names {
+            if self.branch_exists(name)? {
+                branches.push(name);
+            } else {
+                commits.push(name);
+            }
+        }
+        let mut parts = Vec::new();
+        for (names, one, many) in [
+            (branches, "branch", "branches"),
+            (commits, "commit", "commits"),
+        ] {
+            match names.len() {
+                0 => {}
+                1 => parts.push(format!("{one} {}", join_names(&names))),
+                _ => parts.push(format!("{many} {}", join_names(&names))),
+            }
+        }
+        Ok(format!("Merge {}", parts.join("; ")))
+    }
+
+    /// Fail if a merge is in progress, or the index is not clean, so that it
+    /// can be restored by `merge --abort`
+    fn check_can_merge(&self) -> anyhow::Result<()> {
+        if self.merge_state()?.is_some() {
+            anyhow::bail!(
+                "You have not concluded your merge (MERGE_HEAD exists).\n\
+                 Please, commit your changes before you merge."
+            );
+        }
+        let head_commit = self.load_commit(&self.head().load_branch()?.head)?;
+        let stage = self.stage()?;
+        if !stage.unmerged.is_empty() || stage.tree.sha1() != head_commit.tree.as_str() {
+            anyhow::bail!(
+                "Your local changes would be overwritten by merge.\n\
+                 Please commit your changes before you merge."
+            );
+        }
+        Ok(())
+    }
+
+    /// Options of merging trees into `HEAD`, from the config and the
+    /// attributes
+    pub fn merge_tree_options(
+        &self,
+        options: &MergeOptions,
+        bases: &[ObjectSha1],
+        [ours_name, theirs_name]: [&str; 2],
+    ) -> anyhow::Result<MergeTreeOptions> {
+        let config = Config::load(&self.root)?;
+        let base_label = match bases {
+            [base] => base.as_str()[..7].to_string(),
+            _ => "merged common ancestors".to_string(),
+        };
+        Ok(MergeTreeOptions {
+            file: MergeFileOptions {
+                style: ConflictStyle::from_config(config.get("merge.conflictStyle"))?,
+                labels: [ours_name.to_string(), base_label, theirs_name.to_string()],
+                favor: options.favor,
+            },
+            attributes: Attributes::load(&self.root)?,
+        })
+    }
+
+    /// Write the merged tree with conflicts to the working directory, and
+    /// record the conflicts in the index. Returns the conflicted files
+    pub fn write_conflicts(
+        &self,
+        tree: Tree,
+        conflicts: &[Conflict],
+    ) -> anyhow::Result<Vec<String>> {
+        let tree = self.wrap(tree);
+        self.dump_tree(&tree)?;
+        let mut stage = Stage::from(tree.unwrap());
+        for conflict in conflicts {
+            stage
+                .unmerged
+                .insert(conflict.file.clone(), conflict.versions.clone());
+        }
+        let files = stage.unmerged.keys().cloned().collect();
+        self.wrap(stage).save()?;
+        Ok(files)
+    }
+
+    /// Write the merged tree to the working directory and the index, and
+    /// commit it with the parents to the current branch, unless squashing.
+    /// The author is the current one, unless it is given, such as the author
+    /// of a cherry-picked commit
+    pub fn commit_merge(
+        &self,
+        tree: Tree,
+        parents: Vec<ObjectSha1>,
+        message: String,
+        author: Option<Signature>,
+        squash: bool,
+    ) -> anyhow::Result<()> {
+        self.save_tree(&tree)?;
+        let tree = self.wrap(Object::Tree(tree));
+        let tree_sha1 = tree.sha1();
+        let tree = tree.map(|t| t.cast_tree());
+        self.dump_tree(&tree)?;
+        tree.map(Stage::from).save()?;
+        if squash {
+            console_output!("Squash commit -- not updating HEAD");
+            console_output!("Automatic merge went well; stopped before committing as requested");
+            return Ok(());
+        }
+
+        let author = match author {
+            Some(author) => author,
+            None => self.signature(Role::Author)?,
+        };
+        let commit = Commit::new(CommitBuilder {
+            tree: tree_sha1.into(),
+            parents,
+            author,
+            committer: self.signature(Role::Committer)?,
+            message,
+        });
+        let sha1 = commit.sha1();
+        self.wrap(Object::Commit(commit)).save()?;
+        let mut branch = self.head().load_branch()?.cloned();
+        branch.head = sha1.into();
+        branch.save()?;
+        Ok(())
+    }
+
+    /// Three-way merge of the trees, following renames on either side
+    pub fn merge_trees(
+        &self,
+        base: Tree,
+        ours: Tree,
+        theirs: Tree,
+        options: &MergeTreeOptions,
+    ) -> anyhow::Result<(Tree, Vec<Conflict>)> {
+        let (base, ours, theirs) = (self.wrap(base), self.wrap(ours), self.wrap(theirs));
+        let Some(renames) = RenameOptions::from_config(&Config::load(&self.root)?)? else {
+            return auto_merge_trees(&base, &ours, &theirs, options);
+        };
+        let (base, ours, theirs) = align_renames(&base, &ours, &theirs, &renames)?;
+        auto_merge_trees(
+            &self.wrap(base),
+            &self.wrap(ours),
+            &self.wrap(theirs),
+            options,
+        )
+    }
+
+    /// The line at the path in the tree, looking into subtrees
+    fn line_at(&self, tree: &Tree, path: &str) -> anyhow::Result<Option<TreeLine>> {
+        let (name, rest) = match path.split_once('/') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path, None),
+        };
+        let Some(line) = tree.objects.iter().find(|line| line.name == name) else {
+            return Ok(None);
+        };
+        match rest {
+            None => Ok(Some(line.clone())),
+            Some(_) if line.kind != TreeLineKind::Tree => Ok(None),
+            Some(rest) => {
+                let subtree = Object::load_from(self, &line.sha1)?.cast_tree();
+                self.line_at(&subtree, rest)
+            }
+        }
+    }
+
+    /// Merge the best common ancestors into a virtual one, recursively like
+    /// git's `ort` strategy. Conflicts are kept with conflict markers, and the
+    /// virtual commit is only kept in memory
+    fn virtual_merge_base(
+        &self,
+        bases: &[ObjectSha1],
+        options: &MergeTreeOptions,
+    ) -> anyhow::Result<Option<ObjectSha1>> {
+        let Some((first, rest)) = bases.split_first() else {
+            return Ok(None);
+        };
+
+        let mut merged = first.clone();
+        for next in rest {
+            let inner_bases = self.merge_bases(&merged, next)?;
+            let base_tree = match self.virtual_merge_base(&inner_bases, options)? {
+                Some(base) => self.tree_of(&base)?,
+                None => Tree::empty(),
+            };
+            // conflicts are kept, even if a side is favored
+            let options = MergeTreeOptions {
+                file: MergeFileOptions {
+                    style: options.file.style,
+                    labels: [
+                        "Temporary merge branch 1".to_string(),
+                        "merged common ancestors".to_string(),
+                        "Temporary merge branch 2".to_string(),
+                    ],
+                    favor: Favor::Neither,
+                },
+                attributes: options.attributes.clone(),
+            };
+            let (tree, _) = self.merge_trees(
+                base_tree,
+                self.tree_of(&merged)?,
+                self.tree_of(next)?,
+                &options,
+            )?;
+
+            let tree = Object::Tree(tree);
+            let commit = Commit::new(CommitBuilder {
+                tree: tree.sha1().into(),
+                parents: vec![merged, next.clone()],
+                author: self.signature(Role::Author)?,
+                committer: self.signature(Role::Committer)?,
+                message: "merged common ancestors".to_string(),
+            });
+            merged = commit.sha1().into();
+            self.keep_unsaved(tree);
+            self.keep_unsaved(Object::Commit(commit));
+        }
+        Ok(Some(merged))
+    }
+
+    /// Files with local changes in the working tree, compared with `ours`,
+    /// which would be overwritten by writing `target` over `ours`
+    pub fn overwritten_files(&self, ours: &Tree, target: &Tree) -> anyhow::Result<Vec<String>> {
+        let ours = self.wrap(ours.clone());
+        let changed: Vec<_> = compare_trees(&ours, &self.wrap(target.clone()))?
+            .into_iter()
+            .map(|change| change.line.name)
+            .collect();
+        Ok(compare_trees(&ours, &self.working_tree()?)?
+            .into_iter()
+            .filter(|change| change.kind != ComparedKind::Added)
+            .map(|change| change.line.name)
+            .filter(|name| {
+                changed
+                    .iter()
+                    .any(|path| path_matches(path, name) || path_matches(name, path))
+            })
+            .collect())
+    }
+
+    /// Fail like git if writing `target` over `ours` would overwrite local
+    /// changes in the working tree
+    pub fn check_overwritten(&self, ours: &Tree, target: &Tree) -> anyhow::Result<()> {
+        let overwritten = self.overwritten_files(ours, target)?;
+        if !overwritten.is_empty() {
+            anyhow::bail!(
+                "Your local changes to the following files would be overwritten by merge:\n\t{}\n\
+                 Please commit your changes or stash them before you merge.\nAborting",
+                overwritten.join("\n\t")
+            );
+        }
+        Ok(())
+    }
 }
 
 impl MergeService for Repository {
-    /// Merge another branch into the current branch.
+    /// Merge another commit into the current branch.
     ///
-    /// This method will merge the specified branch into the current branch.
+    /// This method will merge the specified commit into the current branch.
     /// It will handle conflicts and return an error if the merge fails.
-    fn merge(&self, theirs_branch: Branch) -> anyhow::Result<()> {
-        let theirs_branch = self.wrap(theirs_branch);
+    fn merge(
+        &self,
+        theirs: &ObjectSha1,
+        theirs_name: &str,
+        options: &MergeOptions,
+    ) -> anyhow::Result<()> {
+        let fast_forward = options.fast_forward;
+        self.check_can_merge()?;
         let ours_branch = self.head().load_branch()?;
-        let ours_commit = ours_branch.get_current_commit()?;
-        let theirs_commit = theirs_branch.get_current_commit()?;
-        let base_commit = self.get_merge_base(&ours_commit, &theirs_commit)?;
-
-        let base_tree = self
-            .wrap(Object::accessor(&base_commit.tree))
-            .load()?
-            .map(|t| t.cast_tree());
-        let ours_tree = self
-            .wrap(Object::accessor(&ours_commit.tree))
-            .load()?
-            .map(|t| t.cast_tree());
-        let theirs_tree = self
-            .wrap(Object::accessor(&theirs_commit.tree))
-            .load()?
-            .map(|t| t.cast_tree());
-
-        let (merged_tree, conflicts) = auto_merge_trees(&base_tree, &ours_tree, &theirs_tree)?;
-
-        // ⚠️ 有冲突，输出冲突提示
-        if !conflicts.is_empty() {
-            for conflict in conflicts {
-                if conflict.line_start == conflict.line_end {
-                    oj_output!(
-                        "Merge conflict in {}: {}",
-                        conflict.file,
-                        conflict.line_start
-                    );
-                } else {
-                    oj_output!(
-                        "Merge conflict in {}: [{}, {}]",
-                        conflict.file,
-                        conflict.line_start,
-                        conflict.line_end
-                    );
-                }
+        if self.is_ancestor(theirs, &ours_branch.head)? {
+            console_output!("Already up to date.");
+            return Ok(());
+        }
+
+        if fast_forward != FastForward::Never && self.is_ancestor(&ours_branch.head, theirs)? {
+            console_output!(
+                "Updating {}..{}",
+                &ours_branch.head.as_str()[..7],
+                &theirs.as_str()[..7]
+            );
+            let theirs_tree = self.tree_of(theirs)?;
+            self.check_overwritten(&self.tree_of(&ours_branch.head)?, &theirs_tree)?;
+            console_output!("Fast-forward");
+            let theirs_tree = self.wrap(theirs_tree);
+            self.dump_tree(&theirs_tree)?;
+            theirs_tree.map(Stage::from).save()?;
+            if options.squash {
+                console_output!("Squash commit -- not updating HEAD");
+                return Ok(());
             }
+
+            let mut ours_branch = ours_branch.cloned();
+            ours_branch.head = theirs.clone();
+            ours_branch.save()?;
             return Ok(());
         }
+        if fast_forward == FastForward::Only {
+            anyhow::bail!("Not possible to fast-forward, aborting.");
+        }
 
-        // ✅ 无冲突，生成合并提交
-        let merged_tree_obj = self.wrap(Object::Tree(merged_tree));
-        merged_tree_obj.save()?;
+        let names = ["HEAD", theirs_name];
+        let MergedTree {
+            tree: merged_tree,
+            conflicts,
+        } = self.merge_tree(&ours_branch.head, theirs, names, options)?;
+        self.check_overwritten(&self.tree_of(&ours_branch.head)?, &merged_tree)?;
+        let message = self.merge_message(&[theirs_name])?;
 
-        let tree_sha1 = merged_tree_obj.sha1();
+        // ⚠️ 有冲突，把带冲突标记的文件写入工作区，并输出冲突提示
+        if !conflicts.is_empty() {
+            // record the conflicts in the index, and the merge state
+            let mut message = format!("{message}\n\n# Conflicts:\n");
+            for file in self.write_conflicts(merged_tree, &conflicts)? {
+                message.push_str(&format!("#\t{file}\n"));
+            }
+            // a squash is not recorded as a merge
+            if !options.squash {
+                self.wrap(MergeState {
+                    theirs: theirs.clone(),
+                    message,
+                })
+                .save()?;
+            }
 
-        let message = format!("Merge branch '{}'", theirs_branch.name);
+            for conflict in conflicts {
+                print_conflict(&conflict, names[0], names[1]);
+            }
+            if options.squash {
+                console_output!("Squash commit -- not updating HEAD");
+            }
+            console_output!("Automatic merge failed; fix conflicts and then commit the result.");
+            return Ok(());
+        }
 
-        let merge_commit = Commit::new(CommitBuilder {
-            tree: tree_sha1.into(),
-            parent: Some(ours_commit.sha1().into()),
-            message,
-        });
+        // ✅ 无冲突，生成合并提交
+        let parents = vec![ours_branch.head.clone(), theirs.clone()];
+        self.commit_merge(merged_tree, parents, message, None, options.squash)
+    }
 
-        let sha1 = merge_commit.sha1();
-        self.wrap(Object::Commit(merge_commit)).save()?;
+    fn merge_octopus(
+        &self,
+        heads: &[(ObjectSha1, String)],
+        options: &MergeOptions,
+    ) -> anyhow::Result<()> {
+        self.check_can_merge()?;
+        let ours_branch = self.head().load_branch()?;
+        let mut remaining = Vec::new();
+        for (head, name) in heads {
+            if self.is_ancestor(head, &ours_branch.head)? {
+                console_output!("Already up to date with {name}");
+            } else {
+                remaining.push((head, name));
+            }
+        }
+        // heads which are ancestors of other heads are merged by them
+        let mut independent = Vec::new();
+        for (i, (head, name)) in remaining.iter().enumerate() {
+            let mut reachable = false;
+            for (j, (other, _)) in remaining.iter().enumerate() {
+                // of the same heads, the first is kept
+                if i != j && (head != other || j < i) && self.is_ancestor(head, other)? {
+                    reachable = true;
+                    break;
+                }
+            }
+            if !reachable {
+                independent.push((*head, *name));
+            }
+        }
+        let remaining = independent;
+        match remaining.as_slice() {
+            [] => {
+                console_output!("Already up to date.");
+                return Ok(());
+            }
+            [(head, name)] => return self.merge(head, name, options),
+            _ => {}
+        }
+        if options.fast_forward == FastForward::Only {
+            anyhow::bail!("Not possible to fast-forward, aborting.");
+        }
 
-        let merged_tree = merged_tree_obj.map(|t| t.cast_tree());
+        // merge the heads one by one into the tree, nothing is written until
+        // all of them are merged
+        let mut tree = self.tree_of(&ours_branch.head)?;
+        if options.strategy == Strategy::Ort {
+            // each head is merged with the bases of it and the heads merged
+            // before, like git-merge-octopus
+            let mut merged = vec![ours_branch.head.clone()];
+            for (head, name) in &remaining {
+                console_output!("Trying simple merge with {name}");
+                let bases = self.merge_bases_many(head, &merged)?;
+                let tree_options = self.merge_tree_options(options, &bases, ["HEAD", name])?;
+                let Some(base) = self.virtual_merge_base(&bases, &tree_options)? else {
+                    anyhow::bail!("refusing to merge unrelated histories");
+                };
+                let (merged_tree, conflicts) = self.merge_trees(
+                    self.tree_of(&base)?,
+                    tree,
+                    self.tree_of(head)?,
+                    &tree_options,
+                )?;
+                if !conflicts.is_empty() {
+                    anyhow::bail!(
+                        "Automated merge did not work.\n\
+                         Should not be doing an octopus.\n\
+                         Merge with strategy octopus failed."
+                    );
+                }
+                tree = merged_tree;
+                merged.push((*head).clone());
+            }
+        }
 
-        self.dump_tree(&merged_tree)?;
+        self.check_overwritten(&self.tree_of(&ours_branch.head)?, &tree)?;
+        let names: Vec<_> = remaining.iter().map(|(_, name)| name.as_str()).collect();
+        let message = self.merge_message(&names)?;
+        let parents = [ours_branch.head.clone()]
+            .into_iter()
+            .chain(remaining.into_iter().map(|(head, _)| head.clone()))
+            .collect();
+        self.commit_merge(tree, parents, message, None, options.squash)
+    }
 
-        merged_tree.map(Stage).save()?;
+    fn merge_continue(&self) -> anyhow::Result<CommitCreateResult> {
+        let Some(merge_state) = self.merge_state()? else {
+            anyhow::bail!("There is no merge in progress (MERGE_HEAD missing).");
+        };
+        self.create_commit(&merge_state.commit_message(), None)
+    }
 
-        let mut ours_branch_cloned = ours_branch.cloned();
-        ours_branch_cloned.head = sha1.clone().into();
-        ours_branch_cloned.save()?;
+    fn merge_abort(&self) -> anyhow::Result<()> {
+        let Some(merge_state) = self.merge_state()? else {
+            anyhow::bail!("There is no merge to abort (MERGE_HEAD missing).");
+        };
+        let head_tree = self
+            .head()
+            .load_branch()?
+            .get_current_commit()?
+            .get_tree()?
+            .cloned()
+            .unwrap();
+        let stage = self.stage()?.unwrap();
+        // like `git reset --merge`, changes which are not from the merge are
+        // kept, and files changed after the merge are not thrown away
+        let overwritten: Vec<_> = self
+            .overwritten_files(&stage.tree, &head_tree)?
+            .into_iter()
+            .filter(|name| !stage.unmerged.contains_key(name))
+            .collect();
+        if let Some(name) = overwritten.first() {
+            anyhow::bail!("Entry '{name}' not uptodate. Cannot merge.");
+        }
 
-        // oj_output!("Merge successful: new HEAD is {}", sha1);
+        self.dump_tree_from(&self.wrap(stage.tree), &self.wrap(head_tree.clone()))?;
+        // unmerged paths may have conflict markers, be added by theirs, or be
+        // moved away from a directory, so they are restored from HEAD
+        for path in stage.unmerged.keys() {
+            let line = self.line_at(&head_tree, path)?;
+            let path = self.working_dir().join(path);
+            match line {
+                Some(line) if line.kind != TreeLineKind::Tree => {
+                    let blob = Object::load_from(self, &line.sha1)?.cast_blob();
+                    std::fs::write(path, blob.as_bytes())?;
+                }
+                _ if path.is_file() || path.is_symlink() => std::fs::remove_file(path)?,
+                _ => {}
+            }
+        }
+        self.wrap(Stage::from(head_tree)).save()?;
+        merge_state.remove()?;
         Ok(())
     }
 
-    fn get_merge_base(&self, commit1: &Commit, commit2: &Commit) -> anyhow::Result<Commit> {
-        let mut visited = HashMap::new(); // 1 表示从 commit1 来，2 表示从 commit2 来，3 表示都到过
-        let mut queue: VecDeque<(u8, crate::models::object::ObjectSha1)> = VecDeque::new();
+    fn merge_bases(&self, a: &ObjectSha1, b: &ObjectSha1) -> anyhow::Result<Vec<ObjectSha1>> {
+        self.merge_bases_many(a, std::slice::from_ref(b))
+    }
 
-        queue.push_back((1u8, commit1.sha1().into()));
-        queue.push_back((2u8, commit2.sha1().into()));
+    fn merge_bases_many(
+        &self,
+        a: &ObjectSha1,
+        others: &[ObjectSha1],
+    ) -> anyhow::Result<Vec<ObjectSha1>> {
+        let ours = self.ancestors(std::slice::from_ref(a))?;
+        let common: Vec<_> = self
+            .ancestors(others)?
+            .into_iter()
+            .filter(|sha1| ours.contains(sha1))
+            .collect();
 
-        while let Some((source, sha)) = queue.pop_front() {
-            // 如果访问过就合并标记
-            let state = visited.entry(sha.clone()).or_insert(0);
-            *state |= source;
+        // a common ancestor is not the best, if it is an ancestor of another
+        let mut worse = HashSet::new();
+        let mut queue = Vec::new();
+        for sha1 in &common {
+            queue.extend(self.load_commit(sha1)?.parents);
+        }
+        while let Some(sha1) = queue.pop() {
+            if worse.insert(sha1.clone()) {
+                queue.extend(self.load_commit(&sha1)?.parents);
+            }
+        }
 
-            if *state == 3 {
-                // 从两个方向都访问到了，找到 LCA
-                let obj = self.wrap(Object::accessor(&sha)).load()?.unwrap();
-                if let Object::Commit(cmt) = obj {
-                    return Ok(cmt);
-                } else {
-                    anyhow::bail!("Object {} is not a commit", sha);
-                }
+        let mut bases = Vec::new();
+        for sha1 in common {
+            if !worse.contains(&sha1) {
+                bases.push((self.load_commit(&sha1)?.committer.timestamp, sha1));
             }
+        }
+        // newer first, like git
+        bases.sort_by(|a, b| b.cmp(a));
+        Ok(bases.into_iter().map(|(_, sha1)| sha1).collect())
+    }
 
-            // 向上继续遍历父节点
-            let Object::Commit(commit) = self.wrap(Object::accessor(&sha)).load()?.unwrap() else {
+    fn is_ancestor(&self, ancestor: &ObjectSha1, commit: &ObjectSha1) -> anyhow::Result<bool> {
+        let mut visited = HashSet::new();
+        let mut queue = vec![commit.clone()];
+        while let Some(sha) = queue.pop() {
+            if sha == *ancestor {
+                return Ok(true);
+            }
+            if !visited.insert(sha.clone()) {
+                continue;
+            }
+            let Object::Commit(commit) = self.load_object(&sha)?.unwrap() else {
                 anyhow::bail!("Object {} is not a commit", sha);
             };
+            queue.extend(commit.parents);
+        }
+        Ok(false)
+    }
+
+    fn merge_tree(
+        &self,
+        ours: &ObjectSha1,
+        theirs: &ObjectSha1,
+        names: [&str; 2],
+        options: &MergeOptions,
+    ) -> anyhow::Result<MergedTree> {
+        let bases = self.merge_bases(ours, theirs)?;
+        if bases.is_empty() {
+            anyhow::bail!("refusing to merge unrelated histories");
+        }
 
-            if let Some(parent_sha) = commit.parent {
-                queue.push_back((source, parent_sha));
+        let ours_tree = self.tree_of(ours)?;
+        let (tree, conflicts) = match options.strategy {
+            // the merge base is not needed to keep ours
+            Strategy::Ours => (ours_tree, vec![]),
+            Strategy::Ort => {
+                let tree_options = self.merge_tree_options(options, &bases, names)?;
+                let base_tree = match self.virtual_merge_base(&bases, &tree_options)? {
+                    Some(base) => self.tree_of(&base)?,
+                    None => Tree::empty(),
+                };
+                let theirs_tree = self.tree_of(theirs)?;
+                self.merge_trees(base_tree, ours_tree, theirs_tree, &tree_options)?
             }
+        };
+        self.save_tree(&tree)?;
+        Ok(MergedTree { tree, conflicts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Store, blob::Blob},
+        services::{
+            branch::BranchService,
+            testing::{head_of, temp_repo},
+        },
+    };
+
+    #[test]
+    fn merge_bases_of_a_branch_merged_twice() {
+        let (_dir, mut repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        repo.checkout_branch("feature", false).unwrap();
+        let f1 = repo.commit_files(&[("b.txt", Some("b\n"))], "f1");
+        repo.checkout_branch("master", false).unwrap();
+        repo.commit_files(&[("c.txt", Some("c\n"))], "m1");
+        repo.merge(&f1, "feature", &MergeOptions::default())
+            .unwrap();
+        let merged = head_of(&repo);
+
+        repo.checkout_branch("feature", false).unwrap();
+        let f2 = repo.commit_files(&[("b.txt", Some("bee\n"))], "f2");
+        // the first commit is reached from both sides by two paths, but only
+        // the last merged commit is the best
+        assert_eq!(repo.merge_bases(&merged, &f2).unwrap(), vec![f1.clone()]);
+        assert_eq!(repo.merge_bases(&f2, &merged).unwrap(), vec![f1]);
+
+        repo.checkout_branch("master", false).unwrap();
+        repo.merge(&f2, "feature", &MergeOptions::default())
+            .unwrap();
+        assert_eq!(repo.merge_bases(&head_of(&repo), &f2).unwrap(), vec![f2]);
+    }
+
+    /// A repository with `master` and `feature` changing `a.txt`
+    /// differently, on `master`. Returns the head of `feature`
+    fn diverged_repo() -> (tempfile::TempDir, Repository, ObjectSha1) {
+        let (dir, mut repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n")), ("b.txt", Some("b\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        repo.checkout_branch("feature", false).unwrap();
+        let feature = repo.commit_files(&[("a.txt", Some("feature\n"))], "feature");
+        repo.checkout_branch("master", false).unwrap();
+        repo.commit_files(&[("a.txt", Some("master\n"))], "master");
+        (dir, repo, feature)
+    }
+
+    #[test]
+    fn merge_should_fast_forward() {
+        let (_dir, mut repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        repo.create_branch("topic", None).unwrap();
+        repo.checkout_branch("feature", false).unwrap();
+        let feature = repo.commit_files(&[("a.txt", Some("feature\n"))], "feature");
+
+        repo.checkout_branch("master", false).unwrap();
+        let ff_only = MergeOptions {
+            fast_forward: FastForward::Only,
+            ..Default::default()
+        };
+        repo.merge(&feature, "feature", &ff_only).unwrap();
+        assert_eq!(head_of(&repo), feature);
+        assert_eq!(repo.read_file("a.txt"), "feature\n");
+        let stage = repo.stage().unwrap().unwrap();
+        assert_eq!(stage.tree.sha1(), repo.tree_of(&feature).unwrap().sha1());
+
+        repo.checkout_branch("topic", false).unwrap();
+        let no_ff = MergeOptions {
+            fast_forward: FastForward::Never,
+            ..Default::default()
+        };
+        repo.merge(&feature, "feature", &no_ff).unwrap();
+        let merge = repo
+            .load_object(&head_of(&repo))
+            .unwrap()
+            .unwrap()
+            .cast_commit();
+        assert_eq!(merge.parents, vec![first, feature]);
+        assert_eq!(merge.message, "Merge branch 'feature'\n");
+        assert_eq!(repo.read_file("a.txt"), "feature\n");
+    }
+
+    #[test]
+    fn merge_ff_only_should_fail_on_diverged_branches() {
+        let (_dir, repo, feature) = diverged_repo();
+        let head = head_of(&repo);
+        let ff_only = MergeOptions {
+            fast_forward: FastForward::Only,
+            ..Default::default()
+        };
+        let err = repo.merge(&feature, "feature", &ff_only).unwrap_err();
+        assert_eq!(err.to_string(), "Not possible to fast-forward, aborting.");
+        assert_eq!(head_of(&repo), head);
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+    }
+
+    /// A criss-cross history, where `master` and `feature` both merged the
+    /// first commit of the other. On `master`, returns the bases and the
+    /// head of `feature`
+    fn criss_cross_repo() -> (tempfile::TempDir, Repository, [ObjectSha1; 3]) {
+        let (dir, mut repo) = temp_repo();
+        repo.commit_files(&[("x.txt", Some("x\n")), ("y.txt", Some("y\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        let a1 = repo.commit_files(&[("x.txt", Some("a\n"))], "a1");
+        repo.checkout_branch("feature", false).unwrap();
+        let b1 = repo.commit_files(&[("y.txt", Some("b\n"))], "b1");
+        repo.merge(&a1, "master", &MergeOptions::default()).unwrap();
+        let feature = repo.commit_files(&[("z.txt", Some("z\n"))], "b2");
+        repo.checkout_branch("master", false).unwrap();
+        repo.merge(&b1, "feature", &MergeOptions::default())
+            .unwrap();
+        repo.commit_files(&[("x.txt", Some("a2\n"))], "a2");
+        (dir, repo, [a1, b1, feature])
+    }
+
+    #[test]
+    fn merge_should_use_a_virtual_merge_base() {
+        let (_dir, repo, [a1, b1, feature]) = criss_cross_repo();
+        let mut bases = repo.merge_bases(&head_of(&repo), &feature).unwrap();
+        bases.sort();
+        let mut expected = vec![a1, b1];
+        expected.sort();
+        assert_eq!(bases, expected);
+
+        // with `b1` alone as the base, x.txt would conflict, but it is `a` in
+        // the merged bases, and only changed by ours
+        repo.merge(&feature, "feature", &MergeOptions::default())
+            .unwrap();
+        assert!(repo.merge_state().unwrap().is_none());
+        assert_eq!(
+            repo.load_commit(&head_of(&repo)).unwrap().parents[1],
+            feature
+        );
+        assert_eq!(repo.read_file("x.txt"), "a2\n");
+        assert_eq!(repo.read_file("y.txt"), "b\n");
+        assert_eq!(repo.read_file("z.txt"), "z\n");
+    }
+
+    #[test]
+    fn merge_tree_should_not_touch_the_working_tree() {
+        let (_dir, repo, feature) = diverged_repo();
+        let head = head_of(&repo);
+        let names = ["HEAD", "feature"];
+        let MergedTree { tree, conflicts } = repo
+            .merge_tree(&head, &feature, names, &MergeOptions::default())
+            .unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].file, "a.txt");
+        let line = tree.get_map()["a.txt"].clone();
+        let blob = Object::load_from(&repo, &line.sha1).unwrap().cast_blob();
+        assert_eq!(
+            blob,
+            Blob::from("<<<<<<< HEAD\nmaster\n=======\nfeature\n>>>>>>> feature\n")
+        );
+
+        assert_eq!(head_of(&repo), head);
+        assert!(repo.merge_state().unwrap().is_none());
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+        assert!(repo.stage().unwrap().unmerged.is_empty());
+    }
+
+    #[test]
+    fn merge_with_the_ours_strategy() {
+        let (_dir, repo, [a1, b1, feature]) = criss_cross_repo();
+        let head = head_of(&repo);
+        // the bases can not be merged without their trees, but they are not
+        // needed by the ours strategy
+        for base in [&a1, &b1] {
+            let tree = Object::Tree(repo.tree_of(base).unwrap());
+            std::fs::remove_file(repo.root.join(tree.location())).unwrap();
         }
+        let options = MergeOptions {
+            strategy: Strategy::Ours,
+            ..Default::default()
+        };
+        repo.merge(&feature, "feature", &options).unwrap();
+        let merge = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(merge.parents, vec![head.clone(), feature.clone()]);
+        assert_eq!(merge.tree, repo.load_commit(&head).unwrap().tree);
+        assert!(!repo.working_dir().join("z.txt").exists());
+
+        let merged = repo.merge_tree(
+            &head,
+            &feature,
+            ["HEAD", "feature"],
+            &MergeOptions::default(),
+        );
+        assert!(merged.is_err());
+    }
+
+    #[test]
+    fn merge_octopus() {
+        let (_dir, mut repo, feature) = diverged_repo();
+        repo.create_branch("other", None).unwrap();
+        repo.checkout_branch("other", false).unwrap();
+        let other = repo.commit_files(&[("c.txt", Some("c\n"))], "other");
+        repo.checkout_branch("master", false).unwrap();
+        let head = repo.commit_files(&[("b.txt", Some("master\n"))], "b");
+
+        // feature conflicts with master, so nothing is changed
+        let heads = [
+            (other.clone(), "other".to_string()),
+            (feature, "feature".to_string()),
+        ];
+        let err = repo
+            .merge_octopus(&heads, &MergeOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Automated merge did not work."));
+        assert_eq!(head_of(&repo), head);
+        assert!(repo.merge_state().unwrap().is_none());
+        assert!(!repo.working_dir().join("c.txt").exists());
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+
+        repo.create_branch("third", None).unwrap();
+        repo.checkout_branch("third", false).unwrap();
+        let third = repo.commit_files(&[("d.txt", Some("d\n"))], "third");
+        repo.checkout_branch("master", false).unwrap();
+        let heads = [
+            (other.clone(), "other".to_string()),
+            (third.clone(), "third".to_string()),
+        ];
+        repo.merge_octopus(&heads, &MergeOptions::default())
+            .unwrap();
+        let merge = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(merge.parents, vec![head, other, third]);
+        assert_eq!(merge.message, "Merge branches 'other' and 'third'\n");
+        assert_eq!(repo.read_file("c.txt"), "c\n");
+        assert_eq!(repo.read_file("d.txt"), "d\n");
+    }
+
+    #[test]
+    fn merge_octopus_of_dependent_heads() {
+        let (_dir, mut repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.create_branch("other", None).unwrap();
+        repo.checkout_branch("other", false).unwrap();
+        let other = repo.commit_files(&[("c.txt", Some("c1\n"))], "other");
+        repo.create_branch("third", None).unwrap();
+        repo.create_branch("fourth", None).unwrap();
+        repo.checkout_branch("third", false).unwrap();
+        let third = repo.commit_files(&[("c.txt", Some("c2\n"))], "third");
+        repo.checkout_branch("fourth", false).unwrap();
+        let fourth = repo.commit_files(&[("e.txt", Some("e\n"))], "fourth");
+        repo.checkout_branch("master", false).unwrap();
+        let head = repo.commit_files(&[("b.txt", Some("b\n"))], "b");
+        repo.create_branch("topic", None).unwrap();
+
+        // `other` is merged by `third`, which descends from it
+        let heads = [
+            (other.clone(), "other".to_string()),
+            (third.clone(), "third".to_string()),
+        ];
+        repo.merge_octopus(&heads, &MergeOptions::default())
+            .unwrap();
+        let merge = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(merge.parents, vec![head.clone(), third.clone()]);
+        assert_eq!(repo.read_file("c.txt"), "c2\n");
+
+        // `fourth` is merged with `other` as the base, which is common to it
+        // and the merged `third`, so c.txt is only changed by `third`
+        repo.checkout_branch("topic", false).unwrap();
+        let heads = [
+            (third.clone(), "third".to_string()),
+            (fourth.clone(), "fourth".to_string()),
+        ];
+        repo.merge_octopus(&heads, &MergeOptions::default())
+            .unwrap();
+        let merge = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(merge.parents, vec![head, third, fourth]);
+        assert_eq!(repo.read_file("c.txt"), "c2\n");
+        assert_eq!(repo.read_file("e.txt"), "e\n");
+    }
+
+    #[test]
+    fn merge_should_not_overwrite_local_changes() {
+        let (_dir, repo, feature) = diverged_repo();
+        let head = head_of(&repo);
+        repo.write_files(&[("a.txt", Some("local\n"))]);
+        let err = repo
+            .merge(&feature, "feature", &MergeOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().starts_with(
+            "Your local changes to the following files would be overwritten by merge:\n\ta.txt\n"
+        ));
+        assert_eq!(repo.read_file("a.txt"), "local\n");
+        assert_eq!(head_of(&repo), head);
+        assert!(repo.merge_state().unwrap().is_none());
+    }
+
+    #[test]
+    fn merge_abort_should_keep_changes_before_the_merge() {
+        let (_dir, repo, feature) = diverged_repo();
+        repo.write_files(&[("b.txt", Some("local\n"))]);
+        repo.merge(&feature, "feature", &MergeOptions::default())
+            .unwrap();
+        assert!(repo.merge_state().unwrap().is_some());
+        assert!(repo.read_file("a.txt").starts_with("<<<<<<< HEAD\n"));
 
-        anyhow::bail!("No common ancestor found between the two commits")
+        repo.merge_abort().unwrap();
+        assert!(repo.merge_state().unwrap().is_none());
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+        assert_eq!(repo.read_file("b.txt"), "local\n");
     }
 }
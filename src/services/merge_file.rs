@@ -0,0 +1,284 @@
+//! Three-way merge of file contents, like `diff3`
+//!
+//! Both sides are diffed against the base. Lines unchanged on both sides are
+//! stable, and the unstable chunks between them are taken from the side which
+//! changed it. If both sides changed a chunk differently, it is a conflict.
+
+use crate::services::diff::{Edit, myers, split_lines};
+
+/// How conflicts are written into files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStyle {
+    /// only ours and theirs
+    #[default]
+    Merge,
+    /// ours, the base (after `|||||||`) and theirs
+    Diff3,
+}
+
+impl ConflictStyle {
+    /// Parse the value of the `merge.conflictStyle` config
+    pub fn from_config(value: Option<&str>) -> anyhow::Result<Self> {
+        match value {
+            None | Some("merge") => Ok(ConflictStyle::Merge),
+            Some("diff3") => Ok(ConflictStyle::Diff3),
+            Some(other) => anyhow::bail!("unknown conflict style '{other}'"),
+        }
+    }
+}
+
+/// How conflicting chunks are resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Favor {
+    /// keep both sides between conflict markers
+    #[default]
+    Neither,
+    /// take ours, `-X ours`
+    Ours,
+    /// take theirs, `-X theirs`
+    Theirs,
+    /// take ours and then theirs, the `union` merge driver
+    Union,
+}
+
+impl Favor {
+    /// Parse the option of `-X`
+    pub fn from_strategy_option(option: &str) -> anyhow::Result<Self> {
+        match option {
+            "ours" => Ok(Favor::Ours),
+            "theirs" => Ok(Favor::Theirs),
+            other => anyhow::bail!("Unknown option for merge-ort: -X{other}"),
+        }
+    }
+}
+
+/// Options of merging files
+#[derive(Debug, Clone)]
+pub struct MergeFileOptions {
+    pub style: ConflictStyle,
+    /// labels after the conflict markers, for ours, the base and theirs
+    pub labels: [String; 3],
+    pub favor: Favor,
+}
+
+impl Default for MergeFileOptions {
+    fn default() -> Self {
+        MergeFileOptions {
+            style: ConflictStyle::default(),
+            labels: ["ours".into(), "base".into(), "theirs".into()],
+            favor: Favor::default(),
+        }
+    }
+}
+
+/// The result of a three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedFile {
+    /// the merged content, with conflict markers if there are conflicts
+    pub content: String,
+    /// ranges of lines in ours which conflict with theirs, 1-based and
+    /// inclusive
+    pub conflicts: Vec<(usize, usize)>,
+}
+
+/// For each line of `base`, the matched line in the other side
+fn matches(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut res = vec![None; base.len()];
+    for edit in myers(base, other) {
+        if let Edit::Equal(x, y) = edit {
+            res[x] = Some(y);
+        }
+    }
+    res
+}
+
+/// End the last line of the content with a newline, if it has none, so that
+/// another line can follow, such as a conflict marker
+fn end_line(content: &mut String) {
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+}
+
+/// Push a conflict marker line, like `=======`, on a line of its own
+fn push_marker(content: &mut String, marker: &str) {
+    end_line(content);
+    content.push_str(marker);
+    content.push('\n');
+}
+
+/// Three-way merge `ours` and `theirs`, which are both changed from `base`
+pub fn merge_file(base: &str, ours: &str, theirs: &str, options: &MergeFileOptions) -> MergedFile {
+    let (base, ours, theirs) = (split_lines(base), split_lines(ours), split_lines(theirs));
+    let ours_match = matches(&base, &ours);
+    let theirs_match = matches(&base, &theirs);
+
+    let mut content = String::new();
+    let mut conflicts = Vec::new();
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        // stable lines, unchanged on both sides
+        while o < base.len() && ours_match[o] == Some(a) && theirs_match[o] == Some(b) {
+            content.push_str(base[o]);
+            (o, a, b) = (o + 1, a + 1, b + 1);
+        }
+        if o == base.len() && a == ours.len() && b == theirs.len() {
+            break;
+        }
+
+        // an unstable chunk, until the next line matched on both sides
+        let next = (o..base.len())
+            .find_map(|i| Some((i, ours_match[i]?, theirs_match[i]?)))
+            .unwrap_or((base.len(), ours.len(), theirs.len()));
+        let base_chunk = &base[o..next.0];
+        let ours_chunk = &ours[a..next.1];
+        let theirs_chunk = &theirs[b..next.2];
+
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            content.push_str(&theirs_chunk.concat());
+        } else if theirs_chunk == base_chunk {
+            content.push_str(&ours_chunk.concat());
+        } else {
+            // like git, lines changed in the same way on both sides are not
+            // part of the conflict
+            let (mut start, mut end) = (0, 0);
+            if options.style == ConflictStyle::Merge || options.favor != Favor::Neither {
+                start = ours_chunk
+                    .iter()
+                    .zip(theirs_chunk)
+                    .take_while(|(x, y)| x == y)
+                    .count();
+                end = ours_chunk[start..]
+                    .iter()
+                    .rev()
+                    .zip(theirs_chunk[start..].iter().rev())
+                    .take_while(|(x, y)| x == y)
+                    .count();
+            }
+            let ours_middle = &ours_chunk[start..ours_chunk.len() - end];
+            let theirs_middle = &theirs_chunk[start..theirs_chunk.len() - end];
+            if options.favor != Favor::Neither {
+                content.push_str(&ours_chunk[..start].concat());
+                match options.favor {
+                    Favor::Ours => content.push_str(&ours_middle.concat()),
+                    Favor::Theirs => content.push_str(&theirs_middle.concat()),
+                    _ => {
+                        content.push_str(&ours_middle.concat());
+                        if !ours_middle.is_empty() && !theirs_middle.is_empty() {
+                            end_line(&mut content);
+                        }
+                        content.push_str(&theirs_middle.concat());
+                    }
+                }
+                content.push_str(&ours_chunk[ours_chunk.len() - end..].concat());
+                (o, a, b) = next;
+                continue;
+            }
+
+            let [ours_label, base_label, theirs_label] = &options.labels;
+
+            content.push_str(&ours_chunk[..start].concat());
+            push_marker(&mut content, &format!("<<<<<<< {ours_label}"));
+            content.push_str(&ours_middle.concat());
+            if options.style == ConflictStyle::Diff3 {
+                push_marker(&mut content, &format!("||||||| {base_label}"));
+                content.push_str(&base_chunk.concat());
+            }
+            push_marker(&mut content, "=======");
+            content.push_str(&theirs_middle.concat());
+            push_marker(&mut content, &format!(">>>>>>> {theirs_label}"));
+            content.push_str(&ours_chunk[ours_chunk.len() - end..].concat());
+
+            let first = a + start + 1;
+            let last = (a + ours_chunk.len() - end).max(first);
+            conflicts.push((first, last));
+        }
+        (o, a, b) = next;
+    }
+
+    MergedFile { content, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_file_clean() {
+        let base = "1\n2\n3\n4\n5\n6\n7\n";
+        let ours = "one\n2\n3\n4\n5\n6\n7\n";
+        let theirs = "1\n2\n3\n4\n5\n6\nseven\neight\n";
+        let merged = merge_file(base, ours, theirs, &MergeFileOptions::default());
+        assert_eq!(merged.content, "one\n2\n3\n4\n5\n6\nseven\neight\n");
+        assert!(merged.conflicts.is_empty());
+
+        // the same change on both sides
+        let merged = merge_file(base, ours, ours, &MergeFileOptions::default());
+        assert_eq!(merged.content, ours);
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_file_conflict() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nB\nc\n";
+        let theirs = "a\nbee\nc\n";
+        let merged = merge_file(base, ours, theirs, &MergeFileOptions::default());
+        assert_eq!(
+            merged.content,
+            "a\n<<<<<<< ours\nB\n=======\nbee\n>>>>>>> theirs\nc\n"
+        );
+        assert_eq!(merged.conflicts, vec![(2, 2)]);
+
+        let options = MergeFileOptions {
+            style: ConflictStyle::Diff3,
+            labels: ["HEAD".into(), "base".into(), "feature".into()],
+            ..Default::default()
+        };
+        let merged = merge_file(base, ours, "a\nbee\nc", &options);
+        assert_eq!(
+            merged.content,
+            "a\n<<<<<<< HEAD\nB\nc\n||||||| base\nb\nc\n=======\nbee\nc\n>>>>>>> feature\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_file_favor() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nB\nc\n";
+        let theirs = "a\nbee\nc\n";
+        let merge = |favor| {
+            let options = MergeFileOptions {
+                favor,
+                ..Default::default()
+            };
+            merge_file(base, ours, theirs, &options)
+        };
+        assert_eq!(merge(Favor::Ours).content, ours);
+        assert_eq!(merge(Favor::Theirs).content, theirs);
+        let union = merge(Favor::Union);
+        assert_eq!(union.content, "a\nB\nbee\nc\n");
+        assert!(union.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_file_without_final_newline() {
+        let base = "a\nb";
+        let ours = "a\nB";
+        let theirs = "a\nbee";
+        let merge = |favor| {
+            let options = MergeFileOptions {
+                favor,
+                ..Default::default()
+            };
+            merge_file(base, ours, theirs, &options).content
+        };
+        assert_eq!(merge(Favor::Ours), ours);
+        assert_eq!(merge(Favor::Theirs), theirs);
+        assert_eq!(merge(Favor::Union), "a\nB\nbee");
+        assert_eq!(
+            merge(Favor::Neither),
+            "a\n<<<<<<< ours\nB\n=======\nbee\n>>>>>>> theirs\n"
+        );
+    }
+}
@@ -8,11 +8,30 @@
 
 pub mod branch;
 pub mod commit;
+pub mod diff;
 pub mod dump_tree;
+pub mod editor;
+pub mod fsck;
+pub mod gc;
+pub mod identity;
 pub mod merge;
+pub mod merge_file;
 pub mod mut_tree;
 pub mod object;
 pub mod oj;
+pub mod prune;
+pub mod reachable;
+pub mod rebase;
 pub mod repo;
+pub mod reset;
+pub mod restore;
+pub mod rev_list;
+pub mod rev_parse;
+pub mod sequencer;
 pub mod stage;
+pub mod stash;
+pub mod status;
+pub mod tag;
+#[cfg(test)]
+pub mod testing;
 pub mod tree;
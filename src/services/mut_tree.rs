@@ -1,21 +1,57 @@
 use log::debug;
 
-use crate::models::{
-    object::{Object, Sha1Able},
-    repo::WithRepo,
-    tree::{Tree, TreeLine, TreeLineKind},
+use crate::{
+    models::{
+        blob::Blob,
+        object::{Object, Sha1Able},
+        repo::WithRepo,
+        tree::{Tree, TreeLine, TreeLineKind},
+    },
+    services::object::ObjectService,
 };
 use std::{collections::HashMap, fs, io, path::Path};
 
+/// The kind and the content of a file in the working directory. Like git, a
+/// symbolic link is not followed, its content is the path it links to
+pub fn read_file(path: &Path) -> io::Result<(TreeLineKind, Blob)> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        let blob = Blob::from_content(target.as_os_str().as_encoded_bytes());
+        return Ok((TreeLineKind::Symlink, blob));
+    }
+    let kind = if is_executable(&metadata) {
+        TreeLineKind::Executable
+    } else {
+        TreeLineKind::File
+    };
+    Ok((kind, Blob::from_content(&fs::read(path)?)))
+}
+
+/// git only records if the owner can execute the file
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &fs::Metadata) -> bool {
+    false
+}
+
 /// A wrapper for the tree, because you may add twice for the same file
 pub struct MutableTree {
     pub data: HashMap<String, TreeLine>,
+    /// Save blobs and subtrees to the object database. If not, only subtrees
+    /// are kept in memory, see
+    /// [crate::models::repo::Repository::keep_unsaved]
     pub save_object: bool,
 }
 
 impl<'a> WithRepo<'a, MutableTree> {
-    fn debug_util(&self, path: &Path, debug_msg: &str) -> io::Result<()> {
-        let relative = path.strip_prefix(self.repo.working_dir()).map_err(|e| {
+    fn relative<'p>(&self, path: &'p Path) -> io::Result<&'p Path> {
+        path.strip_prefix(self.repo.working_dir()).map_err(|e| {
             io::Error::new(
                 io::ErrorKind::InvalidInput,
                 format!(
@@ -24,10 +60,85 @@ impl<'a> WithRepo<'a, MutableTree> {
                     path.display()
                 ),
             )
-        })?;
+        })
+    }
+
+    fn debug_util(&self, path: &Path, debug_msg: &str) -> io::Result<()> {
+        let relative = self.relative(path)?;
         debug!("{} {} ({})", debug_msg, relative.display(), path.display());
         Ok(())
     }
+
+    /// Run `op` with the path in the sub tree of its parent directory, sub
+    /// trees on the way are created, and removed when they become empty
+    fn in_parent(
+        &mut self,
+        path: &Path,
+        op: for<'t> fn(&'t mut Self, &Path) -> io::Result<&'t mut Self>,
+    ) -> io::Result<&mut Self> {
+        let dirs: Vec<String> = self
+            .relative(path)?
+            .parent()
+            .into_iter()
+            .flat_map(|parent| parent.iter())
+            .map(|dir| dir.to_string_lossy().to_string())
+            .collect();
+        self.in_dirs(&dirs, path, op)
+    }
+
+    fn in_dirs(
+        &mut self,
+        dirs: &[String],
+        path: &Path,
+        op: for<'t> fn(&'t mut Self, &Path) -> io::Result<&'t mut Self>,
+    ) -> io::Result<&mut Self> {
+        let Some((dir, rest)) = dirs.split_first() else {
+            return op(self, path);
+        };
+        let data = match self.data.get(dir) {
+            Some(line) if line.kind == TreeLineKind::Tree => self
+                .repo
+                .load_object(&line.sha1)?
+                .unwrap()
+                .cast_tree()
+                .into_map(),
+            _ => HashMap::new(),
+        };
+        let mut tree = self.wrap(MutableTree {
+            data,
+            save_object: self.save_object,
+        });
+        tree.in_dirs(rest, path, op)?;
+
+        let tree = tree.freeze();
+        if tree.objects.is_empty() {
+            // git does not track empty directories
+            self.data.remove(dir);
+            return Ok(self);
+        }
+        self.insert_tree(dir, tree.map(Object::Tree))
+    }
+
+    /// Save the tree, or keep it in memory, and add it as `name`
+    fn insert_tree(&mut self, name: &str, tree: WithRepo<'a, Object>) -> io::Result<&mut Self> {
+        if self.save_object {
+            tree.save()?;
+        } else {
+            // subtrees are needed to compare with other trees
+            self.repo.keep_unsaved(tree.cloned().unwrap());
+        }
+
+        self.data.insert(
+            name.to_string(),
+            TreeLine {
+                kind: TreeLineKind::Tree,
+                name: name.to_string(),
+                sha1: tree.sha1().into(),
+            },
+        );
+
+        Ok(self)
+    }
     /// add file to the stage
     /// it WON'T save stage file (`.git/index`), until you save it.
     pub fn add_file(&mut self, path: &Path) -> io::Result<&mut Self> {
@@ -40,21 +151,15 @@ impl<'a> WithRepo<'a, MutableTree> {
             .to_string_lossy();
 
         self.debug_util(path, "Adding file")?;
-        let ctnt = fs::read(path)?;
-        let blob = Object::Blob(
-            String::from_utf8(ctnt)
-                .map(|str| str.into())
-                .unwrap_or_else(|e| e.into_bytes().into()),
-        );
-
-        let blob = self.wrap(blob);
+        let (kind, blob) = read_file(path)?;
+        let blob = self.wrap(Object::Blob(blob));
 
         if self.save_object {
             blob.save()?;
         }
 
         let line = TreeLine {
-            kind: TreeLineKind::File,
+            kind,
             name: filename.to_string(),
             sha1: blob.sha1().into(),
         };
@@ -85,42 +190,20 @@ impl<'a> WithRepo<'a, MutableTree> {
         });
 
         for item in fs::read_dir(dir)? {
-            tree.add_path(&item?.path())?;
+            tree.add_entry(&item?.path())?;
         }
 
         let tree = tree.freeze().map(Object::Tree);
         let dirname = dir.file_name().unwrap_or_default().to_string_lossy();
-
-        if self.save_object {
-            tree.save()?;
-        }
-
-        self.data.insert(
-            dirname.to_string(),
-            TreeLine {
-                kind: TreeLineKind::Tree,
-                name: dirname.to_string(),
-                sha1: tree.sha1().into(),
-            },
-        );
-
-        Ok(self)
+        self.insert_tree(&dirname, tree)
     }
 
-    /// add a path to the stage
-    pub fn add_path(&mut self, path: &Path) -> io::Result<&mut Self> {
-        if path.is_file() {
+    /// add a file or directory which is directly in the tree
+    fn add_entry(&mut self, path: &Path) -> io::Result<&mut Self> {
+        if path.is_symlink() || path.is_file() {
             self.add_file(path)
         } else if path.is_dir() {
-            if path == self.repo.working_dir() {
-                for item in fs::read_dir(path)? {
-                    self.add_path(&item?.path())?;
-                }
-                Ok(self)
-            } else {
-                self.add_dir(path)
-            }
-            // self.add_dir(path)
+            self.add_dir(path)
         } else {
             Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -129,6 +212,22 @@ impl<'a> WithRepo<'a, MutableTree> {
         }
     }
 
+    /// add a path to the stage, in the sub tree of its directory. Like `git
+    /// add`, a path which is removed from the working directory is removed
+    /// from the stage
+    pub fn add_path(&mut self, path: &Path) -> io::Result<&mut Self> {
+        if path == self.repo.working_dir() {
+            for item in fs::read_dir(path)? {
+                self.add_path(&item?.path())?;
+            }
+            Ok(self)
+        } else if path.symlink_metadata().is_ok() {
+            self.in_parent(path, Self::add_entry)
+        } else {
+            self.in_parent(path, Self::remove_file)
+        }
+    }
+
     pub fn freeze(self) -> WithRepo<'a, Tree> {
         WithRepo::new(self.repo, self.unwrap().data.into())
     }
@@ -173,10 +272,10 @@ impl<'a> WithRepo<'a, MutableTree> {
     }
 
     pub fn remove_path(&mut self, path: &Path) -> io::Result<&mut Self> {
-        if path.is_file() {
-            self.remove_file(path)
+        if path.is_symlink() || path.is_file() {
+            self.in_parent(path, Self::remove_file)
         } else if path.is_dir() {
-            self.remove_dir(path)
+            self.in_parent(path, Self::remove_dir)
         } else {
             Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -2,17 +2,49 @@ use std::io;
 
 use crate::models::{
     Accessible,
+    commit::Commit,
     object::{Object, ObjectSha1},
     repo::{Repository, WithRepo},
+    tree::Tree,
 };
 
 pub trait ObjectService {
     /// Load an object by its SHA1
     fn load_object<'a>(&'a self, sha1: &ObjectSha1) -> io::Result<WithRepo<'a, Object>>;
+    /// Load a commit by its SHA1, fails if the object is not a commit
+    fn load_commit(&self, sha1: &ObjectSha1) -> io::Result<Commit>;
+    /// Resolve a full or abbreviated sha1 to the object it names
+    fn resolve_object(&self, name: &str) -> io::Result<ObjectSha1>;
 }
 
 impl ObjectService for Repository {
     fn load_object<'a>(&'a self, sha1: &ObjectSha1) -> io::Result<WithRepo<'a, Object>> {
         self.wrap(Object::accessor(sha1)).load()
     }
+
+    fn load_commit(&self, sha1: &ObjectSha1) -> io::Result<Commit> {
+        match Object::load_from(self, sha1)? {
+            Object::Commit(commit) => Ok(commit),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("object {sha1} is not a commit"),
+            )),
+        }
+    }
+
+    fn resolve_object(&self, name: &str) -> io::Result<ObjectSha1> {
+        let sha1 = ObjectSha1::from(name.to_ascii_lowercase());
+        if sha1.is_valid() {
+            return Ok(sha1);
+        }
+        Object::resolve_prefix(&self.root, name)
+    }
+}
+
+impl Repository {
+    /// The tree of the commit
+    pub fn tree_of(&self, commit: &ObjectSha1) -> anyhow::Result<Tree> {
+        let commit = self.load_commit(commit)?;
+        Ok(self.load_object(&commit.tree)?.unwrap().cast_tree())
+    }
 }
@@ -0,0 +1,161 @@
+//! Remove loose objects which are not reachable from refs and the index
+
+use crate::{
+    models::{
+        object::{Object, ObjectSha1},
+        repo::Repository,
+    },
+    services::reachable::ReachableService,
+};
+use std::{
+    fs, io,
+    time::{Duration, SystemTime},
+};
+
+/// Unreachable objects newer than it are kept, because they may be written by
+/// a command which is still running
+pub const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// Parse an expire time like `now`, `never` or `2.weeks.ago` to the cutoff
+/// time, objects older than it can be pruned. `None` means nothing expires.
+pub fn parse_expire(expire: &str, now: SystemTime) -> Option<io::Result<SystemTime>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid expire time: {expire}"),
+        )
+    };
+    match expire {
+        "now" | "all" => return Some(Ok(now)),
+        "never" => return None,
+        _ => {}
+    }
+
+    let parts = expire.split(['.', ' ']).collect::<Vec<_>>();
+    let [count, unit, "ago"] = parts[..] else {
+        return Some(Err(invalid()));
+    };
+    let Ok(count) = count.parse::<u64>() else {
+        return Some(Err(invalid()));
+    };
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Some(Err(invalid())),
+    };
+    Some(Ok(now
+        .checked_sub(Duration::from_secs(count * seconds))
+        .unwrap_or(SystemTime::UNIX_EPOCH)))
+}
+
+pub trait PruneService {
+    /// Remove unreachable loose objects modified before `expire`, returns the
+    /// pruned objects and their types. If `dry_run`, nothing is removed.
+    fn prune(
+        &self,
+        expire: Option<SystemTime>,
+        dry_run: bool,
+    ) -> io::Result<Vec<(ObjectSha1, &'static str)>>;
+}
+
+impl PruneService for Repository {
+    fn prune(
+        &self,
+        expire: Option<SystemTime>,
+        dry_run: bool,
+    ) -> io::Result<Vec<(ObjectSha1, &'static str)>> {
+        let Some(expire) = expire else {
+            return Ok(Vec::new());
+        };
+        let reachable = self.reachable_objects()?;
+
+        let mut pruned = Vec::new();
+        for (sha1, path) in Object::loose_objects(&self.root)? {
+            if reachable.contains(&sha1) || fs::metadata(&path)?.modified()? > expire {
+                continue;
+            }
+            let object_type = Object::load_raw(&self.root, &sha1)
+                .map(|raw| raw.object_type)
+                .unwrap_or("unknown");
+            if !dry_run {
+                fs::remove_file(&path)?;
+                if let Some(dir) = path.parent() {
+                    // only removes the dir when it is empty
+                    let _ = fs::remove_dir(dir);
+                }
+            }
+            pruned.push((sha1, object_type));
+        }
+        pruned.sort();
+
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::merge_state::MergeState, services::testing::temp_repo};
+
+    #[test]
+    fn prune_should_keep_commits_of_detached_head() {
+        let (dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        let second = repo.commit_files(&[("a.txt", Some("b\n"))], "second");
+        // the second commit is only referenced by the detached HEAD
+        fs::write(repo.root.join("refs/heads/master"), format!("{first}\n")).unwrap();
+        fs::write(repo.root.join("HEAD"), format!("{second}\n")).unwrap();
+        let repo = Repository::load_at(dir.path()).unwrap();
+
+        let expire = Some(SystemTime::now() + Duration::from_secs(60));
+        assert!(repo.prune(expire, false).unwrap().is_empty());
+
+        fs::write(repo.root.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let repo = Repository::load_at(dir.path()).unwrap();
+        let pruned: Vec<_> = repo.prune(expire, false).unwrap();
+        assert!(pruned.contains(&(second, "commit")));
+        // its tree, but not its blob, which is still in the index
+        assert_eq!(pruned.len(), 2);
+    }
+
+    #[test]
+    fn prune_should_keep_commits_of_merge_head() {
+        let (_dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        let second = repo.commit_files(&[("a.txt", Some("b\n"))], "second");
+        // the second commit is only referenced by MERGE_HEAD
+        fs::write(repo.root.join("refs/heads/master"), format!("{first}\n")).unwrap();
+        fs::write(repo.root.join(MergeState::LOCATION), format!("{second}\n")).unwrap();
+
+        let expire = Some(SystemTime::now() + Duration::from_secs(60));
+        assert!(repo.prune(expire, false).unwrap().is_empty());
+
+        fs::remove_file(repo.root.join(MergeState::LOCATION)).unwrap();
+        let pruned: Vec<_> = repo.prune(expire, false).unwrap();
+        assert!(pruned.contains(&(second, "commit")));
+        // its tree, but not its blob, which is still in the index
+        assert_eq!(pruned.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_expire() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10_000_000);
+        assert_eq!(parse_expire("now", now).unwrap().unwrap(), now);
+        assert!(parse_expire("never", now).is_none());
+        assert_eq!(
+            parse_expire("2.weeks.ago", now).unwrap().unwrap(),
+            now - Duration::from_secs(14 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_expire("1.hour.ago", now).unwrap().unwrap(),
+            now - Duration::from_secs(60 * 60)
+        );
+        assert!(parse_expire("yesterday", now).unwrap().is_err());
+        assert!(parse_expire("3.fortnights.ago", now).unwrap().is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Find objects which are reachable from refs and the index
+
+use crate::{
+    models::{
+        branch::read_ref,
+        merge_state::MergeState,
+        object::{Object, ObjectSha1},
+        repo::Repository,
+        tree::TreeLineKind,
+    },
+    services::{branch::BranchService, object::ObjectService, tag::TagService},
+};
+use std::{collections::HashSet, io};
+
+pub trait ReachableService {
+    /// Commits kept by the state of the repository rather than by branches,
+    /// such as `HEAD` which may be detached, `MERGE_HEAD`, `ORIG_HEAD`, the
+    /// commits of a cherry-pick, revert or rebase in progress and the stash
+    /// entries, named by where they are found
+    fn state_roots(&self) -> io::Result<Vec<(String, ObjectSha1)>>;
+    /// Objects which are always kept, such as objects pointed by branches and
+    /// tags, [ReachableService::state_roots], and trees and blobs in the index
+    fn ref_roots(&self) -> io::Result<Vec<ObjectSha1>>;
+    /// All objects reachable from [ReachableService::ref_roots]. Missing
+    /// objects are skipped, use fsck to find them.
+    fn reachable_objects(&self) -> io::Result<HashSet<ObjectSha1>>;
+}
+
+impl ReachableService for Repository {
+    fn state_roots(&self) -> io::Result<Vec<(String, ObjectSha1)>> {
+        let mut roots = Vec::new();
+        if let Some(sha1) = self.head().detached() {
+            roots.push(("HEAD".to_string(), sha1.clone()));
+        }
+        // set by git before commands which move `HEAD` a long way
+        if let Some(sha1) = read_ref(&self.root, "ORIG_HEAD")? {
+            roots.push(("ORIG_HEAD".to_string(), sha1));
+        }
+        if let Some(sha1) = read_ref(&self.root, MergeState::LOCATION)? {
+            roots.push((MergeState::LOCATION.to_string(), sha1));
+        }
+        if let Some(sequencer) = self.sequencer()? {
+            roots.push(("sequencer/head".to_string(), sequencer.head.clone()));
+            for (_, sha1) in &sequencer.todo {
+                roots.push(("sequencer/todo".to_string(), sha1.clone()));
+            }
+        }
+        if let Some(state) = self.rebase_state()? {
+            roots.push((
+                "rebase-merge/orig-head".to_string(),
+                state.orig_head.clone(),
+            ));
+            roots.push(("rebase-merge/onto".to_string(), state.onto.clone()));
+            for (_, sha1) in &state.todo {
+                roots.push(("rebase-merge/git-rebase-todo".to_string(), sha1.clone()));
+            }
+        }
+        // every entry of the stash reflog, not only `refs/stash`
+        for (n, entry) in self.stash_list()?.entries.iter().enumerate() {
+            roots.push((format!("stash@{{{n}}}"), entry.commit.clone()));
+        }
+        Ok(roots)
+    }
+
+    fn ref_roots(&self) -> io::Result<Vec<ObjectSha1>> {
+        let mut roots = Vec::new();
+        for name in self.list_branch()? {
+            roots.push(self.load_branch(&name)?.unwrap().head);
+        }
+        for name in self.list_tags()? {
+            roots.push(self.load_tag(&name)?.unwrap().target);
+        }
+        roots.extend(self.state_roots()?.into_iter().map(|(_, sha1)| sha1));
+        for line in &self.stage()?.objects {
+            roots.push(line.sha1.clone());
+        }
+        Ok(roots)
+    }
+
+    fn reachable_objects(&self) -> io::Result<HashSet<ObjectSha1>> {
+        let mut reachable = HashSet::new();
+        let mut queue = self.ref_roots()?;
+
+        while let Some(sha1) = queue.pop() {
+            if !reachable.insert(sha1.clone()) {
+                continue;
+            }
+            let Ok(object) = self.load_object(&sha1) else {
+                continue;
+            };
+            match object.unwrap() {
+                Object::Commit(commit) => {
+                    queue.push(commit.tree);
+                    queue.extend(commit.parents);
+                }
+                Object::Tree(tree) => {
+                    for line in tree.objects {
+                        if line.kind == TreeLineKind::Tree {
+                            queue.push(line.sha1);
+                        } else {
+                            // no need to load blobs
+                            reachable.insert(line.sha1);
+                        }
+                    }
+                }
+                Object::Tag(tag) => queue.push(tag.object),
+                Object::Blob(_) => {}
+            }
+        }
+
+        Ok(reachable)
+    }
+}
@@ -0,0 +1,336 @@
+//! Replay commits of the current branch onto another commit
+
+use crate::console_output;
+use crate::models::commit::Signature;
+use crate::models::object::{ObjectSha1, Sha1Able};
+use crate::models::rebase::RebaseState;
+use crate::models::repo::Repository;
+use crate::models::sequencer::Action;
+use crate::models::tree::Tree;
+use crate::services::object::ObjectService;
+use crate::services::reset::{ResetMode, ResetService};
+use crate::services::rev_list::RevListService;
+use crate::services::rev_parse::RevRange;
+
+pub trait RebaseService {
+    /// The commits of the current branch which are not in `upstream`, oldest
+    /// first. Merge commits are left out, like git
+    fn rebase_commits(&self, upstream: &ObjectSha1) -> anyhow::Result<Vec<ObjectSha1>>;
+    /// Reset the current branch to `onto`, and replay the todo list of
+    /// commits on it. It stops at conflicts, to be finished by
+    /// [RebaseService::rebase_continue], [RebaseService::rebase_skip] or
+    /// [RebaseService::rebase_abort]
+    fn rebase(&self, onto: &ObjectSha1, todo: Vec<(Action, ObjectSha1)>) -> anyhow::Result<()>;
+    /// Commit the stopped commit after its conflicts are resolved, and replay
+    /// the remaining ones
+    fn rebase_continue(&self) -> anyhow::Result<()>;
+    /// Leave the stopped commit out, and replay the remaining ones
+    fn rebase_skip(&self) -> anyhow::Result<()>;
+    /// Reset the branch, the index and the working tree to before the rebase
+    fn rebase_abort(&self) -> anyhow::Result<()>;
+}
+
+const CONFLICT_HINT: &str = "hint: Resolve all conflicts manually, mark them as resolved with\n\
+     hint: \"git add/rm <conflicted_files>\", then run \"git rebase --continue\".\n\
+     hint: You can instead skip this commit: run \"git rebase --skip\".\n\
+     hint: To abort and get back to the state before \"git rebase\", run \"git rebase --abort\".";
+
+impl Repository {
+    /// Commit the tree for the action on the commit with its message and
+    /// author. [Action::Squash] and [Action::Fixup] meld it into the commit
+    /// at `HEAD`, keeping the author of that, the others are committed on it,
+    /// unless they are empty
+    fn commit_rebased(
+        &self,
+        action: Action,
+        tree: Tree,
+        message: String,
+        author: Signature,
+    ) -> anyhow::Result<()> {
+        let head = self.head().load_branch()?.unwrap().head;
+        let head_commit = self.load_commit(&head)?;
+        match action {
+            Action::Squash | Action::Fixup => {
+                let message = match action {
+                    Action::Squash => self.edit_message(&format!(
+                        "{}\n\n{}",
+                        head_commit.message.trim_end(),
+                        message
+                    ))?,
+                    _ => head_commit.message,
+                };
+                let author = Some(head_commit.author);
+                self.commit_merge(tree, head_commit.parents, message, author, false)
+            }
+            _ => {
+                if tree.sha1() == head_commit.tree.as_str() {
+                    let subject = message.lines().next().unwrap_or_default();
+                    console_output!("dropping {subject} -- patch contents already upstream");
+                    return Ok(());
+                }
+                let message = match action {
+                    Action::Reword => self.edit_message(&message)?,
+                    _ => message,
+                };
+                self.commit_merge(tree, vec![head], message, Some(author), false)
+            }
+        }
+    }
+
+    /// Replay the commits in the todo list, until all are done or one stops
+    fn run_rebase(&self, mut state: RebaseState) -> anyhow::Result<()> {
+        while let Some((action, sha1)) = state.todo.first().cloned() {
+            // saved before each step, so that it can be aborted if anything
+            // goes wrong
+            self.wrap(state.clone()).save()?;
+            let commit = self.load_commit(&sha1)?;
+            let head = self.head().load_branch()?.unwrap().head;
+            match action {
+                Action::Drop => {}
+                // the commit is kept if it is already on `HEAD`
+                Action::Pick if commit.parents == [head] => self.reset(&sha1, ResetMode::Hard)?,
+                _ => {
+                    let Some(tree) = self.apply_changes(Action::Pick, &sha1)? else {
+                        state.message = commit.message;
+                        self.wrap(state).save()?;
+                        console_output!("{CONFLICT_HINT}");
+                        return Ok(());
+                    };
+                    self.commit_rebased(action, tree, commit.message, commit.author)?;
+                }
+            }
+            state.todo.remove(0);
+        }
+        if let Some(state) = self.rebase_state()? {
+            state.remove()?;
+        }
+        console_output!(
+            "Successfully rebased and updated refs/heads/{}.",
+            state.head_name
+        );
+        Ok(())
+    }
+
+    /// Fail if a rebase is in progress
+    pub fn check_no_rebase(&self) -> anyhow::Result<()> {
+        if self.rebase_state()?.is_some() {
+            anyhow::bail!(
+                "a rebase is already in progress\n\
+                 hint: try \"git rebase (--continue | --skip | --abort)\""
+            );
+        }
+        Ok(())
+    }
+
+    /// The unfinished rebase, or fail if there is none
+    fn load_rebase_state(&self) -> anyhow::Result<RebaseState> {
+        match self.rebase_state()? {
+            Some(state) => Ok(state.unwrap()),
+            None => anyhow::bail!("No rebase in progress?"),
+        }
+    }
+}
+
+impl RebaseService for Repository {
+    fn rebase_commits(&self, upstream: &ObjectSha1) -> anyhow::Result<Vec<ObjectSha1>> {
+        let head = self.head().load_branch()?.unwrap().head;
+        let range = RevRange {
+            include: vec![head],
+            exclude: vec![upstream.clone()],
+        };
+        let mut commits: Vec<_> = self
+            .rev_list(&range, None)?
+            .into_iter()
+            .filter(|(_, commit)| commit.parents.len() <= 1)
+            .map(|(sha1, _)| sha1)
+            .collect();
+        commits.reverse();
+        Ok(commits)
+    }
+
+    fn rebase(&self, onto: &ObjectSha1, todo: Vec<(Action, ObjectSha1)>) -> anyhow::Result<()> {
+        self.check_no_rebase()?;
+        let head = self.check_can_apply("rebase")?;
+        if let Some((action @ (Action::Squash | Action::Fixup), _)) =
+            todo.iter().find(|(action, _)| *action != Action::Drop)
+        {
+            anyhow::bail!("cannot '{}' without a previous commit", action.name());
+        }
+
+        let state = RebaseState {
+            head_name: self.head().branch_name.clone(),
+            orig_head: head,
+            onto: onto.clone(),
+            todo,
+            message: String::new(),
+        };
+        self.wrap(state.clone()).save()?;
+        self.reset(onto, ResetMode::Hard)?;
+        self.run_rebase(state)
+    }
+
+    fn rebase_continue(&self) -> anyhow::Result<()> {
+        let mut state = self.load_rebase_state()?;
+        let stage = self.stage()?.unwrap();
+        if !stage.unmerged.is_empty() {
+            anyhow::bail!(
+                "You must edit all merge conflicts and then\n\
+                 mark them as resolved using git add"
+            );
+        }
+        if let Some((action, sha1)) = state.todo.first().cloned() {
+            let author = self.load_commit(&sha1)?.author;
+            self.commit_rebased(action, stage.tree, state.message.clone(), author)?;
+            state.todo.remove(0);
+        }
+        self.run_rebase(state)
+    }
+
+    fn rebase_skip(&self) -> anyhow::Result<()> {
+        let mut state = self.load_rebase_state()?;
+        let head = self.head().load_branch()?.unwrap().head;
+        self.reset(&head, ResetMode::Hard)?;
+        if !state.todo.is_empty() {
+            state.todo.remove(0);
+        }
+        self.run_rebase(state)
+    }
+
+    fn rebase_abort(&self) -> anyhow::Result<()> {
+        let Some(state) = self.rebase_state()? else {
+            anyhow::bail!("No rebase in progress?");
+        };
+        self.reset(&state.orig_head, ResetMode::Hard)?;
+        state.remove()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::config::Config,
+        services::{
+            branch::BranchService,
+            reachable::ReachableService,
+            testing::{head_of, temp_repo},
+        },
+    };
+    use std::fs;
+
+    /// A repository on `feature`, with commits `a.txt`, `b.txt` and `c.txt`,
+    /// and `master` which changed `a.txt` and added `d.txt` since. The first
+    /// commit of `feature` conflicts with `master`. Returns `master` and the
+    /// commits of `feature`
+    fn rebasing_repo() -> (tempfile::TempDir, Repository, ObjectSha1, [ObjectSha1; 3]) {
+        let (dir, mut repo) = temp_repo();
+        // the editor keeps messages as they are
+        fs::write(
+            repo.root.join(Config::LOCATION),
+            "[core]\n\teditor = true\n",
+        )
+        .unwrap();
+        repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        let master = repo.commit_files(
+            &[("a.txt", Some("master\n")), ("d.txt", Some("d\n"))],
+            "master",
+        );
+        repo.checkout_branch("feature", false).unwrap();
+        let commits = [
+            repo.commit_files(&[("a.txt", Some("feature\n"))], "change a"),
+            repo.commit_files(&[("b.txt", Some("b\n"))], "add b"),
+            repo.commit_files(&[("c.txt", Some("c\n"))], "add c"),
+        ];
+        (dir, repo, master, commits)
+    }
+
+    #[test]
+    fn rebase_should_squash_and_fixup() {
+        let (_dir, repo, master, [a, b, c]) = rebasing_repo();
+        // the commits of feature, oldest first, excluding those of master
+        assert_eq!(
+            repo.rebase_commits(&master).unwrap(),
+            [a, b.clone(), c.clone()]
+        );
+
+        let todo = vec![(Action::Pick, b.clone()), (Action::Squash, c.clone())];
+        repo.rebase(&master, todo).unwrap();
+        assert!(repo.rebase_state().unwrap().is_none());
+        let squashed = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(squashed.parents, vec![master.clone()]);
+        assert_eq!(squashed.message, "add b\n\nadd c\n");
+        assert_eq!(squashed.author, repo.load_commit(&b).unwrap().author);
+        for (file, content) in [("a.txt", "master\n"), ("b.txt", "b\n"), ("c.txt", "c\n")] {
+            assert_eq!(repo.read_file(file), content);
+        }
+
+        let todo = vec![(Action::Pick, b), (Action::Fixup, c)];
+        repo.rebase(&master, todo).unwrap();
+        let fixed = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(fixed.parents, vec![master]);
+        assert_eq!(fixed.message, "add b\n");
+    }
+
+    #[test]
+    fn rebase_should_skip_a_conflicting_commit() {
+        let (_dir, repo, master, [a, b, c]) = rebasing_repo();
+        let todo = vec![
+            (Action::Pick, a.clone()),
+            (Action::Pick, b),
+            (Action::Pick, c),
+        ];
+        repo.rebase(&master, todo).unwrap();
+        let state = repo.rebase_state().unwrap().unwrap();
+        assert_eq!(state.todo[0], (Action::Pick, a));
+        assert_eq!(head_of(&repo), master);
+        assert!(repo.stage().unwrap().unmerged.contains_key("a.txt"));
+
+        repo.rebase_skip().unwrap();
+        assert!(repo.rebase_state().unwrap().is_none());
+        let head = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(head.message, "add c\n");
+        let parent = repo.load_commit(&head.parents[0]).unwrap();
+        assert_eq!(parent.parents, vec![master]);
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+        assert!(repo.stage().unwrap().unmerged.is_empty());
+    }
+
+    #[test]
+    fn rebase_abort_should_restore_the_branch() {
+        let (_dir, repo, master, commits) = rebasing_repo();
+        let orig_head = head_of(&repo);
+        let todo = commits
+            .iter()
+            .map(|sha1| (Action::Pick, sha1.clone()))
+            .collect();
+        repo.rebase(&master, todo).unwrap();
+        // the original commits are only kept by the rebase
+        let roots = repo.ref_roots().unwrap();
+        assert!(roots.contains(&orig_head));
+        assert!(commits.iter().all(|sha1| roots.contains(sha1)));
+
+        repo.rebase_abort().unwrap();
+        assert!(repo.rebase_state().unwrap().is_none());
+        assert_eq!(head_of(&repo), orig_head);
+        assert_eq!(repo.read_file("a.txt"), "feature\n");
+        assert!(!repo.working_dir().join("d.txt").exists());
+        assert!(repo.stage().unwrap().unmerged.is_empty());
+    }
+
+    #[test]
+    fn rebase_should_refuse_unstaged_changes() {
+        let (_dir, repo, master, commits) = rebasing_repo();
+        let orig_head = head_of(&repo);
+        repo.write_files(&[("b.txt", Some("local\n"))]);
+        let todo = commits
+            .iter()
+            .map(|sha1| (Action::Pick, sha1.clone()))
+            .collect();
+        assert!(repo.rebase(&master, todo).is_err());
+        assert!(repo.rebase_state().unwrap().is_none());
+        assert_eq!(head_of(&repo), orig_head);
+        assert_eq!(repo.read_file("b.txt"), "local\n");
+    }
+}
@@ -7,10 +7,11 @@ use std::{collections::HashMap, io};
 
 impl Repository {
     /// get the working directory of the repository
-    pub fn working_tree(&self) -> io::Result<WithRepo<Tree>> {
+    pub fn working_tree(&self) -> io::Result<WithRepo<'_, Tree>> {
         let mut working_tree = self.wrap(MutableTree {
             data: HashMap::new(),
-            save_object: true,
+            // only hashes are needed, saving them leaves garbage objects
+            save_object: false,
         });
 
         working_tree.add_path(self.working_dir())?;
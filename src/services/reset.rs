@@ -0,0 +1,189 @@
+use crate::models::head::{Head, HeadKind};
+use crate::models::object::{Object, ObjectSha1};
+use crate::models::stage::Stage;
+use crate::models::tree::Tree;
+use crate::models::{Accessible, Store, repo::Repository};
+use crate::services::dump_tree::DumpTreeService;
+use crate::services::object::ObjectService;
+use crate::services::tree::{ComparedKind, build_tree, compare_trees, flatten_tree, path_matches};
+
+/// What to reset besides the current branch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResetMode {
+    /// only move the branch (`--soft`)
+    Soft,
+    /// also reset the index (`--mixed`)
+    #[default]
+    Mixed,
+    /// also reset the index and the working tree (`--hard`)
+    Hard,
+}
+
+pub trait ResetService {
+    /// Move the current branch, or a detached `HEAD`, to the commit, and
+    /// reset the index and the working tree as the mode says. An unfinished merge is forgotten,
+    /// except by a soft reset, which refuses to run during a merge. A
+    /// cherry-pick or revert in progress is always forgotten
+    fn reset(&self, commit: &ObjectSha1, mode: ResetMode) -> anyhow::Result<()>;
+    /// Reset the index entries of the paths, relative to the top of the
+    /// working directory, to their entries in the tree. Paths not in the tree
+    /// are removed from the index. The working tree is not touched
+    fn reset_paths(&self, tree: &Tree, paths: &[String]) -> anyhow::Result<()>;
+}
+
+impl ResetService for Repository {
+    fn reset(&self, commit: &ObjectSha1, mode: ResetMode) -> anyhow::Result<()> {
+        let merge_state = self.merge_state()?;
+        if mode == ResetMode::Soft && merge_state.is_some() {
+            anyhow::bail!("Cannot do a soft reset in the middle of a merge.");
+        }
+        let tree = Object::load_from(self, &self.load_commit(commit)?.tree)?.cast_tree();
+
+        if mode == ResetMode::Hard {
+            let stage = self.stage()?.unwrap();
+            let target = self.wrap(tree.clone());
+            // the paths tracked in the index are added, removed or replaced,
+            // which also moves away the files of conflicts
+            self.dump_tree_from(&self.wrap(stage.tree), &target)?;
+            // then changes in the working tree are discarded, untracked files
+            // are kept
+            for change in compare_trees(&target, &self.working_tree()?)? {
+                if matches!(change.kind, ComparedKind::Added) {
+                    continue;
+                }
+                let line = change.old.as_ref().unwrap_or(&change.line);
+                self.dump_line(line, &self.working_dir().join(&line.name))?;
+            }
+        }
+        if mode != ResetMode::Soft {
+            self.wrap(Stage::from(tree)).save()?;
+            if let Some(merge_state) = merge_state {
+                merge_state.remove()?;
+            }
+        }
+
+        // like git, a cherry-pick or revert in progress is forgotten
+        if let Some(sequencer) = self.sequencer()? {
+            sequencer.remove()?;
+        }
+
+        if self.head().detached().is_some() {
+            let head = Head {
+                kind: HeadKind::Detached(commit.clone()),
+                branch_name: "HEAD".to_string(),
+            };
+            head.store(&self.root)?;
+            return Ok(());
+        }
+        let (branch, _) = self.head().load_branch_or_create()?;
+        let mut branch = branch.cloned();
+        branch.head = commit.clone();
+        branch.save()?;
+        Ok(())
+    }
+
+    fn reset_paths(&self, tree: &Tree, paths: &[String]) -> anyhow::Result<()> {
+        let mut stage = self.stage()?.unwrap();
+        let mut files = flatten_tree(&self.wrap(stage.tree))?;
+        let source = flatten_tree(&self.wrap(tree.clone()))?;
+
+        for path in paths {
+            files.retain(|name, _| !path_matches(path, name));
+            files.extend(
+                source
+                    .iter()
+                    .filter(|(name, _)| path_matches(path, name))
+                    .map(|(name, line)| (name.clone(), line.clone())),
+            );
+        }
+
+        stage.tree = build_tree(self, &files)?;
+        for path in paths {
+            stage.resolve(path);
+        }
+        self.wrap(stage).save()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::sequencer::Action,
+        services::{
+            branch::BranchService,
+            sequencer::SequencerService,
+            testing::{head_of, temp_repo},
+        },
+    };
+
+    /// The content of the file in the index
+    fn staged(repo: &Repository, name: &str) -> Option<String> {
+        let stage = repo.stage().unwrap().unwrap();
+        let line = flatten_tree(&repo.wrap(stage.tree)).unwrap().remove(name)?;
+        Some(
+            repo.load_object(&line.sha1)
+                .unwrap()
+                .unwrap()
+                .cast_blob()
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn reset_modes() {
+        let (_dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        let second = repo.commit_files(&[("a.txt", Some("b\n"))], "second");
+
+        repo.reset(&first, ResetMode::Soft).unwrap();
+        assert_eq!(head_of(&repo), first);
+        assert_eq!(staged(&repo, "a.txt").unwrap(), "b\n");
+
+        repo.reset(&first, ResetMode::Mixed).unwrap();
+        assert_eq!(staged(&repo, "a.txt").unwrap(), "a\n");
+        assert_eq!(repo.read_file("a.txt"), "b\n");
+
+        repo.write_files(&[("new.txt", Some("new\n"))]);
+        repo.reset(&second, ResetMode::Hard).unwrap();
+        assert_eq!(head_of(&repo), second);
+        assert_eq!(staged(&repo, "a.txt").unwrap(), "b\n");
+        assert_eq!(repo.read_file("a.txt"), "b\n");
+        // untracked files are kept
+        assert_eq!(repo.read_file("new.txt"), "new\n");
+    }
+
+    #[test]
+    fn reset_paths_should_unstage() {
+        let (_dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.write_files(&[("a.txt", Some("b\n")), ("new.txt", Some("new\n"))]);
+        repo.add_files(&["a.txt", "new.txt"]);
+
+        let tree = repo.tree_of(&first).unwrap();
+        repo.reset_paths(&tree, &["a.txt".into(), "new.txt".into()])
+            .unwrap();
+        assert_eq!(staged(&repo, "a.txt").unwrap(), "a\n");
+        assert!(staged(&repo, "new.txt").is_none());
+        assert_eq!(repo.read_file("a.txt"), "b\n");
+    }
+
+    #[test]
+    fn reset_should_forget_cherry_picks() {
+        let (_dir, mut repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        repo.checkout_branch("feature", false).unwrap();
+        let feature = repo.commit_files(&[("a.txt", Some("feature\n"))], "feature");
+        repo.checkout_branch("master", false).unwrap();
+        let head = repo.commit_files(&[("a.txt", Some("master\n"))], "master");
+
+        repo.apply_commits(Action::Pick, &[feature]).unwrap();
+        assert!(repo.sequencer().unwrap().is_some());
+        repo.reset(&head, ResetMode::Hard).unwrap();
+        assert!(repo.sequencer().unwrap().is_none());
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+        assert!(repo.stage().unwrap().unmerged.is_empty());
+    }
+}
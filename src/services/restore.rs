@@ -0,0 +1,101 @@
+use crate::models::repo::Repository;
+use crate::models::tree::Tree;
+use crate::services::reset::ResetService;
+use crate::services::tree::{flatten_tree, path_matches};
+
+pub trait RestoreService {
+    /// Restore the files of the paths, relative to the top of the working
+    /// directory, from the source tree, in the index if `staged` and in the
+    /// working tree if `worktree`. Files that match the paths in the index but
+    /// are not in the source are removed
+    fn restore(
+        &self,
+        source: &Tree,
+        paths: &[String],
+        staged: bool,
+        worktree: bool,
+    ) -> anyhow::Result<()>;
+}
+
+impl RestoreService for Repository {
+    fn restore(
+        &self,
+        source: &Tree,
+        paths: &[String],
+        staged: bool,
+        worktree: bool,
+    ) -> anyhow::Result<()> {
+        let stage = self.stage()?.unwrap();
+        let tracked = flatten_tree(&self.wrap(stage.tree))?;
+        let files = flatten_tree(&self.wrap(source.clone()))?;
+        let matched = |name: &String| paths.iter().any(|path| path_matches(path, name));
+
+        for path in paths {
+            let known = |name: &String| path_matches(path, name);
+            if !files.keys().any(known) && !tracked.keys().any(known) {
+                anyhow::bail!("pathspec '{path}' did not match any file(s) known to git");
+            }
+        }
+
+        if staged {
+            self.reset_paths(source, paths)?;
+        }
+        if worktree {
+            for name in tracked.keys().filter(|name| matched(name)) {
+                let path = self.working_dir().join(name);
+                if !files.contains_key(name) && (path.is_file() || path.is_symlink()) {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            for (name, line) in files.iter().filter(|(name, _)| matched(name)) {
+                self.dump_line(line, &self.working_dir().join(name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::testing::temp_repo;
+
+    #[test]
+    fn restore_worktree_and_index() {
+        let (_dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("d/a.txt", Some("a\n")), ("b.txt", Some("b\n"))], "first");
+        repo.commit_files(&[("d/a.txt", Some("changed\n"))], "second");
+        repo.write_files(&[("d/a.txt", Some("local\n")), ("b.txt", None)]);
+
+        // from the index
+        let index = repo.stage().unwrap().unwrap().tree;
+        repo.restore(&index, &["b.txt".into()], false, true)
+            .unwrap();
+        assert_eq!(repo.read_file("b.txt"), "b\n");
+        assert_eq!(repo.read_file("d/a.txt"), "local\n");
+
+        // from another commit, to the index only
+        let first = repo.tree_of(&first).unwrap();
+        repo.restore(&first, &["d".into()], true, false).unwrap();
+        let stage = repo.stage().unwrap().unwrap();
+        let staged = flatten_tree(&repo.wrap(stage.tree)).unwrap();
+        assert_eq!(
+            staged["d/a.txt"].sha1,
+            flatten_tree(&repo.wrap(first.clone())).unwrap()["d/a.txt"].sha1
+        );
+        assert_eq!(repo.read_file("d/a.txt"), "local\n");
+
+        // and to both
+        repo.restore(&first, &["d/a.txt".into()], true, true)
+            .unwrap();
+        assert_eq!(repo.read_file("d/a.txt"), "a\n");
+
+        let err = repo
+            .restore(&first, &["none".into()], false, true)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'none' did not match any file(s) known to git"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! List commits selected by revisions
+
+use crate::{
+    models::{commit::Commit, object::ObjectSha1, repo::Repository},
+    services::{object::ObjectService, rev_parse::RevRange},
+};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+pub trait RevListService {
+    /// Commits in the range, newest first, at most `max_count` of them
+    fn rev_list(
+        &self,
+        range: &RevRange,
+        max_count: Option<usize>,
+    ) -> anyhow::Result<Vec<(ObjectSha1, Commit)>>;
+    /// All ancestors of the commits, including themselves
+    fn ancestors(&self, commits: &[ObjectSha1]) -> anyhow::Result<HashSet<ObjectSha1>>;
+}
+
+impl RevListService for Repository {
+    fn ancestors(&self, commits: &[ObjectSha1]) -> anyhow::Result<HashSet<ObjectSha1>> {
+        let mut visited = HashSet::new();
+        let mut queue = commits.to_vec();
+        while let Some(sha1) = queue.pop() {
+            if visited.insert(sha1.clone()) {
+                queue.extend(self.load_commit(&sha1)?.parents);
+            }
+        }
+        Ok(visited)
+    }
+
+    fn rev_list(
+        &self,
+        range: &RevRange,
+        max_count: Option<usize>,
+    ) -> anyhow::Result<Vec<(ObjectSha1, Commit)>> {
+        let mut visited = self.ancestors(&range.exclude)?;
+        let mut res = Vec::new();
+
+        // newer commits first, like git
+        let mut queue = BinaryHeap::new();
+        let mut pending = HashMap::new();
+        for sha1 in &range.include {
+            let commit = self.load_commit(sha1)?;
+            queue.push((commit.committer.timestamp, sha1.clone()));
+            pending.insert(sha1.clone(), commit);
+        }
+
+        while let Some((_, sha1)) = queue.pop() {
+            if max_count.is_some_and(|max| res.len() >= max) {
+                break;
+            }
+            if !visited.insert(sha1.clone()) {
+                continue;
+            }
+            let commit = pending.remove(&sha1).expect("queued commits are loaded");
+            for parent in &commit.parents {
+                if visited.contains(parent) || pending.contains_key(parent) {
+                    continue;
+                }
+                let parent_commit = self.load_commit(parent)?;
+                queue.push((parent_commit.committer.timestamp, parent.clone()));
+                pending.insert(parent.clone(), parent_commit);
+            }
+            res.push((sha1, commit));
+        }
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,248 @@
+//! Parse revisions, such as `HEAD~2`, `main^`, `v1.0^{}`, `@{upstream}`,
+//! `stash@{1}` or `A..B`
+//!
+//! See <https://git-scm.com/docs/gitrevisions>
+
+use crate::{
+    models::{
+        branch::read_ref,
+        commit::Commit,
+        config::Config,
+        object::{GitObject, Object, ObjectSha1},
+        repo::Repository,
+    },
+    services::{merge::MergeService, object::ObjectService},
+};
+
+/// Commits selected by revisions, that is, commits reachable from any of
+/// `include` but not from any of `exclude`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RevRange {
+    pub include: Vec<ObjectSha1>,
+    pub exclude: Vec<ObjectSha1>,
+}
+
+pub trait RevParseService {
+    /// Resolve a revision to the object it names
+    fn rev_parse(&self, rev: &str) -> anyhow::Result<ObjectSha1>;
+    /// Resolve a revision which should name a commit
+    fn rev_parse_commit(&self, rev: &str) -> anyhow::Result<ObjectSha1>;
+    /// Resolve revisions and ranges like `A..B`, `A...B` and `^A`
+    fn rev_parse_range(&self, revs: &[String]) -> anyhow::Result<RevRange>;
+    /// The full ref name of the upstream of a local branch, configured by
+    /// `branch.<name>.remote` and `branch.<name>.merge`
+    fn upstream_of(&self, branch: &str) -> anyhow::Result<String>;
+}
+
+/// Split the revision into the name and the `~` / `^` suffixes
+fn split_suffix(rev: &str) -> (&str, &str) {
+    let mut depth = 0;
+    for (i, c) in rev.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '~' | '^' if depth == 0 => return rev.split_at(i),
+            _ => {}
+        }
+    }
+    (rev, "")
+}
+
+/// Split the leading number of the string, which defaults to 1
+fn split_number(s: &str) -> anyhow::Result<(usize, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = match &s[..end] {
+        "" => 1,
+        digits => digits.parse()?,
+    };
+    Ok((n, &s[end..]))
+}
+
+impl Repository {
+    fn unknown_revision(rev: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "ambiguous argument '{rev}': unknown revision or path not in the working tree"
+        )
+    }
+
+    /// Follow annotated tags to the object they point to
+    fn peel(&self, sha1: &ObjectSha1) -> anyhow::Result<(ObjectSha1, Object)> {
+        let mut sha1 = sha1.clone();
+        loop {
+            match self.load_object(&sha1)?.unwrap() {
+                Object::Tag(tag) => sha1 = tag.object,
+                object => return Ok((sha1, object)),
+            }
+        }
+    }
+
+    /// The commit the revision names, peeling tags
+    fn load_commit_of(&self, sha1: &ObjectSha1, rev: &str) -> anyhow::Result<(ObjectSha1, Commit)> {
+        match self.peel(sha1)? {
+            (sha1, Object::Commit(commit)) => Ok((sha1, commit)),
+            _ => anyhow::bail!("revision '{rev}' is not a commit"),
+        }
+    }
+
+    /// Resolve a name like `HEAD`, `main`, `origin/main` or an abbreviated
+    /// sha1, in the same order as git
+    fn resolve_name(&self, name: &str) -> anyhow::Result<ObjectSha1> {
+        let sha1 = ObjectSha1::from(name.to_ascii_lowercase());
+        if sha1.is_valid() {
+            return Ok(sha1);
+        }
+
+        let mut refnames = Vec::new();
+        // only special refs like HEAD and MERGE_HEAD are at the top level
+        if name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+            refnames.push(name.to_string());
+        }
+        for dir in ["refs/", "refs/tags/", "refs/heads/", "refs/remotes/"] {
+            refnames.push(format!("{dir}{name}"));
+        }
+        refnames.push(format!("refs/remotes/{name}/HEAD"));
+
+        for refname in refnames {
+            if let Some(sha1) = read_ref(&self.root, &refname)? {
+                return Ok(sha1);
+            }
+        }
+        self.resolve_object(name)
+            .map_err(|_| Self::unknown_revision(name))
+    }
+}
+
+impl RevParseService for Repository {
+    fn rev_parse(&self, rev: &str) -> anyhow::Result<ObjectSha1> {
+        let (name, mut suffix) = split_suffix(rev);
+
+        let mut sha1 = if let Some((branch, spec)) = name.split_once("@{") {
+            let spec = spec
+                .strip_suffix('}')
+                .ok_or_else(|| Self::unknown_revision(rev))?;
+            // only the stash has a reflog
+            if let (Ok(n), "stash" | "refs/stash") = (spec.parse::<usize>(), branch) {
+                let list = self.stash_list()?;
+                let Some(entry) = list.entries.get(n) else {
+                    anyhow::bail!("log for 'stash' only has {} entries", list.entries.len());
+                };
+                entry.commit.clone()
+            } else {
+                if !matches!(spec.to_lowercase().as_str(), "upstream" | "u") {
+                    anyhow::bail!(
+                        "revision '{rev}' is not supported, only @{{upstream}} and stash@{{n}} are"
+                    );
+                }
+                let branch = match branch {
+                    "" => self.head().branch_name.clone(),
+                    branch => branch.to_string(),
+                };
+                let upstream = self.upstream_of(&branch)?;
+                read_ref(&self.root, &upstream)?
+                    .ok_or_else(|| anyhow::anyhow!("upstream {upstream} does not exist"))?
+            }
+        } else {
+            match name {
+                "" => return Err(Self::unknown_revision(rev)),
+                "@" => self.resolve_name("HEAD")?,
+                name => self.resolve_name(name)?,
+            }
+        };
+
+        while !suffix.is_empty() {
+            if let Some(rest) = suffix.strip_prefix('~') {
+                let (n, rest) = split_number(rest)?;
+                for _ in 0..n {
+                    let (_, commit) = self.load_commit_of(&sha1, rev)?;
+                    sha1 = commit
+                        .parents
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| Self::unknown_revision(rev))?;
+                }
+                suffix = rest;
+            } else if let Some(rest) = suffix.strip_prefix("^{") {
+                let (peel, rest) = rest
+                    .split_once('}')
+                    .ok_or_else(|| Self::unknown_revision(rev))?;
+                let object = self.load_object(&sha1)?.unwrap();
+                let (peeled, peeled_object) = self.peel(&sha1)?;
+                sha1 = match (peel, object, peeled_object) {
+                    ("object", ..) => sha1,
+                    ("tag", Object::Tag(_), _) => sha1,
+                    ("", ..) => peeled,
+                    ("commit", _, Object::Commit(_)) => peeled,
+                    ("tree", _, Object::Commit(commit)) => commit.tree,
+                    ("tree", _, Object::Tree(_)) => peeled,
+                    ("blob", _, Object::Blob(_)) => peeled,
+                    (peel, object, _) => anyhow::bail!(
+                        "revision '{rev}' is a {}, which can not be peeled to {peel}",
+                        object.object_type()
+                    ),
+                };
+                suffix = rest;
+            } else if let Some(rest) = suffix.strip_prefix('^') {
+                let (n, rest) = split_number(rest)?;
+                let (peeled, commit) = self.load_commit_of(&sha1, rev)?;
+                sha1 = peeled;
+                if n != 0 {
+                    sha1 = commit
+                        .parents
+                        .into_iter()
+                        .nth(n - 1)
+                        .ok_or_else(|| Self::unknown_revision(rev))?;
+                }
+                suffix = rest;
+            } else {
+                return Err(Self::unknown_revision(rev));
+            }
+        }
+
+        Ok(sha1)
+    }
+
+    fn rev_parse_commit(&self, rev: &str) -> anyhow::Result<ObjectSha1> {
+        let (sha1, _) = self.load_commit_of(&self.rev_parse(rev)?, rev)?;
+        Ok(sha1)
+    }
+
+    fn rev_parse_range(&self, revs: &[String]) -> anyhow::Result<RevRange> {
+        let mut range = RevRange::default();
+        let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+
+        for rev in revs {
+            if let Some((a, b)) = rev.split_once("...") {
+                let (a, b) = (or_head(a), or_head(b));
+                let a = self.rev_parse_commit(&a)?;
+                let b = self.rev_parse_commit(&b)?;
+                range.exclude.extend(self.merge_bases(&a, &b)?);
+                range.include.extend([b, a]);
+            } else if let Some((a, b)) = rev.split_once("..") {
+                range.exclude.push(self.rev_parse_commit(&or_head(a))?);
+                range.include.push(self.rev_parse_commit(&or_head(b))?);
+            } else if let Some(rev) = rev.strip_prefix('^').filter(|r| !r.is_empty()) {
+                range.exclude.push(self.rev_parse_commit(rev)?);
+            } else {
+                range.include.push(self.rev_parse_commit(rev)?);
+            }
+        }
+
+        Ok(range)
+    }
+
+    fn upstream_of(&self, branch: &str) -> anyhow::Result<String> {
+        let config = Config::load(&self.root)?;
+        let (Some(remote), Some(merge)) = (
+            config.get(&format!("branch.{branch}.remote")),
+            config.get(&format!("branch.{branch}.merge")),
+        ) else {
+            anyhow::bail!("no upstream configured for branch '{branch}'");
+        };
+
+        if remote == "." {
+            return Ok(merge.to_string());
+        }
+        let name = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+        Ok(format!("refs/remotes/{remote}/{name}"))
+    }
+}
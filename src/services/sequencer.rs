@@ -0,0 +1,332 @@
+//! Apply or revert commits one by one, for `cherry-pick` and `revert`
+
+use crate::console_output;
+use crate::models::object::{ObjectSha1, Sha1Able};
+use crate::models::repo::Repository;
+use crate::models::sequencer::{Action, Sequencer};
+use crate::models::tree::Tree;
+use crate::services::commit::CommitService;
+use crate::services::merge::{MergeOptions, print_conflict};
+use crate::services::object::ObjectService;
+use crate::services::reset::{ResetMode, ResetService};
+use crate::services::tree::{ComparedKind, compare_trees};
+
+pub trait SequencerService {
+    /// Apply the action to the commits one by one on the current branch, each
+    /// as a new commit. It stops at conflicts, to be finished by
+    /// [SequencerService::sequencer_continue] or undone by
+    /// [SequencerService::sequencer_abort]
+    fn apply_commits(&self, action: Action, commits: &[ObjectSha1]) -> anyhow::Result<()>;
+    /// Commit the stopped commit after its conflicts are resolved, and apply
+    /// the remaining ones
+    fn sequencer_continue(&self) -> anyhow::Result<()>;
+    /// Reset the branch, the index and the working tree to before the
+    /// `cherry-pick` or `revert`
+    fn sequencer_abort(&self) -> anyhow::Result<()>;
+}
+
+/// The command of the action, used in messages
+fn command_of(action: Action) -> &'static str {
+    match action {
+        Action::Revert => "revert",
+        _ => "cherry-pick",
+    }
+}
+
+impl Repository {
+    /// Print the commit at the current branch, like `[main 2d8a4e1] subject`
+    fn print_head_commit(&self) -> anyhow::Result<()> {
+        let branch = self.head().load_branch()?;
+        let commit = self.load_commit(&branch.head)?;
+        let subject = commit.message.lines().next().unwrap_or_default();
+        console_output!(
+            "[{} {}] {subject}",
+            branch.full_name(),
+            &branch.head.as_str()[..7]
+        );
+        Ok(())
+    }
+
+    /// Fail if a merge is in progress, or the index or the tracked files in
+    /// the working tree differ from `HEAD`, as the command would overwrite
+    /// them. Returns `HEAD`
+    pub fn check_can_apply(&self, command: &str) -> anyhow::Result<ObjectSha1> {
+        if self.merge_state()?.is_some() {
+            anyhow::bail!("You have not concluded your merge (MERGE_HEAD exists).");
+        }
+        let head = self.head().load_branch()?.unwrap().head;
+        let stage = self.stage()?;
+        let unstaged = compare_trees(&self.wrap(stage.tree.clone()), &self.working_tree()?)?
+            .into_iter()
+            .any(|change| change.kind != ComparedKind::Added);
+        if !stage.unmerged.is_empty()
+            || stage.tree.sha1() != self.tree_of(&head)?.sha1()
+            || unstaged
+        {
+            anyhow::bail!(
+                "your local changes would be overwritten by {command}.\n\
+                 hint: commit your changes or stash them to proceed."
+            );
+        }
+        Ok(head)
+    }
+
+    /// Merge the changes of the commit into the tree of `HEAD`, or the
+    /// reverse of them for [Action::Revert]. Conflicts are written to the
+    /// working tree and the index, and then `None` is returned
+    pub fn apply_changes(&self, action: Action, sha1: &ObjectSha1) -> anyhow::Result<Option<Tree>> {
+        let commit = self.load_commit(sha1)?;
+        let subject = commit.message.lines().next().unwrap_or_default();
+        let short = &sha1.as_str()[..7];
+        let parent_tree = match commit.parents.as_slice() {
+            [] => Tree::empty(),
+            [parent] => self.tree_of(parent)?,
+            _ => anyhow::bail!("commit {sha1} is a merge but no -m option was given."),
+        };
+
+        let label = format!("{short} ({subject})");
+        let parent_label = format!("parent of {label}");
+        let (base, theirs, base_label, theirs_label) = match action {
+            Action::Revert => (self.tree_of(sha1)?, parent_tree, label, parent_label),
+            _ => (parent_tree, self.tree_of(sha1)?, parent_label, label),
+        };
+        let head = self.head().load_branch()?.unwrap().head;
+        let mut options =
+            self.merge_tree_options(&MergeOptions::default(), &[], ["HEAD", &theirs_label])?;
+        options.file.labels[1] = base_label;
+        let (tree, conflicts) = self.merge_trees(base, self.tree_of(&head)?, theirs, &options)?;
+        if conflicts.is_empty() {
+            return Ok(Some(tree));
+        }
+
+        self.write_conflicts(tree, &conflicts)?;
+        for conflict in &conflicts {
+            print_conflict(conflict, "HEAD", &theirs_label);
+        }
+        let verb = match action {
+            Action::Revert => "revert",
+            _ => "apply",
+        };
+        console_output!("error: could not {verb} {short}... {subject}");
+        Ok(None)
+    }
+
+    /// Apply the action to the commit on the current branch, and commit it.
+    /// Returns the message of the commit, if it is stopped by conflicts or
+    /// the result is empty
+    fn apply_action(&self, action: Action, sha1: &ObjectSha1) -> anyhow::Result<Option<String>> {
+        let commit = self.load_commit(sha1)?;
+        let message = match action {
+            Action::Revert => {
+                let subject = commit.message.lines().next().unwrap_or_default();
+                format!("Revert \"{subject}\"\n\nThis reverts commit {sha1}.")
+            }
+            _ => commit.message.clone(),
+        };
+        let Some(tree) = self.apply_changes(action, sha1)? else {
+            return Ok(Some(message));
+        };
+        let head = self.head().load_branch()?.unwrap().head;
+        if tree.sha1() == self.tree_of(&head)?.sha1() {
+            console_output!(
+                "The previous {} is now empty, possibly due to conflict resolution.",
+                command_of(action)
+            );
+            return Ok(Some(message));
+        }
+        let author = (action == Action::Pick).then_some(commit.author);
+        self.commit_merge(tree, vec![head], message, author, false)?;
+        self.print_head_commit()?;
+        Ok(None)
+    }
+
+    /// Apply the commits in the todo list, until all are done or one stops
+    fn run_sequencer(&self, mut sequencer: Sequencer) -> anyhow::Result<()> {
+        while let Some((action, sha1)) = sequencer.todo.first().cloned() {
+            if let Some(message) = self.apply_action(action, &sha1)? {
+                sequencer.message = message;
+                self.wrap(sequencer).save()?;
+                console_output!(
+                    "hint: after resolving the conflicts, mark the corrected paths\n\
+                     hint: with 'git add <paths>' or 'git rm <paths>'\n\
+                     hint: and finish with 'git {} --continue'",
+                    command_of(action)
+                );
+                return Ok(());
+            }
+            sequencer.todo.remove(0);
+        }
+        if let Some(sequencer) = self.sequencer()? {
+            sequencer.remove()?;
+        }
+        Ok(())
+    }
+}
+
+impl SequencerService for Repository {
+    fn apply_commits(&self, action: Action, commits: &[ObjectSha1]) -> anyhow::Result<()> {
+        if self.sequencer()?.is_some() {
+            anyhow::bail!(
+                "a cherry-pick or revert is already in progress\n\
+                 hint: try \"git {} (--continue | --abort)\"",
+                command_of(action)
+            );
+        }
+        let head = self.check_can_apply(command_of(action))?;
+        self.run_sequencer(Sequencer {
+            head,
+            todo: commits.iter().map(|sha1| (action, sha1.clone())).collect(),
+            message: String::new(),
+        })
+    }
+
+    fn sequencer_continue(&self) -> anyhow::Result<()> {
+        let Some(sequencer) = self.sequencer()? else {
+            anyhow::bail!("no cherry-pick or revert in progress");
+        };
+        let mut sequencer = sequencer.unwrap();
+        // the stopped commit may be committed by the user, or dropped if it
+        // is resolved as empty
+        let head = self.head().load_branch()?.unwrap().head;
+        let stage = self.stage()?;
+        if !stage.unmerged.is_empty() || stage.tree.sha1() != self.tree_of(&head)?.sha1() {
+            // like git, a picked commit keeps its author
+            let author = match sequencer.todo.first() {
+                Some((Action::Pick, sha1)) => Some(self.load_commit(sha1)?.author),
+                _ => None,
+            };
+            self.create_commit(&sequencer.message, author)?;
+            self.print_head_commit()?;
+        }
+        if !sequencer.todo.is_empty() {
+            sequencer.todo.remove(0);
+        }
+        self.run_sequencer(sequencer)
+    }
+
+    fn sequencer_abort(&self) -> anyhow::Result<()> {
+        let Some(sequencer) = self.sequencer()? else {
+            anyhow::bail!("no cherry-pick or revert in progress");
+        };
+        // the reset also removes the sequencer
+        self.reset(&sequencer.head, ResetMode::Hard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        branch::BranchService,
+        reachable::ReachableService,
+        testing::{head_of, temp_repo},
+    };
+
+    /// A repository on `master`, with the commits of `feature` which are not
+    /// on it. The first one conflicts with `master`, the second does not.
+    /// `feature` is deleted, so they are only kept by the sequencer
+    fn picking_repo() -> (tempfile::TempDir, Repository, [ObjectSha1; 2]) {
+        let (dir, mut repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        repo.create_branch("feature", None).unwrap();
+        repo.checkout_branch("feature", false).unwrap();
+        let conflicting = repo.commit_files(&[("a.txt", Some("feature\n"))], "conflicting");
+        let clean = repo.commit_files(&[("b.txt", Some("b\n"))], "clean");
+        repo.checkout_branch("master", false).unwrap();
+        repo.delete_branch("feature").unwrap();
+        repo.commit_files(&[("a.txt", Some("master\n"))], "master");
+        (dir, repo, [conflicting, clean])
+    }
+
+    #[test]
+    fn cherry_pick_and_revert() {
+        let (_dir, repo, [_, clean]) = picking_repo();
+        let head = head_of(&repo);
+
+        repo.apply_commits(Action::Pick, std::slice::from_ref(&clean))
+            .unwrap();
+        let picked = repo.load_commit(&head_of(&repo)).unwrap();
+        let original = repo.load_commit(&clean).unwrap();
+        assert_eq!(picked.parents, vec![head]);
+        assert_eq!(picked.message, original.message);
+        assert_eq!(picked.author, original.author);
+        assert_eq!(repo.read_file("b.txt"), "b\n");
+        assert!(repo.sequencer().unwrap().is_none());
+
+        let picked_sha1 = head_of(&repo);
+        repo.apply_commits(Action::Revert, std::slice::from_ref(&picked_sha1))
+            .unwrap();
+        let reverted = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(
+            reverted.message,
+            format!("Revert \"clean\"\n\nThis reverts commit {picked_sha1}.\n")
+        );
+        assert!(!repo.working_dir().join("b.txt").exists());
+    }
+
+    #[test]
+    fn cherry_pick_should_stop_at_conflicts_and_continue() {
+        let (_dir, repo, [conflicting, clean]) = picking_repo();
+        let head = head_of(&repo);
+
+        repo.apply_commits(Action::Pick, &[conflicting.clone(), clean.clone()])
+            .unwrap();
+        assert_eq!(head_of(&repo), head);
+        let sequencer = repo.sequencer().unwrap().unwrap().unwrap();
+        assert_eq!(
+            sequencer.todo,
+            vec![(Action::Pick, conflicting.clone()), (Action::Pick, clean)]
+        );
+        assert!(repo.stage().unwrap().unmerged.contains_key("a.txt"));
+        // the commits are only kept by the sequencer
+        let roots = repo.ref_roots().unwrap();
+        assert!(roots.contains(&conflicting));
+
+        repo.write_files(&[("a.txt", Some("resolved\n"))]);
+        repo.add_files(&["a.txt"]);
+        repo.sequencer_continue().unwrap();
+        assert!(repo.sequencer().unwrap().is_none());
+        let last = repo.load_commit(&head_of(&repo)).unwrap();
+        assert_eq!(last.message, "clean\n");
+        let resolved = repo.load_commit(&last.parents[0]).unwrap();
+        assert_eq!(resolved.parents, vec![head]);
+        assert_eq!(
+            resolved.message,
+            repo.load_commit(&conflicting).unwrap().message
+        );
+        assert_eq!(repo.read_file("a.txt"), "resolved\n");
+        assert_eq!(repo.read_file("b.txt"), "b\n");
+    }
+
+    #[test]
+    fn cherry_pick_abort() {
+        let (_dir, repo, [conflicting, clean]) = picking_repo();
+        let head = head_of(&repo);
+        repo.write_files(&[("new.txt", Some("new\n"))]);
+
+        repo.apply_commits(Action::Pick, &[conflicting, clean])
+            .unwrap();
+        repo.sequencer_abort().unwrap();
+        assert!(repo.sequencer().unwrap().is_none());
+        assert_eq!(head_of(&repo), head);
+        assert_eq!(repo.read_file("a.txt"), "master\n");
+        assert!(repo.stage().unwrap().unmerged.is_empty());
+        // untracked files are kept
+        assert_eq!(repo.read_file("new.txt"), "new\n");
+    }
+
+    #[test]
+    fn cherry_pick_should_not_overwrite_local_changes() {
+        let (_dir, repo, [conflicting, _]) = picking_repo();
+        repo.write_files(&[("a.txt", Some("local\n"))]);
+        let err = repo
+            .apply_commits(Action::Pick, &[conflicting])
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("your local changes would be overwritten by cherry-pick.")
+        );
+        assert_eq!(repo.read_file("a.txt"), "local\n");
+        assert!(repo.sequencer().unwrap().is_none());
+    }
+}
@@ -1,4 +1,8 @@
-use crate::models::{repo::WithRepo, stage::Stage};
+use crate::models::{
+    repo::{Repository, WithRepo},
+    stage::Stage,
+};
+use std::{io, path::PathBuf};
 
 use super::mut_tree::MutableTree;
 
@@ -11,9 +15,83 @@ impl<'a> StageService<'a> for WithRepo<'a, Stage> {
         WithRepo::new(
             self.repo,
             MutableTree {
-                data: self.unwrap().0.into_map(),
+                data: self.unwrap().tree.into_map(),
                 save_object: true,
             },
         )
     }
 }
+
+impl Repository {
+    /// Stage the paths like `git add`, and save the stage. Adding a conflicted
+    /// path marks it as resolved
+    pub fn add_paths(&self, paths: &[PathBuf]) -> io::Result<()> {
+        let stage = self.stage()?;
+        let (unmerged, subtrees) = (stage.unmerged.clone(), stage.subtrees.clone());
+        let mut muter = stage.into_muter();
+
+        for path in paths {
+            muter.add_path(path)?;
+        }
+
+        let mut stage = muter.freeze().map(|tree| Stage {
+            tree,
+            unmerged,
+            subtrees,
+        });
+        for path in paths {
+            stage.resolve(&self.relative_path(path));
+        }
+        stage.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{repo::Repository, tree::TreeLineKind},
+        services::{object::ObjectService, testing::temp_repo},
+    };
+
+    /// Full paths of all files in the stage
+    fn staged(repo: &Repository) -> Vec<String> {
+        let mut files = Vec::new();
+        let mut queue = vec![(String::new(), repo.stage().unwrap().unwrap().tree)];
+        while let Some((prefix, tree)) = queue.pop() {
+            for line in tree.objects {
+                let name = format!("{prefix}{}", line.name);
+                if line.kind == TreeLineKind::Tree {
+                    let tree = repo.load_object(&line.sha1).unwrap().unwrap();
+                    queue.push((format!("{name}/"), tree.cast_tree()));
+                } else {
+                    files.push(name);
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn add_paths_should_stage_files_in_their_directories() {
+        let (_dir, repo) = temp_repo();
+        repo.write_files(&[
+            ("a.txt", Some("a\n")),
+            ("d/b.txt", Some("b\n")),
+            ("d/e/c.txt", Some("c\n")),
+        ]);
+        repo.add_files(&["a.txt", "d/b.txt"]);
+        assert_eq!(staged(&repo), ["a.txt", "d/b.txt"]);
+        repo.add_files(&["d/e/c.txt"]);
+        assert_eq!(staged(&repo), ["a.txt", "d/b.txt", "d/e/c.txt"]);
+
+        // removed files are removed, and so are the emptied directories
+        repo.write_files(&[("d/b.txt", None)]);
+        repo.add_files(&["d/b.txt"]);
+        assert_eq!(staged(&repo), ["a.txt", "d/e/c.txt"]);
+        repo.write_files(&[("d/e/c.txt", None)]);
+        repo.add_files(&["d/e/c.txt"]);
+        assert_eq!(staged(&repo), ["a.txt"]);
+        assert_eq!(repo.stage().unwrap().objects.len(), 1);
+    }
+}
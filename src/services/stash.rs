@@ -0,0 +1,314 @@
+//! Save changes of the index and the working tree away, and restore them
+
+use crate::console_output;
+use crate::models::blob::Blob;
+use crate::models::commit::{Commit, CommitBuilder};
+use crate::models::object::{Object, ObjectSha1, Sha1Able};
+use crate::models::repo::Repository;
+use crate::models::stage::Stage;
+use crate::models::stash::StashEntry;
+use crate::models::tree::{Tree, TreeLine};
+use crate::services::dump_tree::DumpTreeService;
+use crate::services::identity::Role;
+use crate::services::merge::{MergeOptions, print_conflict};
+use crate::services::object::ObjectService;
+use crate::services::reset::{ResetMode, ResetService};
+use crate::services::rev_parse::RevParseService;
+use crate::services::tree::{ComparedKind, build_tree, compare_trees, flatten_tree, path_matches};
+use std::collections::BTreeMap;
+use std::{fs, io};
+
+pub trait StashService {
+    /// Save the changes of the index and the working tree as a new stash
+    /// entry, and reset them to `HEAD`. Untracked files are also saved and
+    /// removed if `include_untracked`. Returns `false` if there is nothing to
+    /// save
+    fn stash_push(&self, message: Option<&str>, include_untracked: bool) -> anyhow::Result<bool>;
+    /// Apply the changes of `stash@{n}` to the working tree by a three-way
+    /// merge, and also to the index if `index`. Returns `false` if there are
+    /// conflicts
+    fn stash_apply(&self, n: usize, index: bool) -> anyhow::Result<bool>;
+    /// Remove `stash@{n}`, returns its commit
+    fn stash_drop(&self, n: usize) -> anyhow::Result<ObjectSha1>;
+}
+
+impl Repository {
+    /// Save the content of the file in the working directory as a blob
+    fn save_working_file(&self, line: &TreeLine) -> io::Result<TreeLine> {
+        let content = fs::read(self.working_dir().join(&line.name))?;
+        let blob = self.wrap(Object::Blob(Blob::from_content(&content)));
+        blob.save()?;
+        Ok(TreeLine {
+            sha1: blob.sha1().into(),
+            ..line.clone()
+        })
+    }
+
+    /// Save the tree, and a commit of it
+    fn commit_tree(
+        &self,
+        tree: Tree,
+        parents: Vec<ObjectSha1>,
+        message: String,
+    ) -> anyhow::Result<ObjectSha1> {
+        self.save_tree(&tree)?;
+        let tree = self.wrap(Object::Tree(tree));
+        let commit = self.wrap(Object::Commit(Commit::new(CommitBuilder {
+            tree: tree.sha1().into(),
+            parents,
+            author: self.signature(Role::Author)?,
+            committer: self.signature(Role::Committer)?,
+            message,
+        })));
+        commit.save()?;
+        Ok(commit.sha1().into())
+    }
+
+    /// The entry `stash@{n}`, or fail if it does not exist
+    pub fn stash_entry(&self, n: usize) -> anyhow::Result<StashEntry> {
+        let list = self.stash_list()?;
+        if list.entries.is_empty() {
+            anyhow::bail!("No stash entries found.");
+        }
+        match list.entries.get(n) {
+            Some(entry) => Ok(entry.clone()),
+            None => anyhow::bail!("stash@{{{n}}} is not a valid reference"),
+        }
+    }
+
+    /// Remove the file, and the directories left empty by it
+    fn remove_working_file(&self, name: &str) -> io::Result<()> {
+        let path = self.working_dir().join(name);
+        fs::remove_file(&path)?;
+        for dir in path.ancestors().skip(1) {
+            if dir == self.working_dir() || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StashService for Repository {
+    fn stash_push(&self, message: Option<&str>, include_untracked: bool) -> anyhow::Result<bool> {
+        // the head may be detached from branches
+        let Ok(head) = self.rev_parse_commit("HEAD") else {
+            anyhow::bail!("You do not have the initial commit yet");
+        };
+        let stage = self.stage()?.unwrap();
+        if let Some(file) = stage.unmerged.keys().next() {
+            anyhow::bail!("{file}: needs merge\ncould not save index tree");
+        }
+
+        // the working tree is the index with changes of tracked files
+        let index_tree = stage.tree;
+        let mut files = flatten_tree(&self.wrap(index_tree.clone()))?;
+        let mut untracked = BTreeMap::new();
+        for change in compare_trees(&self.wrap(index_tree.clone()), &self.working_tree()?)? {
+            if change.kind == ComparedKind::Added {
+                if include_untracked {
+                    for file in self.files_of(change.line)? {
+                        untracked.insert(file.name.clone(), self.save_working_file(&file)?);
+                    }
+                }
+                continue;
+            }
+            files.retain(|name, _| !path_matches(&change.line.name, name));
+            if change.kind != ComparedKind::Deleted {
+                for file in self.files_of(change.line)? {
+                    files.insert(file.name.clone(), self.save_working_file(&file)?);
+                }
+            }
+        }
+        let working_tree = build_tree(self, &files)?;
+        let head_tree = self.tree_of(&head)?.sha1();
+        if index_tree.sha1() == head_tree
+            && working_tree.sha1() == head_tree
+            && untracked.is_empty()
+        {
+            return Ok(false);
+        }
+
+        let head_commit = self.load_commit(&head)?;
+        let subject = head_commit.message.lines().next().unwrap_or_default();
+        let branch_name = match self.head().detached() {
+            Some(_) => "(no branch)",
+            None => &self.head().branch_name,
+        };
+        let on = format!("{branch_name}: {} {subject}", &head.as_str()[..7]);
+        let mut parents = vec![head.clone()];
+        parents.push(self.commit_tree(index_tree, vec![head.clone()], format!("index on {on}"))?);
+        if !untracked.is_empty() {
+            let tree = build_tree(self, &untracked)?;
+            parents.push(self.commit_tree(tree, vec![], format!("untracked files on {on}"))?);
+        }
+        let message = match message {
+            Some(message) => format!("On {branch_name}: {message}"),
+            None => format!("WIP on {on}"),
+        };
+        let commit = self.commit_tree(working_tree, parents, message.clone())?;
+
+        let mut list = self.stash_list()?.unwrap();
+        list.entries.insert(
+            0,
+            StashEntry {
+                commit,
+                signature: self.signature(Role::Committer)?.to_string(),
+                message: message.clone(),
+            },
+        );
+        self.wrap(list).save()?;
+
+        self.reset(&head, ResetMode::Hard)?;
+        for name in untracked.keys() {
+            self.remove_working_file(name)?;
+        }
+        console_output!("Saved working directory and index state {message}");
+        Ok(true)
+    }
+
+    fn stash_apply(&self, n: usize, index: bool) -> anyhow::Result<bool> {
+        let entry = self.stash_entry(n)?;
+        let stash = self.load_commit(&entry.commit)?;
+        let [base, index_commit, untracked @ ..] = stash.parents.as_slice() else {
+            anyhow::bail!("{} is not a stash-like commit", entry.commit);
+        };
+        let stage = self.stage()?.unwrap();
+        if !stage.unmerged.is_empty() {
+            anyhow::bail!("Cannot apply a stash in the middle of a merge");
+        }
+
+        let ours = stage.tree;
+        let base_tree = self.tree_of(base)?;
+        let names = ["Updated upstream", "Stashed changes"];
+        let mut options = self.merge_tree_options(&MergeOptions::default(), &[], names)?;
+        options.file.labels[1] = "Version stash was based on".to_string();
+        let (merged, conflicts) = self.merge_trees(
+            base_tree.clone(),
+            ours.clone(),
+            self.tree_of(&entry.commit)?,
+            &options,
+        )?;
+
+        // local changes are kept, unless the stash changes the same files
+        self.check_overwritten(&ours, &merged)?;
+        let untracked = match untracked.first() {
+            Some(commit) => flatten_tree(&self.wrap(self.tree_of(commit)?))?,
+            None => BTreeMap::new(),
+        };
+        let existing: Vec<_> = untracked
+            .keys()
+            .filter(|name| self.working_dir().join(name).exists())
+            .collect();
+        if !existing.is_empty() {
+            for name in existing {
+                console_output!("{name} already exists, no checkout");
+            }
+            anyhow::bail!("could not restore untracked files from stash");
+        }
+
+        let index_tree = if index {
+            let (tree, conflicts) = self.merge_trees(
+                base_tree,
+                ours.clone(),
+                self.tree_of(index_commit)?,
+                &options,
+            )?;
+            if !conflicts.is_empty() {
+                anyhow::bail!("Conflicts in index. Try without --index.");
+            }
+            tree
+        } else if !conflicts.is_empty() {
+            // like git, the merged changes are staged to resolve conflicts
+            merged.clone()
+        } else {
+            // like git, only files added by the stash are staged
+            let mut files = flatten_tree(&self.wrap(ours.clone()))?;
+            for (name, line) in flatten_tree(&self.wrap(merged.clone()))? {
+                files.entry(name).or_insert(line);
+            }
+            build_tree(self, &files)?
+        };
+
+        self.dump_tree_from(&self.wrap(ours), &self.wrap(merged))?;
+        for (name, line) in &untracked {
+            self.dump_line(line, &self.working_dir().join(name))?;
+        }
+        let mut stage = Stage::from(index_tree);
+        for conflict in &conflicts {
+            stage
+                .unmerged
+                .insert(conflict.file.clone(), conflict.versions.clone());
+            print_conflict(conflict, names[0], names[1]);
+        }
+        self.wrap(stage).save()?;
+        Ok(conflicts.is_empty())
+    }
+
+    fn stash_drop(&self, n: usize) -> anyhow::Result<ObjectSha1> {
+        let entry = self.stash_entry(n)?;
+        let mut list = self.stash_list()?.unwrap();
+        list.entries.remove(n);
+        self.wrap(list).save()?;
+        Ok(entry.commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{reachable::ReachableService, testing::temp_repo};
+
+    #[test]
+    fn stash_push_and_apply() {
+        let (_dir, repo) = temp_repo();
+        repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        assert!(!repo.stash_push(None, false).unwrap());
+
+        repo.write_files(&[("b.txt", Some("b\n"))]);
+        repo.add_files(&["b.txt"]);
+        repo.write_files(&[("a.txt", Some("changed\n")), ("c.txt", Some("c\n"))]);
+        assert!(repo.stash_push(Some("work"), true).unwrap());
+        let entry = repo.stash_entry(0).unwrap();
+        assert_eq!(entry.message, "On master: work");
+        assert_eq!(repo.load_commit(&entry.commit).unwrap().parents.len(), 3);
+        assert_eq!(repo.read_file("a.txt"), "a\n");
+        for file in ["b.txt", "c.txt"] {
+            assert!(!repo.working_dir().join(file).exists());
+        }
+        // the stash is only kept by its reflog
+        let roots = repo.state_roots().unwrap();
+        assert!(roots.contains(&("stash@{0}".to_string(), entry.commit.clone())));
+
+        assert!(repo.stash_apply(0, true).unwrap());
+        assert_eq!(repo.read_file("a.txt"), "changed\n");
+        assert_eq!(repo.read_file("b.txt"), "b\n");
+        assert_eq!(repo.read_file("c.txt"), "c\n");
+        let stage = repo.stage().unwrap().unwrap();
+        let staged = flatten_tree(&repo.wrap(stage.tree)).unwrap();
+        assert!(staged.contains_key("b.txt"));
+        assert!(!staged.contains_key("c.txt"));
+
+        assert_eq!(repo.stash_drop(0).unwrap(), entry.commit);
+        assert!(repo.stash_entry(0).is_err());
+    }
+
+    #[test]
+    fn stash_push_on_detached_head() {
+        let (dir, repo) = temp_repo();
+        let first = repo.commit_files(&[("a.txt", Some("a\n"))], "first");
+        fs::write(repo.root.join("HEAD"), format!("{first}\n")).unwrap();
+        let repo = Repository::load_at(dir.path()).unwrap();
+
+        repo.write_files(&[("a.txt", Some("changed\n"))]);
+        assert!(repo.stash_push(None, false).unwrap());
+        let entry = repo.stash_entry(0).unwrap();
+        assert_eq!(
+            entry.message,
+            format!("WIP on (no branch): {} first", &first.as_str()[..7])
+        );
+        assert_eq!(repo.load_commit(&entry.commit).unwrap().parents[0], first);
+        assert_eq!(repo.head().detached(), Some(&first));
+        assert_eq!(repo.read_file("a.txt"), "a\n");
+    }
+}
@@ -0,0 +1,96 @@
+//! Changes in the index and the working tree, shown by `git status`
+
+use crate::{
+    models::{config::Config, repo::Repository, tree::Tree},
+    services::{
+        rev_parse::RevParseService,
+        tree::{ComparedLine, RenameOptions, compare_trees, detect_renames},
+    },
+};
+
+pub struct StatusChanges {
+    /// changes from `HEAD` to the index
+    pub staged: Vec<ComparedLine>,
+    /// changes from the index to the working tree, untracked files are added
+    pub unstaged: Vec<ComparedLine>,
+}
+
+pub trait StatusService {
+    /// Changes to be committed and not staged, sorted by their paths, without
+    /// unmerged paths. Renames are detected like `diff.renames`
+    fn status_changes(&self) -> anyhow::Result<StatusChanges>;
+}
+
+impl StatusService for Repository {
+    fn status_changes(&self) -> anyhow::Result<StatusChanges> {
+        let stage = self.stage()?;
+        // before the first commit, everything in the index is new
+        let head_tree = match self.rev_parse_commit("HEAD") {
+            Ok(head) => self.tree_of(&head)?,
+            Err(_) => Tree::empty(),
+        };
+        let renames = RenameOptions::from_config(&Config::load(&self.root)?)?;
+
+        let mut staged = compare_trees(&self.wrap(head_tree), &self.wrap(stage.tree.clone()))?;
+        let mut unstaged = compare_trees(&self.wrap(stage.tree.clone()), &self.working_tree()?)?;
+        if let Some(options) = &renames {
+            staged = detect_renames(self, staged, options, false)?;
+            unstaged = detect_renames(self, unstaged, options, true)?;
+        }
+        for changes in [&mut staged, &mut unstaged] {
+            changes.retain(|x| !stage.unmerged.contains_key(&x.line.name));
+            changes.sort_by(|a, b| a.line.name.cmp(&b.line.name));
+        }
+        Ok(StatusChanges { staged, unstaged })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{testing::temp_repo, tree::ComparedKind};
+    use std::fs;
+
+    /// The kinds and the paths of the changes, renames are `old -> new`
+    fn summary(changes: &[ComparedLine]) -> Vec<(ComparedKind, String)> {
+        changes
+            .iter()
+            .map(|change| match (&change.kind, &change.old) {
+                (ComparedKind::Renamed { .. }, Some(old)) => {
+                    (change.kind, format!("{} -> {}", old.name, change.line.name))
+                }
+                _ => (change.kind, change.line.name.clone()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn status_should_show_moved_files() {
+        let (_dir, repo) = temp_repo();
+        let content = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        repo.commit_files(&[("a.txt", Some(content)), ("b.txt", Some("b\n"))], "first");
+        let dir = repo.working_dir();
+        fs::create_dir(dir.join("d")).unwrap();
+        fs::rename(dir.join("a.txt"), dir.join("d/a.txt")).unwrap();
+        let renamed = ComparedKind::Renamed { similarity: 100 };
+
+        let changes = repo.status_changes().unwrap();
+        assert!(changes.staged.is_empty());
+        assert_eq!(
+            summary(&changes.unstaged),
+            [(renamed, "a.txt -> d/a.txt".to_string())]
+        );
+
+        repo.write_files(&[("d/a.txt", Some(&content.replace('5', "five")))]);
+        repo.add_files(&["a.txt", "d/a.txt"]);
+        let changes = repo.status_changes().unwrap();
+        assert_eq!(
+            summary(&changes.staged),
+            [(
+                ComparedKind::Renamed { similarity: 76 },
+                "a.txt -> d/a.txt".to_string()
+            )]
+        );
+        assert!(changes.unstaged.is_empty());
+    }
+}
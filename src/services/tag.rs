@@ -0,0 +1,149 @@
+//! Create, list, delete and verify tags
+
+use crate::models::{
+    Accessible,
+    branch::{PackedRefs, ref_names},
+    object::{GitObject, Object, ObjectSha1, RawObject, Sha1Able},
+    repo::{Repository, WithRepo},
+    tag::{Tag, TagRef},
+};
+use crate::services::{identity::Role, object::ObjectService};
+use std::{collections::BTreeSet, io};
+
+pub trait TagService {
+    /// load a tag ref by its name, without `refs/tags/`
+    fn load_tag(&self, name: &str) -> io::Result<WithRepo<'_, TagRef>>;
+    /// Names of all tags, sorted
+    fn list_tags(&self) -> io::Result<Vec<String>>;
+    /// Tag the object, with an annotated [Tag] object if there is a message,
+    /// or a lightweight tag otherwise. An existing tag is replaced only if
+    /// `force`, and its old target is returned
+    fn create_tag(
+        &self,
+        name: &str,
+        object: &ObjectSha1,
+        message: Option<&str>,
+        force: bool,
+    ) -> anyhow::Result<Option<ObjectSha1>>;
+    /// Delete the tag, returns what it pointed to
+    fn delete_tag(&self, name: &str) -> anyhow::Result<ObjectSha1>;
+    /// Check that the tag is an annotated tag, and its object exists with the
+    /// recorded type. Signatures are not supported, so they are not checked
+    fn verify_tag(&self, name: &str) -> anyhow::Result<Tag>;
+}
+
+/// Match the tag name with wildcards. Unlike paths, `*` also matches `/`, so
+/// `v1.*` matches `v1.0/rc1`, like `git tag -l`
+pub fn tag_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        [b'*', rest @ ..] => (0..=name.len()).any(|i| tag_matches(rest, &name[i..])),
+        [b'?', rest @ ..] => !name.is_empty() && tag_matches(rest, &name[1..]),
+        [c, rest @ ..] => name.first() == Some(c) && tag_matches(rest, &name[1..]),
+    }
+}
+
+impl TagService for Repository {
+    fn load_tag(&self, name: &str) -> io::Result<WithRepo<'_, TagRef>> {
+        self.wrap(TagRef::accessor(&name.to_string())).load()
+    }
+
+    fn list_tags(&self) -> io::Result<Vec<String>> {
+        let mut names = BTreeSet::new();
+        ref_names(&self.root.join(TagRef::DIRECTORY), "", &mut names)?;
+        let packed = PackedRefs::load(&self.root)?;
+        names.extend(
+            packed
+                .names_under(&format!("{}/", TagRef::DIRECTORY))
+                .map(str::to_string),
+        );
+        Ok(names.into_iter().collect())
+    }
+
+    fn create_tag(
+        &self,
+        name: &str,
+        object: &ObjectSha1,
+        message: Option<&str>,
+        force: bool,
+    ) -> anyhow::Result<Option<ObjectSha1>> {
+        if !TagRef::validate_name(name) {
+            anyhow::bail!("'{name}' is not a valid tag name.");
+        }
+        let old = self.load_tag(name).ok().map(|tag| tag.unwrap().target);
+        if old.is_some() && !force {
+            anyhow::bail!("tag '{name}' already exists");
+        }
+
+        let target = match message {
+            Some(message) => {
+                let mut message = message.to_string();
+                if !message.is_empty() && !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                let tag = self.wrap(Object::Tag(Tag {
+                    object: object.clone(),
+                    object_type: self.load_object(object)?.object_type().to_string(),
+                    name: name.to_string(),
+                    tagger: self.signature(Role::Committer)?.to_string(),
+                    message,
+                }));
+                tag.save()?;
+                tag.sha1().into()
+            }
+            None => object.clone(),
+        };
+        self.wrap(TagRef {
+            name: name.to_string(),
+            target: target.clone(),
+        })
+        .save()?;
+        Ok(old.filter(|old| *old != target))
+    }
+
+    fn delete_tag(&self, name: &str) -> anyhow::Result<ObjectSha1> {
+        let Ok(tag) = self.load_tag(name) else {
+            anyhow::bail!("tag '{name}' not found.");
+        };
+        tag.remove()?;
+        Ok(tag.unwrap().target)
+    }
+
+    fn verify_tag(&self, name: &str) -> anyhow::Result<Tag> {
+        let Ok(tag_ref) = self.load_tag(name) else {
+            anyhow::bail!("tag '{name}' not found.");
+        };
+        let tag = match self.load_object(&tag_ref.target)?.unwrap() {
+            Object::Tag(tag) => tag,
+            object => anyhow::bail!(
+                "{name}: cannot verify a non-tag object of type {}.",
+                object.object_type()
+            ),
+        };
+        let raw = Object::load_raw(&self.root, &tag.object)
+            .map_err(|_| anyhow::anyhow!("{name}: tagged object {} is missing", tag.object))?;
+        if RawObject::type_of(&tag.object_type) != Some(raw.object_type) {
+            anyhow::bail!(
+                "{name}: tagged object {} is a {}, not a {}",
+                tag.object,
+                raw.object_type,
+                tag.object_type
+            );
+        }
+        Ok(tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_matches() {
+        assert!(tag_matches(b"v1.*", b"v1.0"));
+        assert!(tag_matches(b"rc*", b"rc/1"));
+        assert!(tag_matches(b"v?.0", b"v2.0"));
+        assert!(!tag_matches(b"v1.*", b"v2.0"));
+        assert!(!tag_matches(b"v?", b"v"));
+    }
+}
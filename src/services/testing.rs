@@ -0,0 +1,60 @@
+//! Helpers to test services with repositories in temporary directories
+
+use crate::{
+    models::{object::ObjectSha1, repo::Repository},
+    services::commit::{CommitCreateResult, CommitService},
+};
+use std::fs;
+use tempfile::TempDir;
+
+/// A new repository in a temporary directory, which is removed when the
+/// directory is dropped
+pub fn temp_repo() -> (TempDir, Repository) {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = Repository::init_at(dir.path()).unwrap();
+    (dir, repo)
+}
+
+/// The commit of the current branch
+pub fn head_of(repo: &Repository) -> ObjectSha1 {
+    repo.head().load_branch().unwrap().head.clone()
+}
+
+impl Repository {
+    /// Write the files to the working directory, `None` removes the file
+    pub fn write_files(&self, files: &[(&str, Option<&str>)]) {
+        for (path, content) in files {
+            let path = self.working_dir().join(path);
+            match content {
+                Some(content) => {
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    fs::write(path, content).unwrap();
+                }
+                None => fs::remove_file(path).unwrap(),
+            }
+        }
+    }
+
+    /// The content of the file in the working directory
+    pub fn read_file(&self, path: &str) -> String {
+        fs::read_to_string(self.working_dir().join(path)).unwrap()
+    }
+
+    /// Stage the paths like `git add`, removed files are removed from the
+    /// index
+    pub fn add_files(&self, paths: &[&str]) {
+        let paths: Vec<_> = paths.iter().map(|p| self.working_dir().join(p)).collect();
+        self.add_paths(&paths).unwrap();
+    }
+
+    /// Write, stage and commit the files, returns the new commit
+    pub fn commit_files(&self, files: &[(&str, Option<&str>)], message: &str) -> ObjectSha1 {
+        self.write_files(files);
+        let paths: Vec<_> = files.iter().map(|(path, _)| *path).collect();
+        self.add_files(&paths);
+        match self.create_commit(message, None).unwrap() {
+            CommitCreateResult::Success(info) => info.commit_sha1.into(),
+            CommitCreateResult::NothingToCommit => panic!("nothing to commit"),
+        }
+    }
+}
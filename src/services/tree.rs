@@ -1,15 +1,23 @@
 //! Tree Services
 
+use crate::models::attributes::{AttrValue, Attributes};
+use crate::models::config::Config;
 use crate::models::object::Sha1Able;
+use crate::models::repo::Repository;
+use crate::services::diff::{Edit, myers, split_lines};
+use crate::services::merge_file::{Favor, MergeFileOptions, merge_file};
+use crate::services::mut_tree::read_file;
 use crate::{
     models::Accessible,
     models::{
+        blob::Blob,
         object::Object,
         repo::WithRepo,
+        stage::Unmerged,
         tree::{Tree, TreeLine, TreeLineKind},
     },
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::{collections::HashSet, fmt::Display, io, path::Path};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,16 +25,31 @@ pub enum ComparedKind {
     Added,
     Deleted,
     Modified,
+    /// moved from [ComparedLine::old], with the similarity of the contents in
+    /// percent, only found by [detect_renames]
+    Renamed {
+        similarity: u8,
+    },
+    /// copied from [ComparedLine::old], only found by [detect_renames]
+    Copied {
+        similarity: u8,
+    },
 }
 
 pub struct ComparedLine {
     pub kind: ComparedKind,
+    /// the line in the `to` tree, or in the `from` tree if it is deleted
     pub line: TreeLine,
+    /// the line in the `from` tree, if it is modified or deleted
+    pub old: Option<TreeLine>,
 }
 
 impl ComparedLine {
     fn prepent_parent(mut self, path: &Path) -> Self {
         self.line.name = path.join(&self.line.name).to_string_lossy().to_string();
+        if let Some(old) = &mut self.old {
+            old.name = path.join(&old.name).to_string_lossy().to_string();
+        }
         self
     }
 }
@@ -43,6 +66,15 @@ impl Display for ComparedLine {
             ComparedKind::Added => {
                 write!(f, "        new file:   {}", self.line.name)
             }
+            ComparedKind::Renamed { .. } | ComparedKind::Copied { .. } => {
+                let old = self.old.as_ref().map_or("", |old| &old.name);
+                let action = if matches!(self.kind, ComparedKind::Renamed { .. }) {
+                    "renamed:"
+                } else {
+                    "copied:"
+                };
+                write!(f, "        {action:<12}{old} -> {}", self.line.name)
+            }
         }
     }
 }
@@ -64,7 +96,7 @@ fn compare_tree_with_path(
         let item_from = from_map.get(item).copied();
         let item_to = to_map.get(item).copied();
         match (item_from, item_to) {
-            (Some(item_from), Some(item_to)) if item_from.sha1 != item_to.sha1 => {
+            (Some(item_from), Some(item_to)) if !same(item_from, item_to) => {
                 if item_from.kind == TreeLineKind::Tree && item_to.kind == TreeLineKind::Tree {
                     let a = from
                         .wrap(Object::accessor(&item_from.sha1))
@@ -84,6 +116,7 @@ fn compare_tree_with_path(
                         ComparedLine {
                             kind: ComparedKind::Modified,
                             line: item_to.clone(),
+                            old: Some(item_from.clone()),
                         }
                         .prepent_parent(root),
                     );
@@ -94,6 +127,7 @@ fn compare_tree_with_path(
                     ComparedLine {
                         kind: ComparedKind::Deleted,
                         line: removed.clone(),
+                        old: Some(removed.clone()),
                     }
                     .prepent_parent(root),
                 );
@@ -103,6 +137,7 @@ fn compare_tree_with_path(
                     ComparedLine {
                         kind: ComparedKind::Added,
                         line: added.clone(),
+                        old: None,
                     }
                     .prepent_parent(root),
                 );
@@ -114,23 +149,504 @@ fn compare_tree_with_path(
     Ok(res)
 }
 
-/// 比较两个 tree
+/// Changes from the `from` tree to the `to` tree, named by their full paths.
+/// A file whose kind changed, like its executable bit, is modified
 pub fn compare_trees(from: &WithRepo<Tree>, to: &WithRepo<Tree>) -> io::Result<Vec<ComparedLine>> {
     compare_tree_with_path(Path::new(""), from, to)
 }
 
+/// Whether the path is the pathspec, or under the directory of it. An empty
+/// pathspec, the top of the working directory, matches all paths
+pub fn path_matches(pathspec: &str, path: &str) -> bool {
+    let pathspec = pathspec.trim_end_matches('/');
+    pathspec.is_empty()
+        || path == pathspec
+        || path
+            .strip_prefix(pathspec)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// How renames and copies are detected by [detect_renames]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameOptions {
+    /// the minimum similarity of a renamed file in percent
+    pub threshold: u8,
+    /// also find files copied from modified or deleted files
+    pub copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        RenameOptions {
+            threshold: 50,
+            copies: false,
+        }
+    }
+}
+
+impl RenameOptions {
+    /// Parse a similarity like git's `-M`, `50%` is 50 percent, and digits
+    /// without `%` are a fraction, so `5` is also 50 percent
+    pub fn parse_threshold(value: &str) -> anyhow::Result<u8> {
+        let invalid = || anyhow::anyhow!("invalid rename threshold '{value}'");
+        let percent = match value.strip_suffix('%') {
+            Some(percent) => percent.parse::<u32>().map_err(|_| invalid())?,
+            None => {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                // the first two digits after `0.`
+                format!("{value:0<2}")[..2].parse().map_err(|_| invalid())?
+            }
+        };
+        if percent > 100 {
+            return Err(invalid());
+        }
+        Ok(percent as u8)
+    }
+
+    /// Read `diff.renames` (`false`, `true` or `copies`) and
+    /// `diff.renameThreshold`, `None` if renames are not detected
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let copies = match config.get("diff.renames").map(str::to_lowercase).as_deref() {
+            Some("false" | "no" | "off" | "0") => return Ok(None),
+            Some("copies" | "copy") => true,
+            _ => false,
+        };
+        let threshold = match config.get("diff.renameThreshold") {
+            Some(value) => Self::parse_threshold(value)?,
+            None => RenameOptions::default().threshold,
+        };
+        Ok(Some(RenameOptions { threshold, copies }))
+    }
+}
+
+impl Repository {
+    /// Files under the line, with their full paths. A file is itself.
+    pub fn files_of(&self, line: TreeLine) -> anyhow::Result<Vec<TreeLine>> {
+        if line.kind != TreeLineKind::Tree {
+            return Ok(vec![line]);
+        }
+        let tree = Object::load_from(self, &line.sha1)?.cast_tree();
+        let mut files = Vec::new();
+        for child in tree.objects {
+            files.extend(self.files_of(TreeLine {
+                name: format!("{}/{}", line.name, child.name),
+                ..child
+            })?);
+        }
+        Ok(files)
+    }
+}
+
+/// How much of the contents are the same in percent, by the bytes of common
+/// lines. Binary files are only similar to the same file
+fn similarity(a: &Blob, b: &Blob) -> u8 {
+    let (Blob::Text(a), Blob::Text(b)) = (a, b) else {
+        return if a.as_bytes() == b.as_bytes() { 100 } else { 0 };
+    };
+    let max = a.len().max(b.len());
+    if max == 0 {
+        return 100;
+    }
+    let (a, b) = (split_lines(a), split_lines(b));
+    let common: usize = myers(&a, &b)
+        .into_iter()
+        .map(|edit| match edit {
+            Edit::Equal(x, _) => a[x].len(),
+            _ => 0,
+        })
+        .sum();
+    (common * 100 / max) as u8
+}
+
+/// Pair `sources` with `targets` of similar contents, returns the indexes and
+/// the similarity. Exact matches are found first, and each target is paired at
+/// most once, so is each source unless `reuse_sources`. Targets are read from
+/// the working directory if `targets_in_working_dir`, as they are not saved
+fn find_renames(
+    repo: &Repository,
+    sources: &[TreeLine],
+    targets: &[TreeLine],
+    targets_in_working_dir: bool,
+    threshold: u8,
+    reuse_sources: bool,
+) -> anyhow::Result<Vec<(usize, usize, u8)>> {
+    let is_link = |line: &TreeLine| line.kind == TreeLineKind::Symlink;
+    let basename = |line: &TreeLine| line.name.rsplit('/').next().unwrap_or("").to_string();
+    let load = |line: &TreeLine| -> anyhow::Result<Blob> {
+        Ok(Object::load_from(repo, &line.sha1)?.cast_blob())
+    };
+    let load_target = |line: &TreeLine| -> anyhow::Result<Blob> {
+        if targets_in_working_dir {
+            return Ok(read_file(&repo.working_dir().join(&line.name))?.1);
+        }
+        load(line)
+    };
+    let sources_blob = sources
+        .iter()
+        .map(load)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let targets_blob = targets
+        .iter()
+        .map(load_target)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // (similarity, same basename, source, target)
+    let mut candidates = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        for (j, target) in targets.iter().enumerate() {
+            let (a, b) = (&sources_blob[i], &targets_blob[j]);
+            // empty files are similar to any empty file, and are not renames
+            if is_link(source) != is_link(target) || a.as_bytes().is_empty() {
+                continue;
+            }
+            let score = if source.sha1 == target.sha1 {
+                100
+            } else {
+                // the similarity can not reach the threshold
+                let (min, max) = (a.as_bytes().len(), b.as_bytes().len());
+                let (min, max) = (min.min(max), min.max(max));
+                if min * 100 < max * threshold as usize {
+                    continue;
+                }
+                similarity(a, b).min(99)
+            };
+            if score >= threshold {
+                candidates.push((score, basename(source) == basename(target), i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.cmp(a));
+
+    let mut used_sources = HashSet::new();
+    let mut used_targets = HashSet::new();
+    let mut pairs = Vec::new();
+    for (score, _, i, j) in candidates {
+        if used_targets.contains(&j) || (!reuse_sources && used_sources.contains(&i)) {
+            continue;
+        }
+        used_sources.insert(i);
+        used_targets.insert(j);
+        pairs.push((i, j, score));
+    }
+    Ok(pairs)
+}
+
+/// Find files renamed or copied in the changes from [compare_trees], where
+/// the `from` tree is saved, and so is the `to` tree unless it is the working
+/// tree, `in_working_dir`. Added and deleted directories are split into files
+pub fn detect_renames(
+    repo: &Repository,
+    changes: Vec<ComparedLine>,
+    options: &RenameOptions,
+    in_working_dir: bool,
+) -> anyhow::Result<Vec<ComparedLine>> {
+    let mut res = Vec::new();
+    let (mut deleted, mut added, mut modified) = (Vec::new(), Vec::new(), Vec::new());
+    for change in changes {
+        match change.kind {
+            ComparedKind::Added => added.extend(repo.files_of(change.line)?),
+            ComparedKind::Deleted => deleted.extend(repo.files_of(change.line)?),
+            ComparedKind::Modified if change.line.kind != TreeLineKind::Tree => {
+                modified.extend(change.old.clone());
+                res.push(change);
+            }
+            _ => res.push(change),
+        }
+    }
+
+    let mut renamed = HashSet::new();
+    let mut copied = HashSet::new();
+    for (i, j, similarity) in find_renames(
+        repo,
+        &deleted,
+        &added,
+        in_working_dir,
+        options.threshold,
+        false,
+    )? {
+        renamed.insert(j);
+        res.push(ComparedLine {
+            kind: ComparedKind::Renamed { similarity },
+            line: added[j].clone(),
+            old: Some(deleted[i].clone()),
+        });
+    }
+    if options.copies {
+        let sources: Vec<_> = modified
+            .into_iter()
+            .chain(deleted.iter().cloned())
+            .collect();
+        let targets: Vec<_> = (0..added.len()).filter(|j| !renamed.contains(j)).collect();
+        let lines: Vec<_> = targets.iter().map(|&j| added[j].clone()).collect();
+        for (i, k, similarity) in find_renames(
+            repo,
+            &sources,
+            &lines,
+            in_working_dir,
+            options.threshold,
+            true,
+        )? {
+            copied.insert(targets[k]);
+            res.push(ComparedLine {
+                kind: ComparedKind::Copied { similarity },
+                line: lines[k].clone(),
+                old: Some(sources[i].clone()),
+            });
+        }
+    }
+
+    let renamed_from: HashSet<_> = res
+        .iter()
+        .filter(|change| matches!(change.kind, ComparedKind::Renamed { .. }))
+        .filter_map(|change| change.old.as_ref().map(|old| old.name.clone()))
+        .collect();
+    for line in deleted {
+        if !renamed_from.contains(&line.name) {
+            res.push(ComparedLine {
+                kind: ComparedKind::Deleted,
+                old: Some(line.clone()),
+                line,
+            });
+        }
+    }
+    for (j, line) in added.into_iter().enumerate() {
+        if !renamed.contains(&j) && !copied.contains(&j) {
+            res.push(ComparedLine {
+                kind: ComparedKind::Added,
+                line,
+                old: None,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// All files in the tree, by their full paths
+pub fn flatten_tree(tree: &WithRepo<Tree>) -> anyhow::Result<BTreeMap<String, TreeLine>> {
+    let mut files = BTreeMap::new();
+    for line in &tree.objects {
+        for file in tree.repo.files_of(line.clone())? {
+            files.insert(file.name.clone(), file);
+        }
+    }
+    Ok(files)
+}
+
+/// Build the tree of files named by their full paths, subtrees are saved
+pub fn build_tree(repo: &Repository, files: &BTreeMap<String, TreeLine>) -> io::Result<Tree> {
+    let mut lines = HashMap::new();
+    let mut dirs: BTreeMap<&str, BTreeMap<String, TreeLine>> = BTreeMap::new();
+    for (path, line) in files {
+        match path.split_once('/') {
+            Some((dir, rest)) => {
+                dirs.entry(dir)
+                    .or_default()
+                    .insert(rest.to_string(), line.clone());
+            }
+            None => {
+                lines.insert(
+                    path.clone(),
+                    TreeLine {
+                        name: path.clone(),
+                        ..line.clone()
+                    },
+                );
+            }
+        }
+    }
+    for (dir, files) in dirs {
+        let subtree = repo.wrap(Object::Tree(build_tree(repo, &files)?));
+        subtree.save()?;
+        lines.insert(
+            dir.to_string(),
+            TreeLine {
+                kind: TreeLineKind::Tree,
+                name: dir.to_string(),
+                sha1: subtree.sha1().into(),
+            },
+        );
+    }
+    Ok(Tree::from(lines))
+}
+
+/// Move files renamed on one side to their new paths in the base and the
+/// other side, so that [auto_merge_trees] merges edits of the other side into
+/// the renamed files. Returns the base, ours and theirs
+pub fn align_renames(
+    base: &WithRepo<Tree>,
+    ours: &WithRepo<Tree>,
+    theirs: &WithRepo<Tree>,
+    options: &RenameOptions,
+) -> anyhow::Result<(Tree, Tree, Tree)> {
+    let repo = base.repo;
+    let mut base_files = flatten_tree(base)?;
+    let mut sides = [flatten_tree(ours)?, flatten_tree(theirs)?];
+
+    // renames from the base to each side
+    let mut renames = [HashMap::new(), HashMap::new()];
+    for (side, files) in sides.iter().enumerate() {
+        let sources: Vec<_> = base_files
+            .iter()
+            .filter(|(path, _)| !files.contains_key(*path))
+            .map(|(_, line)| line.clone())
+            .collect();
+        let targets: Vec<_> = files
+            .iter()
+            .filter(|(path, _)| !base_files.contains_key(*path))
+            .map(|(_, line)| line.clone())
+            .collect();
+        for (i, j, _) in find_renames(repo, &sources, &targets, false, options.threshold, false)? {
+            renames[side].insert(sources[i].name.clone(), targets[j].name.clone());
+        }
+    }
+
+    let mut moved = false;
+    for side in 0..2 {
+        let other = 1 - side;
+        for (from, to) in &renames[side] {
+            // renamed to another path on the other side, or the new path is
+            // taken there, they are left as they are
+            if renames[other]
+                .get(from)
+                .is_some_and(|other_to| other_to != to)
+                || (sides[other].contains_key(to) && renames[other].get(from) != Some(to))
+            {
+                continue;
+            }
+            if let Some(line) = sides[other].remove(from) {
+                sides[other].insert(to.clone(), line);
+            }
+            if let Some(line) = base_files.remove(from) {
+                base_files.insert(to.clone(), line);
+            }
+            moved = true;
+        }
+    }
+
+    if !moved {
+        return Ok((Tree::clone(base), Tree::clone(ours), Tree::clone(theirs)));
+    }
+    let [ours_files, theirs_files] = &sides;
+    Ok((
+        build_tree(repo, &base_files)?,
+        build_tree(repo, ours_files)?,
+        build_tree(repo, theirs_files)?,
+    ))
+}
+
+/// Why a path conflicts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// both sides changed the same lines of a text file, from `line_start` to
+    /// `line_end` in ours
+    Content { line_start: usize, line_end: usize },
+    /// both sides changed a binary file or a symlink differently, ours is kept
+    Binary,
+    /// modified on one side and deleted on the other, the modified version is
+    /// kept
+    ModifyDelete,
+    /// a file on one side and a directory on the other, the directory is kept
+    /// at `path`, and the file is moved to [Conflict::file]
+    FileDirectory { path: String },
+    /// a regular file on one side and a symlink on the other, ours is kept
+    DistinctTypes,
+}
+
 #[derive(Debug, Clone)]
 pub struct Conflict {
+    /// the path where the conflicted file is left
     pub file: String,
-    pub line_start: usize,
-    pub line_end: usize,
+    pub kind: ConflictKind,
+    /// the file in the base, ours and theirs, named by the full path
+    pub versions: Unmerged,
 }
 
-/// 自动合并 tree，如果冲突，则返回注入了冲突标记的 tree 和冲突信息
+impl Conflict {
+    fn prepent_parent(mut self, path: &str) -> Self {
+        self.file = format!("{path}/{}", self.file);
+        for line in self.versions.iter_mut().flatten() {
+            line.name = format!("{path}/{}", line.name);
+        }
+        if let ConflictKind::FileDirectory { path: dir } = &mut self.kind {
+            *dir = format!("{path}/{dir}");
+        }
+        self
+    }
+
+    /// Describe the conflict like git, `ours` and `theirs` name the sides
+    pub fn message(&self, ours: &str, theirs: &str) -> String {
+        let file = &self.file;
+        match &self.kind {
+            ConflictKind::Content { .. } => format!("CONFLICT (content): Merge conflict in {file}"),
+            ConflictKind::Binary => format!(
+                "warning: Cannot merge binary files: {file} ({ours} vs. {theirs})\n\
+                 CONFLICT (content): Merge conflict in {file}"
+            ),
+            ConflictKind::ModifyDelete => {
+                let (deleted, modified) = match self.versions[1] {
+                    None => (ours, theirs),
+                    Some(_) => (theirs, ours),
+                };
+                format!(
+                    "CONFLICT (modify/delete): {file} deleted in {deleted} and modified in \
+                     {modified}. Version {modified} of {file} left in tree."
+                )
+            }
+            ConflictKind::FileDirectory { path } => {
+                let side = match self.versions[1] {
+                    Some(_) => ours,
+                    None => theirs,
+                };
+                format!(
+                    "CONFLICT (file/directory): directory in the way of {path} from {side}; \
+                     moving it to {file} instead."
+                )
+            }
+            ConflictKind::DistinctTypes => format!(
+                "CONFLICT (distinct types): {file} had different types on each side; \
+                 kept the version of {ours}."
+            ),
+        }
+    }
+}
+
+/// Whether the lines have the same content and the same kind
+fn same(a: &TreeLine, b: &TreeLine) -> bool {
+    a.sha1 == b.sha1 && a.kind == b.kind
+}
+
+/// Options of merging trees
+#[derive(Debug, Clone, Default)]
+pub struct MergeTreeOptions {
+    pub file: MergeFileOptions,
+    /// the `merge` attribute selects the merge driver of a path, `union`, or
+    /// `binary` which is also used if it is unset
+    pub attributes: Attributes,
+}
+
+/// Merge the trees automatically, returns the merged tree, where conflicted
+/// files have conflict markers, and the conflicts
+///
+/// Merged blobs and subtrees are saved, so that the merged tree can be dumped
+/// to the working directory.
 pub fn auto_merge_trees(
     base: &WithRepo<Tree>,
     ours: &WithRepo<Tree>,
     theirs: &WithRepo<Tree>,
+    options: &MergeTreeOptions,
+) -> anyhow::Result<(Tree, Vec<Conflict>)> {
+    auto_merge_trees_in("", base, ours, theirs, options)
+}
+
+/// [auto_merge_trees] in the directory `dir`, which is empty or ends with `/`
+fn auto_merge_trees_in(
+    dir: &str,
+    base: &WithRepo<Tree>,
+    ours: &WithRepo<Tree>,
+    theirs: &WithRepo<Tree>,
+    options: &MergeTreeOptions,
 ) -> anyhow::Result<(Tree, Vec<Conflict>)> {
     let mut merged_map = HashMap::new();
     let mut conflicts = Vec::new();
@@ -160,200 +676,309 @@ pub fn auto_merge_trees(
                 // only ours added
                 merged_map.insert(item.clone(), i.clone());
             }
-            (Some(b), Some(i), None) | (Some(b), None, Some(i)) if b.sha1 == i.sha1 => {
+            (Some(b), Some(i), None) | (Some(b), None, Some(i)) if same(b, i) => {
                 // only ours deleted, or only theirs deleted
                 // do nothing
             }
 
-            (_, Some(o), Some(t)) if o.sha1 == t.sha1 => {
+            (_, Some(o), Some(t)) if same(o, t) => {
                 // o==t
                 merged_map.insert(item.clone(), o.clone());
             }
-            (Some(b), Some(f), Some(s)) | (Some(b), Some(s), Some(f)) if b.sha1 == f.sha1 => {
+            (Some(b), Some(f), Some(s)) | (Some(b), Some(s), Some(f)) if same(b, f) => {
                 // f unchanged, s modified
                 merged_map.insert(item.clone(), s.clone());
             }
 
-            (_, None, Some(_)) | (_, Some(_), None) => {
-                panic!("Not required.")
-            }
+            (b, o, t) => {
+                // changed differently on both sides, or modified on one side
+                // and deleted on the other. A directory and a file at the
+                // path are merged separately
+                let tree_of = |line: Option<&TreeLine>| -> anyhow::Result<Option<Tree>> {
+                    Ok(match line {
+                        Some(line) if line.kind == TreeLineKind::Tree => {
+                            Some(Object::load_from(ours.repo, &line.sha1)?.cast_tree())
+                        }
+                        _ => None,
+                    })
+                };
 
-            (Some(b), Some(o), Some(t)) if o.sha1 != t.sha1 => {
-                // Conflict: o!=t
-                if o.kind != t.kind {
-                    anyhow::bail!(
-                        "Conflict: different kinds of objects, o: {}, t: {}",
-                        o.kind,
-                        t.kind
-                    );
-                }
-                if o.kind == TreeLineKind::Tree {
-                    // 处理子目录冲突
-                    let b_tree = base
-                        .wrap(Object::accessor(&b.sha1))
-                        .load()?
-                        .unwrap()
-                        .cast_tree();
-                    let o_tree = ours
-                        .wrap(Object::accessor(&o.sha1))
-                        .load()?
-                        .unwrap()
-                        .cast_tree();
-                    let t_tree = theirs
-                        .wrap(Object::accessor(&t.sha1))
-                        .load()?
-                        .unwrap()
-                        .cast_tree();
-                    let (merged_subtree, sub_conflicts) = auto_merge_trees(
-                        &base.wrap(Object::Tree(b_tree).cast_tree()),
-                        &ours.wrap(Object::Tree(o_tree).cast_tree()),
-                        &theirs.wrap(Object::Tree(t_tree).cast_tree()),
+                let mut merged_dir = None;
+                let (ours_dir, theirs_dir) = (tree_of(o)?, tree_of(t)?);
+                if ours_dir.is_some() || theirs_dir.is_some() {
+                    // merge the subdirectories
+                    let (merged_subtree, sub_conflicts) = auto_merge_trees_in(
+                        &format!("{dir}{item}/"),
+                        &base.wrap(tree_of(b)?.unwrap_or_else(Tree::empty)),
+                        &ours.wrap(ours_dir.unwrap_or_else(Tree::empty)),
+                        &theirs.wrap(theirs_dir.unwrap_or_else(Tree::empty)),
+                        options,
                     )?;
-                    merged_map.insert(
-                        item.clone(),
-                        TreeLine {
+                    conflicts.extend(sub_conflicts.into_iter().map(|c| c.prepent_parent(item)));
+                    // empty directories are not recorded
+                    if !merged_subtree.objects.is_empty() {
+                        let merged_subtree = ours.wrap(Object::Tree(merged_subtree));
+                        merged_subtree.save()?;
+                        merged_dir = Some(TreeLine {
                             name: item.clone(),
                             kind: TreeLineKind::Tree,
                             sha1: merged_subtree.sha1().into(),
-                        },
-                    );
-                    conflicts.extend(sub_conflicts);
-                    continue;
-                }
-                handle_conflict(&mut conflicts, o, t, ours, theirs)?;
-            }
-            (None, Some(o), Some(t)) if o.sha1 != t.sha1 => {
-                // Conflict: o!=t
-                if o.kind != t.kind {
-                    anyhow::bail!(
-                        "Conflict: different kinds of objects, o: {}, t: {}",
-                        o.kind,
-                        t.kind
-                    );
+                        });
+                    }
                 }
-                if o.kind == TreeLineKind::Tree {
-                    // 处理子目录冲突
-                    let o_tree = ours
-                        .wrap(Object::accessor(&o.sha1))
-                        .load()?
-                        .unwrap()
-                        .cast_tree();
-                    let t_tree = theirs
-                        .wrap(Object::accessor(&t.sha1))
-                        .load()?
-                        .unwrap()
-                        .cast_tree();
-                    let (merged_subtree, sub_conflicts) = auto_merge_trees(
-                        &base.wrap(Tree {
-                            objects: Vec::new(),
-                        }),
-                        &ours.wrap(o_tree),
-                        &theirs.wrap(t_tree),
-                    )?;
-                    merged_map.insert(
-                        item.clone(),
-                        TreeLine {
-                            name: item.clone(),
-                            kind: TreeLineKind::Tree,
-                            sha1: merged_subtree.sha1().into(),
-                        },
-                    );
-                    conflicts.extend(sub_conflicts);
-                    continue;
+
+                let not_tree = |line: &&TreeLine| line.kind != TreeLineKind::Tree;
+                let (b, o, t) = (b.filter(not_tree), o.filter(not_tree), t.filter(not_tree));
+                let path = format!("{dir}{item}");
+                let (merged_file, file_conflicts) = merge_blobs(&path, b, o, t, ours, options)?;
+
+                match (merged_dir, merged_file) {
+                    (Some(dir), Some(file)) => {
+                        // file/directory conflict, move the file away
+                        let side = if o.is_some() {
+                            &options.file.labels[0]
+                        } else {
+                            &options.file.labels[2]
+                        };
+                        let moved = format!("{item}~{}", side.replace('/', "_"));
+                        let rename = |line: Option<&TreeLine>| {
+                            line.map(|line| TreeLine {
+                                name: moved.clone(),
+                                ..line.clone()
+                            })
+                        };
+                        conflicts.push(Conflict {
+                            file: moved.clone(),
+                            kind: ConflictKind::FileDirectory { path: item.clone() },
+                            versions: [None, rename(o), rename(t)],
+                        });
+                        // the file may also conflict with its base, like
+                        // modify/delete, where it is moved to
+                        conflicts.extend(file_conflicts.into_iter().map(|conflict| Conflict {
+                            file: moved.clone(),
+                            versions: conflict.versions.map(|line| rename(line.as_ref())),
+                            ..conflict
+                        }));
+                        merged_map.insert(item.clone(), dir);
+                        merged_map.insert(moved.clone(), rename(Some(&file)).unwrap());
+                    }
+                    (dir, file) => {
+                        conflicts.extend(file_conflicts);
+                        if let Some(line) = dir.or(file) {
+                            merged_map.insert(item.clone(), line);
+                        }
+                    }
                 }
-                handle_conflict(&mut conflicts, o, t, ours, theirs)?;
             }
-            _ => {}
         }
     }
 
     let merged_tree = Tree::from(merged_map);
+    conflicts.sort_by(|a, b| a.file.cmp(&b.file));
     Ok((merged_tree, conflicts))
 }
 
-fn handle_conflict(
-    conflicts: &mut Vec<Conflict>,
-    o: &TreeLine,
-    t: &TreeLine,
+/// Three-way merge of a file at `path` which is not a directory, returns the
+/// merged line, and the conflicts
+fn merge_blobs(
+    path: &str,
+    b: Option<&TreeLine>,
+    o: Option<&TreeLine>,
+    t: Option<&TreeLine>,
     ours: &WithRepo<Tree>,
-    theirs: &WithRepo<Tree>,
-) -> anyhow::Result<()> {
-    // 处理冲突，返回冲突信息
-    match o.kind {
-        TreeLineKind::File => {
-            let a = ours
-                .wrap(Object::accessor(&o.sha1))
-                .load()?
-                .map(|a| a.cast_blob());
-            let a_str = a.as_string();
-            let a_lines = a_str.lines();
-            let b = theirs
-                .wrap(Object::accessor(&t.sha1))
-                .load()?
-                .map(|b| b.cast_blob());
-            let b_str = b.as_string();
-            let b_lines = b_str.lines();
-            let mut conflicting = false;
-            let mut line_start = 0;
-            let mut line_end = 0;
-
-            for (index, (a_line, b_line)) in a_lines.zip(b_lines).enumerate() {
-                if a_line != b_line {
-                    // has conflict
-                    if !conflicting {
-                        // starting conflict block
-                        conflicting = true;
-                        line_start = index + 1;
-                    }
-                    line_end = index + 1;
-                } else {
-                    // not conflicting
-                    if conflicting {
-                        conflicting = false;
-                        // add conflict block to conflicts
-                        conflicts.push(Conflict {
-                            file: o.name.clone(),
-                            line_end,
-                            line_start,
-                        });
-                    }
-                    line_start = 0;
-                    line_end = 0;
-                }
-            }
+    options: &MergeTreeOptions,
+) -> anyhow::Result<(Option<TreeLine>, Vec<Conflict>)> {
+    let conflict = |file: &TreeLine, kind| Conflict {
+        file: file.name.clone(),
+        kind,
+        versions: [b.cloned(), o.cloned(), t.cloned()],
+    };
+    let mut file_options = options.file.clone();
+    let mut binary = false;
+    match options.attributes.get(path, "merge") {
+        Some(AttrValue::Value(driver)) if driver == "union" => file_options.favor = Favor::Union,
+        Some(AttrValue::Value(driver)) if driver == "binary" => binary = true,
+        Some(AttrValue::Unset) => binary = true,
+        _ => {}
+    }
+    // a file which can not be merged by lines, ours is kept unless a side is
+    // favored
+    let unmergeable = |o: &TreeLine, t: &TreeLine| match file_options.favor {
+        Favor::Ours => (Some(o.clone()), vec![]),
+        Favor::Theirs => (Some(t.clone()), vec![]),
+        _ => (Some(o.clone()), vec![conflict(o, ConflictKind::Binary)]),
+    };
 
-            if conflicting {
-                conflicts.push(Conflict {
-                    file: o.name.clone(),
-                    line_start,
-                    line_end,
-                });
-            }
-            Ok(())
+    match (b, o, t) {
+        (_, None, None) => Ok((None, vec![])),
+        (None, Some(i), None) | (None, None, Some(i)) => Ok((Some(i.clone()), vec![])),
+        (Some(b), Some(i), None) | (Some(b), None, Some(i)) if same(b, i) => Ok((None, vec![])),
+        (_, Some(o), Some(t)) if same(o, t) => Ok((Some(o.clone()), vec![])),
+        (Some(b), Some(f), Some(s)) | (Some(b), Some(s), Some(f)) if same(b, f) => {
+            Ok((Some(s.clone()), vec![]))
         }
-        TreeLineKind::Executable => {
-            panic!("Not required.")
-        }
-        TreeLineKind::Symlink => {
-            panic!("Not required.")
-        }
-        TreeLineKind::Tree => {
-            panic!("Shouldn't go here.")
+
+        // modified on one side, and deleted on the other
+        (_, Some(i), None) | (_, None, Some(i)) => Ok((
+            Some(i.clone()),
+            vec![conflict(i, ConflictKind::ModifyDelete)],
+        )),
+
+        (b, Some(o), Some(t)) => {
+            let is_link = |line: &TreeLine| line.kind == TreeLineKind::Symlink;
+            if is_link(o) != is_link(t) {
+                return Ok((
+                    Some(o.clone()),
+                    vec![conflict(o, ConflictKind::DistinctTypes)],
+                ));
+            }
+            if is_link(o) || binary {
+                // symlinks can not be merged
+                return Ok(unmergeable(o, t));
+            }
+
+            let load = |line: &TreeLine| -> anyhow::Result<Blob> {
+                Ok(Object::load_from(ours.repo, &line.sha1)?.cast_blob())
+            };
+            let base = match b {
+                Some(b) if !is_link(b) => load(b)?,
+                // both sides added the file
+                _ => Blob::from(""),
+            };
+            let (Blob::Text(base), Blob::Text(a), Blob::Text(b_text)) = (base, load(o)?, load(t)?)
+            else {
+                // binary files can not be merged
+                return Ok(unmergeable(o, t));
+            };
+
+            let merged = merge_file(&base, &a, &b_text, &file_options);
+            let blob = ours.wrap(Object::Blob(Blob::Text(merged.content)));
+            blob.save()?;
+            // the executable bit changed on one side is kept
+            let kind = match b {
+                Some(b) if b.kind == o.kind => t.kind.clone(),
+                _ => o.kind.clone(),
+            };
+            let line = TreeLine {
+                kind,
+                name: o.name.clone(),
+                sha1: blob.sha1().into(),
+            };
+            let conflicts = merged
+                .conflicts
+                .into_iter()
+                .map(|(line_start, line_end)| {
+                    conflict(
+                        o,
+                        ConflictKind::Content {
+                            line_start,
+                            line_end,
+                        },
+                    )
+                })
+                .collect();
+            Ok((Some(line), conflicts))
         }
     }
 }
-// /// 注入 Git 样式冲突标记，返回合并内容和冲突行区间
-// fn inject_conflict_markers(ours: &Blob, theirs: &Blob) -> (String, usize, usize) {
-//     let ours_lines: Vec<&str> = ours.content.lines().collect();
-//     let theirs_lines: Vec<&str> = theirs.content.lines().collect();
-//
-//     let start = 1;
-//     let end = std::cmp::max(ours_lines.len(), theirs_lines.len());
-//
-//     let merged = format!(
-//         "<<<<<<< ours\n{}\n=======\n{}\n>>>>>>> theirs\n",
-//         ours.content.trim_end(),
-//         theirs.content.trim_end()
-//     );
-//     (merged, start, end)
-// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{object::ObjectSha1, repo::Repository},
+        services::testing::temp_repo,
+    };
+
+    /// Merge the trees of the commits with the default options
+    fn merge_commits(
+        repo: &Repository,
+        [base, ours, theirs]: [&ObjectSha1; 3],
+    ) -> (Tree, Vec<Conflict>) {
+        let tree = |commit| repo.wrap(repo.tree_of(commit).unwrap());
+        let options = MergeTreeOptions::default();
+        auto_merge_trees(&tree(base), &tree(ours), &tree(theirs), &options).unwrap()
+    }
+
+    #[test]
+    fn merge_file_and_directory() {
+        let (_dir, repo) = temp_repo();
+        let base = repo.commit_files(&[("x", Some("x\n"))], "base");
+        let ours = repo.commit_files(&[("a", Some("a\n"))], "ours");
+        let theirs = repo.commit_files(&[("a", None), ("a/b", Some("b\n"))], "theirs");
+
+        let (tree, conflicts) = merge_commits(&repo, [&base, &ours, &theirs]);
+        let files = flatten_tree(&repo.wrap(tree)).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["a/b", "a~ours", "x"]);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].file, "a~ours");
+        assert_eq!(
+            conflicts[0].kind,
+            ConflictKind::FileDirectory { path: "a".into() }
+        );
+        assert_eq!(conflicts[0].versions[1].as_ref().unwrap().name, "a~ours");
+        assert!(conflicts[0].versions[2].is_none());
+    }
+
+    #[test]
+    fn merge_file_modified_and_replaced_by_directory() {
+        let (_dir, repo) = temp_repo();
+        let base = repo.commit_files(&[("d/a", Some("a\n"))], "base");
+        let ours = repo.commit_files(&[("d/a", Some("changed\n"))], "ours");
+        let theirs = repo.commit_files(&[("d/a", None), ("d/a/b", Some("b\n"))], "theirs");
+
+        let (tree, conflicts) = merge_commits(&repo, [&base, &ours, &theirs]);
+        let files = flatten_tree(&repo.wrap(tree)).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["d/a/b", "d/a~ours"]);
+        let kinds: Vec<_> = conflicts.iter().map(|c| (&c.file, &c.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (
+                    &"d/a~ours".to_string(),
+                    &ConflictKind::FileDirectory { path: "d/a".into() }
+                ),
+                (&"d/a~ours".to_string(), &ConflictKind::ModifyDelete),
+            ]
+        );
+        // the versions of the modify/delete conflict are at the moved path
+        let names: Vec<_> = conflicts[1]
+            .versions
+            .iter()
+            .flatten()
+            .map(|l| &l.name)
+            .collect();
+        assert_eq!(names, ["d/a~ours", "d/a~ours"]);
+    }
+
+    #[test]
+    fn test_parse_rename_threshold() {
+        assert_eq!(RenameOptions::parse_threshold("50%").unwrap(), 50);
+        assert_eq!(RenameOptions::parse_threshold("100%").unwrap(), 100);
+        // like git, digits are a fraction
+        assert_eq!(RenameOptions::parse_threshold("9").unwrap(), 90);
+        assert_eq!(RenameOptions::parse_threshold("05").unwrap(), 5);
+        assert_eq!(RenameOptions::parse_threshold("755").unwrap(), 75);
+        assert!(RenameOptions::parse_threshold("120%").is_err());
+        assert!(RenameOptions::parse_threshold("half").is_err());
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("", "a/b"));
+        assert!(path_matches("a", "a/b"));
+        assert!(path_matches("a/", "a/b"));
+        assert!(path_matches("a/b", "a/b"));
+        assert!(!path_matches("a", "ab"));
+        assert!(!path_matches("a/b", "a"));
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = Blob::from("1\n2\n3\n4\n5\n6\n7\n8\n9\n0\n");
+        let b = Blob::from("1\n2\n3\n4\nfive\n6\n7\n8\n9\n0\n");
+        assert_eq!(similarity(&a, &a), 100);
+        assert_eq!(similarity(&a, &b), 78);
+        assert_eq!(similarity(&a, &Blob::from("x\n")), 0);
+    }
+}